@@ -0,0 +1,275 @@
+use anyhow::{ensure, Result};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Rule {
+	#[default]
+	ZieglerNichols,
+	TyreusLuyben,
+}
+
+impl Rule {
+	pub(crate) const ALL: &'static [Rule] = &[Rule::ZieglerNichols, Rule::TyreusLuyben];
+
+	/// The factors for `k_p = a * ku`, `t_i = b * tu` and `t_d = c * tu`.
+	fn factors(self) -> (f64, f64, f64) {
+		match self {
+			Rule::ZieglerNichols => (0.6, 0.5, 0.125),
+			Rule::TyreusLuyben => (1.0 / 2.2, 2.2, 1.0 / 6.3),
+		}
+	}
+}
+
+/// The settings for the relay experiment on the line, see [Relay].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Autotune {
+	/// The speed we drive at, in percent.
+	pub(crate) speed: f64,
+	/// The relay output, as relative wheel differential.
+	pub(crate) relay: f64,
+	/// The relay only switches once the error is larger than this.
+	pub(crate) hysteresis: f64,
+	/// The amount of oscillations we ignore while the robot settles in.
+	pub(crate) settle_cycles: usize,
+	/// The amount of oscillations we measure, at least one.
+	#[serde(deserialize_with = "at_least_one")]
+	pub(crate) cycles: usize,
+	/// We give up after this many seconds.
+	pub(crate) timeout: f64,
+}
+
+impl Default for Autotune {
+	fn default() -> Self {
+		Self {
+			speed: 30.0,
+			relay: 0.2,
+			hysteresis: 3.0,
+			settle_cycles: 2,
+			cycles: 4,
+			timeout: 30.0,
+		}
+	}
+}
+
+/// Refuses `0` for [Autotune::cycles], as there is nothing to measure then.
+fn at_least_one<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+	let cycles = usize::deserialize(deserializer)?;
+	if cycles < 1 {
+		return Err(de::Error::custom("autotune needs at least 1 cycle to measure"));
+	}
+	Ok(cycles)
+}
+
+/// The outcome of the relay experiment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Ultimate {
+	/// The ultimate gain, in the units of the line PID (that's the correction times `1000`).
+	pub(crate) gain: f64,
+	/// The period of the oscillation in `s`.
+	pub(crate) period: f64,
+	/// Half of the peak to peak amplitude of the reflection.
+	pub(crate) amplitude: f64,
+}
+
+/// The gains for the line PID, with the same signs and units as in the settings file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Gains {
+	pub(crate) k_p: f64,
+	pub(crate) k_i: f64,
+	pub(crate) k_d: f64,
+}
+
+impl Ultimate {
+	/// Applies the tuning rule. `direction` is the sign of `k_p`, `dt` the tick time in `s`.
+	///
+	/// Our PID sums up the error every tick for the integral, and uses `last_error - error` for the
+	/// derivative, so we have to scale the classic `t_i` and `t_d` by the tick time.
+	pub(crate) fn gains(&self, rule: Rule, direction: f64, dt: f64) -> Gains {
+		let (a, b, c) = rule.factors();
+		let k_p = a * self.gain;
+		let t_i = b * self.period;
+		let t_d = c * self.period;
+
+		Gains {
+			k_p: direction * k_p,
+			k_i: direction * k_p * dt / t_i,
+			k_d: -direction * k_p * t_d / dt,
+		}
+	}
+}
+
+/// A relay (Åström–Hägglund) experiment.
+///
+/// Instead of the PID, the relay steers with a fixed differential towards the line. That makes
+/// the robot oscillate around the line, and from the amplitude and period of that oscillation we
+/// get the ultimate gain and period.
+#[derive(Debug, Clone)]
+pub(crate) struct Relay {
+	relay: f64,
+	hysteresis: f64,
+	direction: f64,
+	settle_cycles: usize,
+	cycles: usize,
+
+	high: bool,
+	ticks: usize,
+	// The tick of every switch from low to high.
+	switches: Vec<usize>,
+	peaks: Vec<(f64, f64)>,
+	min: f64,
+	max: f64,
+}
+
+impl Relay {
+	pub(crate) fn new(settings: &Autotune, direction: f64) -> Relay {
+		Relay {
+			relay: settings.relay,
+			hysteresis: settings.hysteresis,
+			direction,
+			settle_cycles: settings.settle_cycles,
+			cycles: settings.cycles,
+
+			high: false,
+			ticks: 0,
+			switches: Vec::new(),
+			peaks: Vec::new(),
+			min: f64::INFINITY,
+			max: f64::NEG_INFINITY,
+		}
+	}
+
+	/// The amount of full oscillations we saw so far.
+	pub(crate) fn cycles(&self) -> usize {
+		self.switches.len().saturating_sub(1)
+	}
+
+	pub(crate) fn is_done(&self) -> bool {
+		self.cycles() >= self.settle_cycles + self.cycles
+	}
+
+	/// Feeds the line error, returns the relative wheel differential to drive with.
+	pub(crate) fn update(&mut self, error: f64) -> f64 {
+		self.ticks += 1;
+		self.min = self.min.min(error);
+		self.max = self.max.max(error);
+
+		if !self.high && error > self.hysteresis {
+			self.high = true;
+			self.switches.push(self.ticks);
+			self.peaks.push((self.min, self.max));
+			self.min = f64::INFINITY;
+			self.max = f64::NEG_INFINITY;
+		} else if self.high && error < -self.hysteresis {
+			self.high = false;
+		}
+
+		// The PID would steer with `k_p * error`, so the relay steers with the sign of `k_p` as well.
+		let output = if self.high { self.relay } else { -self.relay };
+		self.direction * output
+	}
+
+	/// The ultimate gain and period, once we measured enough.
+	pub(crate) fn result(&self, dt: f64) -> Result<Ultimate> {
+		let first = self.settle_cycles;
+		// We need one full oscillation after settling, whatever `cycles` says.
+		ensure!(self.is_done() && self.switches.len() >= first + 2,
+			"Only saw {} oscillations, instead of {} and {} to settle",
+			self.cycles(), self.cycles.max(1), self.settle_cycles,
+		);
+		let last = self.switches.len() - 1;
+		let period = (self.switches[last] - self.switches[first]) as f64 * dt / (last - first) as f64;
+
+		// `peaks[i]` holds the extremes of the cycle that ended with `switches[i]`.
+		let measured = &self.peaks[first + 1..=last];
+		let amplitude = measured.iter()
+			.map(|(min, max)| (max - min) / 2.0)
+			.sum::<f64>() / measured.len() as f64;
+
+		// With the hysteresis, the describing function of the relay gives this.
+		let amplitude_squared = amplitude.powi(2) - self.hysteresis.powi(2);
+		ensure!(amplitude_squared > 0.0,
+			"The amplitude {amplitude:.1} isn't larger than the hysteresis {}", self.hysteresis,
+		);
+		let gain = 4.0 * self.relay * 1000.0 / (std::f64::consts::PI * amplitude_squared.sqrt());
+
+		Ok(Ultimate { gain, period, amplitude })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DT: f64 = 0.01;
+
+	fn settings(settle_cycles: usize, cycles: usize) -> Autotune {
+		Autotune { settle_cycles, cycles, ..Autotune::default() }
+	}
+
+	/// Feeds a sine with `amplitude` and a period of `period` ticks, until the relay is done.
+	fn run(relay: &mut Relay, amplitude: f64, period: usize) {
+		for tick in 0..100 * period {
+			if relay.is_done() {
+				return;
+			}
+			let phase = tick as f64 / period as f64 * std::f64::consts::TAU;
+			relay.update(amplitude * phase.sin());
+		}
+	}
+
+	#[test]
+	fn measures_the_oscillation() {
+		let mut relay = Relay::new(&settings(1, 3), 1.0);
+		run(&mut relay, 20.0, 100);
+		let ultimate = relay.result(DT).unwrap();
+		assert!((ultimate.period - 1.0).abs() < 0.02, "{ultimate:?}");
+		assert!((ultimate.amplitude - 20.0).abs() < 0.5, "{ultimate:?}");
+		assert!(ultimate.gain.is_finite() && ultimate.gain > 0.0);
+	}
+
+	#[test]
+	fn no_result_before_done() {
+		let mut relay = Relay::new(&settings(1, 3), 1.0);
+		assert!(relay.result(DT).is_err());
+		relay.update(10.0);
+		assert!(relay.result(DT).is_err());
+	}
+
+	#[test]
+	fn zero_cycles_give_no_result_and_dont_panic() {
+		let mut relay = Relay::new(&settings(0, 0), 1.0);
+		assert!(relay.is_done());
+		assert!(relay.result(DT).is_err());
+		run(&mut relay, 20.0, 100);
+		assert!(relay.result(DT).is_err());
+
+		let mut relay = Relay::new(&settings(2, 0), 1.0);
+		run(&mut relay, 20.0, 100);
+		assert!(relay.result(DT).is_err());
+	}
+
+	#[test]
+	fn an_amplitude_within_the_hysteresis_gives_no_result() {
+		let mut relay = Relay::new(&settings(0, 2), 1.0);
+		for error in [4.0, -4.0, 4.0, -4.0, 4.0, -4.0, 4.0] {
+			relay.update(error);
+		}
+		relay.hysteresis = 5.0;
+		assert!(relay.result(DT).is_err());
+	}
+
+	#[test]
+	fn zero_cycles_are_refused_in_the_settings() {
+		assert!(toml::from_str::<Autotune>("cycles = 0").is_err());
+		assert_eq!(toml::from_str::<Autotune>("cycles = 1").unwrap().cycles, 1);
+	}
+
+	#[test]
+	fn the_relay_follows_the_direction() {
+		let mut relay = Relay::new(&settings(1, 1), -1.0);
+		assert_eq!(relay.update(0.0), 0.2);
+		assert_eq!(relay.update(10.0), -0.2);
+	}
+}
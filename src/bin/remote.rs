@@ -0,0 +1,9 @@
+//! Gets and sets the settings of the robot while it runs, switches its state, and starts and
+//! stops drives, over the remote control of `[remote]`.
+//!
+//! Usage: `remote [--address ev3dev:7701] [get [PATH] | set PATH VALUE | state [NAME] | start | stop | save]`,
+//! or one command per line on standard input.
+
+fn main() -> anyhow::Result<()> {
+    roborace2023::remote()
+}
@@ -0,0 +1,8 @@
+//! Searches for good settings by driving in the simulation, and prints the best ones as a table
+//! like the one in `robot_settings.toml`.
+//!
+//! Usage: `tune [twiddle|random|nelder-mead] [ITERATIONS]`
+
+fn main() -> anyhow::Result<()> {
+    roborace2023::tune()
+}
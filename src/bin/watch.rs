@@ -0,0 +1,8 @@
+//! Watches the telemetry the robot streams with `[stream]`, and prints it as a table or writes
+//! it into a CSV file.
+//!
+//! Usage: `watch [ADDRESS] [--udp] [--columns tick,reflection,left,right] [--output PATH]`
+
+fn main() -> anyhow::Result<()> {
+    roborace2023::watch()
+}
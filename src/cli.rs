@@ -0,0 +1,172 @@
+use std::path::PathBuf;
+use clap::{Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use crate::logging::{self, Level};
+use crate::robot::button::Input;
+use crate::state::RobotState;
+
+/// Follows the line around the circle, and the robot in front of us.
+///
+/// Without a command, the robot goes into the menu.
+#[derive(Debug, Parser)]
+#[command(name = "roborace2023")]
+pub(crate) struct Cli {
+	/// The settings file of the robot, which has to exist. Without it, we take the first
+	/// `robot_settings.toml` in the working directory, next to this binary, or in `/etc/roborace`.
+	#[arg(long, short, global = true, value_name = "PATH", env = "ROBORACE_CONFIG")]
+	pub(crate) config: Option<PathBuf>,
+
+	/// The settings of this run, on top of the ones of the robot.
+	#[arg(long, global = true, value_name = "PATH", env = "ROBORACE_RUN_CONFIG")]
+	pub(crate) run_config: Option<PathBuf>,
+
+	/// Use the settings of `[profiles.<NAME>]` from any of the settings files on top of all others.
+	#[arg(long, short, global = true, value_name = "NAME")]
+	pub(crate) profile: Option<String>,
+
+	/// How much to log, `debug` logs every tick of a drive. Without it, `log.level` in the
+	/// settings decides.
+	#[arg(long, global = true, value_enum, value_name = "LEVEL")]
+	pub(crate) log_level: Option<Level>,
+
+	/// How much to log for one target, e.g. `control=debug`. The targets are `control`, `state`,
+	/// `hardware`, `menu`, `settings`, `run` and `network`.
+	#[arg(long = "log", global = true, value_name = "TARGET=LEVEL", value_parser = logging::parse_target)]
+	pub(crate) log_targets: Vec<(String, Level)>,
+
+	/// Read the settings and get all devices, but don't run anything.
+	#[arg(long, global = true)]
+	pub(crate) dry_run: bool,
+
+	/// Take the button presses from the brick or the keyboard, instead of `buttons` of
+	/// `[hardware]`.
+	#[arg(long, global = true, value_enum)]
+	pub(crate) buttons: Option<Input>,
+
+	/// Run on the robot, or on a simulated one which needs no hardware.
+	#[arg(long, short, global = true, value_enum, default_value_t = Backend::Ev3)]
+	pub(crate) backend: Backend,
+
+	#[command(subcommand)]
+	pub(crate) command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Backend {
+	/// The motors and sensors of the EV3.
+	Ev3,
+	/// A robot on a simulated track with `diameter`, which drives as fast as it can compute.
+	Sim,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum Simulation {
+	/// Follow a leader that changes its speed.
+	Follow,
+	/// One drive on a circle with `diameter`.
+	Track,
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub(crate) enum Command {
+	/// Do nothing and exit.
+	Exit,
+	/// Open the menu for selecting any robot state.
+	Menu,
+	/// Wait for a press of the touch sensor, then start the line driving.
+	Start,
+	/// Run the quick and dirty test method.
+	Test,
+	/// Check every motor, sensor and button, with your help.
+	Selftest,
+	/// Print the reflection and the distance, until right is pressed.
+	Measure,
+	/// Find line PID values with a relay experiment on the line.
+	Autotune,
+	/// Start the line driving.
+	Drive,
+	/// Drive by hand, with the arrow keys or the remote control.
+	Teleop,
+	/// Drive simple only, for testing PID values.
+	#[command(name = "driveS")]
+	DriveSimple,
+	/// Move the left motor a tiny bit.
+	L {
+		/// How far, in rotations.
+		#[arg(allow_negative_numbers = true)]
+		rotations: f64,
+	},
+	/// Move the right motor a tiny bit.
+	R {
+		/// How far, in rotations.
+		#[arg(allow_negative_numbers = true)]
+		rotations: f64,
+	},
+	/// Print the robot struct out, for debugging.
+	Print,
+	/// Simulate a drive, needs no robot.
+	Sim {
+		#[arg(value_enum, default_value_t = Simulation::Follow)]
+		simulation: Simulation,
+	},
+	/// Look at the directories of past drives.
+	Runs {
+		#[command(subcommand)]
+		command: RunsCommand,
+	},
+	/// Print the shell completions.
+	Completions {
+		shell: Shell,
+	},
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub(crate) enum RunsCommand {
+	/// List all runs, the oldest first.
+	List,
+	/// Print the summary, the state changes, the error and the settings of a run.
+	Show {
+		/// The name of the run, the newest one without it.
+		name: Option<String>,
+	},
+}
+
+impl Command {
+	/// The name on the command line.
+	pub(crate) fn name(&self) -> &'static str {
+		match self {
+			Command::Exit => "exit",
+			Command::Menu => "menu",
+			Command::Start => "start",
+			Command::Test => "test",
+			Command::Selftest => "selftest",
+			Command::Measure => "measure",
+			Command::Autotune => "autotune",
+			Command::Drive => "drive",
+			Command::DriveSimple => "driveS",
+			Command::Teleop => "teleop",
+			Command::L { .. } => "l",
+			Command::R { .. } => "r",
+			Command::Print => "print",
+			Command::Sim { .. } => "sim",
+			Command::Runs { .. } => "runs",
+			Command::Completions { .. } => "completions",
+		}
+	}
+
+	/// The state the robot starts in, for the commands that run the state machine.
+	pub(crate) fn state(&self) -> Option<RobotState> {
+		Some(match self {
+			Command::Exit => RobotState::Exit,
+			Command::Menu => RobotState::InMenu,
+			Command::Start => RobotState::Start,
+			Command::Test => RobotState::Test,
+			Command::Measure => RobotState::Measure,
+			Command::Autotune => RobotState::Autotune,
+			Command::Drive => RobotState::DriveEntry,
+			Command::DriveSimple => RobotState::DriveSimpleOnly,
+			Command::Teleop => RobotState::Teleop,
+			_ => return None,
+		})
+	}
+}
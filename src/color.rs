@@ -0,0 +1,235 @@
+use anyhow::{ensure, Result};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use crate::filter::Debounce;
+
+/// The mode of the color sensor, with the names from ev3dev.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub(crate) enum ColorMode {
+	/// The reflection in percent, the sensor does the calibration.
+	#[default]
+	#[serde(rename = "COL-REFLECT")]
+	ColReflect,
+	/// The raw reflection, which we scale with `raw_black` and `raw_white`.
+	#[serde(rename = "REF-RAW")]
+	RefRaw,
+	/// The raw red, green and blue values, which we scale with `white` and mix with `weights`.
+	/// Only in this mode we see the color of markers.
+	#[serde(rename = "RGB-RAW")]
+	RgbRaw,
+}
+
+impl ColorMode {
+	pub(crate) fn name(&self) -> &'static str {
+		match self {
+			ColorMode::ColReflect => "COL-REFLECT",
+			ColorMode::RefRaw => "REF-RAW",
+			ColorMode::RgbRaw => "RGB-RAW",
+		}
+	}
+}
+
+/// How we read the color sensor, and how we get a reflection in percent out of it.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct ColorSettings {
+	pub(crate) mode: ColorMode,
+
+	/// The raw values of black and white in `REF-RAW`.
+	pub(crate) raw_black: f64,
+	pub(crate) raw_white: f64,
+
+	/// The raw red, green and blue values of white in `RGB-RAW`, each up to `1020`.
+	pub(crate) white: [f64; 3],
+	/// How much red, green and blue count for the reflection in `RGB-RAW`.
+	pub(crate) weights: [f64; 3],
+}
+
+impl Default for ColorSettings {
+	fn default() -> Self {
+		Self {
+			mode: ColorMode::ColReflect,
+
+			raw_black: 0.0,
+			raw_white: 1020.0,
+
+			white: [1020.0; 3],
+			weights: [1.0; 3],
+		}
+	}
+}
+
+impl ColorSettings {
+	/// Fails for calibrations we can't scale with, as they'd divide by zero.
+	pub(crate) fn check(&self) -> Result<()> {
+		ensure!(self.raw_black != self.raw_white, "color.raw_black and color.raw_white are both {}", self.raw_black);
+		ensure!(self.white.iter().all(|&x| x > 0.0), "color.white must be above 0, not {:?}", self.white);
+		ensure!(self.weights.iter().all(|&x| x >= 0.0) && self.weights.iter().sum::<f64>() > 0.0,
+			"color.weights must not be negative, and not all 0, not {:?}", self.weights
+		);
+		Ok(())
+	}
+
+	/// The reflection in percent from a raw `REF-RAW` value.
+	pub(crate) fn scale_raw(&self, raw: f64) -> f64 {
+		(raw - self.raw_black) / (self.raw_white - self.raw_black) * 100.0
+	}
+
+	/// Red, green and blue in percent of white from raw `RGB-RAW` values.
+	pub(crate) fn scale_rgb(&self, raw: [f64; 3]) -> [f64; 3] {
+		[0, 1, 2].map(|i| raw[i] / self.white[i] * 100.0)
+	}
+
+	/// The reflection in percent from red, green and blue in percent.
+	pub(crate) fn mix_rgb(&self, rgb: [f64; 3]) -> f64 {
+		let total: f64 = self.weights.iter().sum();
+		rgb.iter().zip(self.weights).map(|(x, weight)| x * weight).sum::<f64>() / total
+	}
+}
+
+/// Refuses color settings that fail [ColorSettings::check].
+pub(crate) fn checked<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ColorSettings, D::Error> {
+	let settings = ColorSettings::deserialize(deserializer)?;
+	settings.check().map_err(de::Error::custom)?;
+	Ok(settings)
+}
+
+/// What happens when we drive over a marker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MarkerAction {
+	/// Only print it into the log.
+	#[default]
+	Log,
+	/// Switch to the exit state.
+	Exit,
+	/// Stop, as at the end of the exit.
+	Stop,
+}
+
+/// A colored marker on the track, e.g. a red stop line.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct Marker {
+	pub(crate) name: String,
+	/// Red, green and blue in percent of white.
+	pub(crate) color: [f64; 3],
+	/// How far away (in percent) a color may be from `color` to still count.
+	pub(crate) tolerance: f64,
+	/// The time in `s` we need to see the color, before it counts.
+	pub(crate) time: f64,
+	#[serde(default)]
+	pub(crate) action: MarkerAction,
+}
+
+/// Recognizes markers, once per time we drive over one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Markers {
+	debounces: Vec<Debounce>,
+	seen: Vec<bool>,
+}
+
+impl Markers {
+	pub(crate) fn reset(&mut self) {
+		self.debounces.clear();
+		self.seen.clear();
+	}
+
+	/// Returns the index of the marker we just drove onto.
+	pub(crate) fn update(&mut self, markers: &[Marker], rgb: [f64; 3], dt: f64) -> Option<usize> {
+		self.debounces.resize_with(markers.len(), Debounce::default);
+		self.seen.resize(markers.len(), false);
+
+		let mut found = None;
+		for (i, marker) in markers.iter().enumerate() {
+			let distance = rgb.iter().zip(marker.color)
+				.map(|(a, b)| (a - b) * (a - b))
+				.sum::<f64>()
+				.sqrt();
+			let ticks = ((marker.time / dt).ceil() as usize).max(1);
+
+			let on_marker = self.debounces[i].update(distance <= marker.tolerance, ticks);
+			if on_marker && !self.seen[i] && found.is_none() {
+				found = Some(i);
+			}
+			self.seen[i] = on_marker;
+		}
+		found
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DT: f64 = 0.01;
+
+	fn markers() -> Vec<Marker> {
+		vec![
+			Marker { name: "red".to_owned(), color: [80.0, 10.0, 10.0], tolerance: 15.0, time: 0.03, action: MarkerAction::Stop },
+			Marker { name: "green".to_owned(), color: [10.0, 80.0, 10.0], tolerance: 15.0, time: 0.0, action: MarkerAction::Log },
+		]
+	}
+
+	#[test]
+	fn a_marker_counts_once_we_saw_it_long_enough() {
+		let markers = markers();
+		let mut seen = Markers::default();
+		let red = [75.0, 15.0, 5.0];
+		assert_eq!(seen.update(&markers, red, DT), None);
+		assert_eq!(seen.update(&markers, red, DT), None);
+		assert_eq!(seen.update(&markers, red, DT), Some(0));
+		// Only once per time we drive over it.
+		assert_eq!(seen.update(&markers, red, DT), None);
+
+		let white = [100.0; 3];
+		assert_eq!(seen.update(&markers, white, DT), None);
+		for _ in 0..2 {
+			assert_eq!(seen.update(&markers, red, DT), None);
+		}
+		assert_eq!(seen.update(&markers, red, DT), Some(0));
+	}
+
+	#[test]
+	fn a_marker_needs_the_time_in_a_row() {
+		let markers = markers();
+		let mut seen = Markers::default();
+		let red = [80.0, 10.0, 10.0];
+		for rgb in [red, red, [100.0; 3], red, red] {
+			assert_eq!(seen.update(&markers, rgb, DT), None);
+		}
+		assert_eq!(seen.update(&markers, red, DT), Some(0));
+	}
+
+	#[test]
+	fn only_colors_within_the_tolerance_count() {
+		let markers = markers();
+		let mut seen = Markers::default();
+		assert_eq!(seen.update(&markers, [10.0, 60.0, 10.0], DT), None);
+		assert_eq!(seen.update(&markers, [10.0, 70.0, 10.0], DT), Some(1));
+
+		seen.reset();
+		assert_eq!(seen.update(&markers, [10.0, 70.0, 10.0], DT), Some(1));
+	}
+
+	#[test]
+	fn scaling() {
+		let settings = ColorSettings { raw_black: 100.0, raw_white: 600.0, white: [500.0, 1000.0, 250.0], weights: [1.0, 0.0, 3.0], ..ColorSettings::default() };
+		assert_eq!(settings.scale_raw(350.0), 50.0);
+		assert_eq!(settings.scale_rgb([250.0, 250.0, 250.0]), [50.0, 25.0, 100.0]);
+		assert_eq!(settings.mix_rgb([20.0, 50.0, 40.0]), 35.0);
+	}
+
+	#[test]
+	fn calibrations_that_divide_by_zero_are_refused() {
+		assert!(ColorSettings::default().check().is_ok());
+		for bad in [
+			"raw_black = 500\nraw_white = 500",
+			"white = [1020, 0, 1020]",
+			"weights = [0, 0, 0]",
+			"weights = [1, -1, 0]",
+		] {
+			let result = checked(toml::from_str::<toml::Value>(bad).unwrap());
+			assert!(result.is_err(), "{bad}");
+		}
+		assert!(checked(toml::from_str::<toml::Value>("weights = [0, 1, 0]").unwrap()).is_ok());
+	}
+}
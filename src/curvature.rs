@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+
+/// Learns the curvature of the circle while we drive on it, and steers along it.
+///
+/// The curvature comes from the odometry of the two wheels while the line PID is settled, i.e.
+/// while the line error is small. The feed-forward then adds the wheel differential that drives
+/// exactly this curvature, so the PID only has to correct deviations.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Curvature {
+	/// Add the feed-forward to the steering.
+	pub(crate) enabled: bool,
+	/// Learn the curvature, this also works without `enabled`.
+	pub(crate) learn: bool,
+
+	/// The distance of the wheels in `cm`.
+	pub(crate) wheel_width: f64,
+	/// The diameter of the wheels in `cm`.
+	pub(crate) wheel_diameter: f64,
+
+	/// How much of a new measurement goes into the curvature per tick, `0.0 ..= 1.0`.
+	pub(crate) learn_rate: f64,
+	/// We only learn while the line error is at most this.
+	pub(crate) max_error: f64,
+	/// We only learn while we drive at least this fast, in `cm/s`.
+	pub(crate) min_speed: f64,
+
+	#[serde(skip)]
+	curvature: Option<f64>,
+	#[serde(skip)]
+	last_position: Option<(f64, f64)>,
+	#[serde(skip)]
+	samples: usize,
+}
+
+impl Default for Curvature {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			learn: true,
+
+			wheel_width: 14.0,
+			wheel_diameter: 5.6,
+
+			learn_rate: 0.01,
+			max_error: 5.0,
+			min_speed: 5.0,
+
+			curvature: None,
+			last_position: None,
+			samples: 0,
+		}
+	}
+}
+
+impl Curvature {
+	/// Forgets the wheel positions, but keeps what we learned about the track.
+	pub(crate) fn reset(&mut self) {
+		self.last_position = None;
+	}
+
+	/// The learned curvature in `1/cm`, positive for clockwise, or the one of the `diameter` we
+	/// were given if we haven't learned anything yet.
+	pub(crate) fn curvature(&self, diameter: f64) -> f64 {
+		self.curvature.unwrap_or(2.0 / diameter)
+	}
+
+	/// The `diameter` in `cm` for the settings file that matches what we learned.
+	pub(crate) fn suggested_diameter(&self) -> Option<f64> {
+		self.curvature.filter(|_| self.samples > 0).map(|x| 2.0 / x)
+	}
+
+	pub(crate) fn samples(&self) -> usize {
+		self.samples
+	}
+
+	/// The relative wheel differential for driving along the curvature.
+	pub(crate) fn feed_forward(&self, diameter: f64) -> f64 {
+		if self.enabled {
+			self.wheel_width * self.curvature(diameter) / 2.0
+		} else {
+			0.0
+		}
+	}
+
+	/// Feeds the wheel positions in rotations, and the line error of this tick.
+	pub(crate) fn update(&mut self, left: f64, right: f64, line_error: f64, dt: f64, diameter: f64) {
+		let Some((last_left, last_right)) = self.last_position.replace((left, right)) else {
+			return;
+		};
+
+		let circumference = std::f64::consts::PI * self.wheel_diameter;
+		let left = (left - last_left) * circumference;
+		let right = (right - last_right) * circumference;
+		let distance = (left + right) / 2.0;
+
+		if !self.learn || line_error.abs() > self.max_error || distance < self.min_speed * dt {
+			return;
+		}
+
+		let measured = (left - right) / (self.wheel_width * distance);
+		let curvature = self.curvature(diameter);
+		self.curvature = Some(curvature + self.learn_rate * (measured - curvature));
+		self.samples += 1;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::f64::consts::PI;
+
+	const DT: f64 = 0.01;
+
+	/// Drives `ticks` along a circle with `diameter`, positive for clockwise, at `speed` in `cm/s`.
+	fn drive(curvature: &mut Curvature, diameter: f64, speed: f64, line_error: f64, ticks: usize) {
+		let circumference = PI * curvature.wheel_diameter;
+		let radius = diameter / 2.0;
+		let (mut left, mut right) = (0.0, 0.0);
+		for _ in 0..=ticks {
+			curvature.update(left, right, line_error, DT, 200.0);
+			let angle = speed * DT / radius;
+			left += (radius + curvature.wheel_width / 2.0) * angle / circumference;
+			right += (radius - curvature.wheel_width / 2.0) * angle / circumference;
+		}
+	}
+
+	#[test]
+	fn learns_the_curvature_of_an_arc() {
+		let mut curvature = Curvature::default();
+		assert_eq!(curvature.suggested_diameter(), None);
+		assert_eq!(curvature.curvature(200.0), 0.01);
+
+		drive(&mut curvature, 120.0, 20.0, 0.0, 2000);
+		assert_eq!(curvature.samples(), 2000);
+		assert!((curvature.curvature(200.0) - 2.0 / 120.0).abs() < 1e-6, "{}", curvature.curvature(200.0));
+		assert!((curvature.suggested_diameter().unwrap() - 120.0).abs() < 0.01);
+	}
+
+	#[test]
+	fn counter_clockwise_is_negative() {
+		let mut curvature = Curvature::default();
+		drive(&mut curvature, -80.0, 20.0, 0.0, 2000);
+		assert!((curvature.suggested_diameter().unwrap() + 80.0).abs() < 0.01);
+	}
+
+	#[test]
+	fn learns_only_while_settled_and_moving() {
+		let mut curvature = Curvature::default();
+		let (max_error, min_speed) = (curvature.max_error, curvature.min_speed);
+		drive(&mut curvature, 120.0, 20.0, max_error + 1.0, 100);
+		drive(&mut curvature, 120.0, min_speed / 2.0, 0.0, 100);
+		assert_eq!(curvature.samples(), 0);
+		assert_eq!(curvature.suggested_diameter(), None);
+
+		curvature.learn = false;
+		drive(&mut curvature, 120.0, 20.0, 0.0, 100);
+		assert_eq!(curvature.samples(), 0);
+	}
+
+	#[test]
+	fn a_reset_keeps_what_we_learned() {
+		let mut curvature = Curvature::default();
+		drive(&mut curvature, 120.0, 20.0, 0.0, 2000);
+		curvature.reset();
+		// The first positions after a reset are no step.
+		curvature.update(1000.0, 0.0, 0.0, DT, 200.0);
+		assert!((curvature.suggested_diameter().unwrap() - 120.0).abs() < 0.01);
+	}
+
+	#[test]
+	fn feed_forward_drives_the_curvature() {
+		let mut curvature = Curvature::default();
+		assert_eq!(curvature.feed_forward(200.0), 0.0);
+		curvature.enabled = true;
+		assert_eq!(curvature.feed_forward(200.0), 14.0 * 0.01 / 2.0);
+	}
+}
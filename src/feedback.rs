@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use ev3dev_lang_rust::{sound, Led, PowerSupply};
+use serde::{Deserialize, Serialize};
+use crate::logging::HARDWARE;
+use crate::state::RobotState;
+
+/// How many cues wait for the ones before them, before we drop new ones.
+const QUEUE: usize = 8;
+
+/// How often we look at the battery.
+const BATTERY_EVERY: Duration = Duration::from_secs(10);
+
+/// How often we say again that the battery is low.
+const LOW_BATTERY_AGAIN: Duration = Duration::from_secs(60);
+
+/// How long the LEDs are off and on again when they blink.
+const BLINK_TIME: Duration = Duration::from_millis(125);
+
+/// The colour of the LEDs between the cues, the one ev3dev starts with.
+const LED_IDLE: LedColor = LedColor::Green;
+
+/// What happened, each with a [Cue] of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Event {
+	/// Ready to go, right before the motors start, for a drive, teleop and autotune.
+	Ready,
+	/// The drive stops at the end.
+	Stop,
+	/// We lost the line and search it.
+	LineLost,
+	/// We found the line again.
+	LineFound,
+	/// We didn't find the line again, and gave up.
+	GaveUp,
+	/// A tick failed, e.g. because a sensor did.
+	Fault,
+	/// The battery is below `low_battery`.
+	LowBattery,
+	/// Autotune is done, and has gains for us.
+	Calibrated,
+}
+
+/// The colours of the LEDs on the brick.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LedColor {
+	Off,
+	Red,
+	Green,
+	Amber,
+	Orange,
+	Yellow,
+}
+
+impl LedColor {
+	fn ev3(&self) -> (u8, u8) {
+		match self {
+			LedColor::Off => Led::COLOR_OFF,
+			LedColor::Red => Led::COLOR_RED,
+			LedColor::Green => Led::COLOR_GREEN,
+			LedColor::Amber => Led::COLOR_AMBER,
+			LedColor::Orange => Led::COLOR_ORANGE,
+			LedColor::Yellow => Led::COLOR_YELLOW,
+		}
+	}
+}
+
+/// One tone: the frequency in `Hz`, how long in `ms`, and the pause after it in `ms`.
+pub(crate) type Tone = (f64, u32, u32);
+
+/// What we play for an [Event]: the tones first, then the text through `espeak`. Meanwhile the
+/// LEDs take the colour, and blink `blink` times, and after it they go back to [LED_IDLE].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Cue {
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub(crate) tones: Vec<Tone>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) speak: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) led: Option<LedColor>,
+	pub(crate) blink: u32,
+}
+
+impl Cue {
+	fn tones(tones: &[Tone], led: LedColor) -> Cue {
+		Cue { tones: tones.to_vec(), led: Some(led), ..Cue::default() }
+	}
+}
+
+/// Plays a [Cue] for every [Event] and state change, so we can tell them apart at the track. A
+/// thread does the playing, so the control loop never waits for it. In the simulation the cues
+/// only go into the log.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Feedback {
+	pub(crate) enabled: bool,
+	/// Below this battery voltage, we play `low_battery` every minute, in `V`.
+	pub(crate) low_battery: f64,
+	pub(crate) cues: BTreeMap<Event, Cue>,
+	/// The cues for entering a state, by its name in the menu, e.g. "drive follow".
+	pub(crate) states: BTreeMap<String, Cue>,
+
+	#[serde(skip)]
+	player: Option<Player>,
+}
+
+impl Default for Feedback {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			low_battery: 7.0,
+			cues: BTreeMap::from([
+				(Event::Ready, Cue::tones(&[(880.0, 100, 50), (1320.0, 150, 0)], LedColor::Green)),
+				(Event::Stop, Cue::tones(&[(660.0, 300, 0)], LedColor::Amber)),
+				(Event::LineLost, Cue { blink: 4, ..Cue::tones(&[(440.0, 80, 40), (440.0, 80, 0)], LedColor::Red) }),
+				(Event::LineFound, Cue::tones(&[(880.0, 80, 0)], LedColor::Green)),
+				(Event::GaveUp, Cue::tones(&[(440.0, 200, 50), (330.0, 200, 50), (220.0, 400, 0)], LedColor::Red)),
+				(Event::Fault, Cue { speak: Some("fault".to_owned()), led: Some(LedColor::Red), blink: 10, ..Cue::default() }),
+				(Event::LowBattery, Cue { speak: Some("battery low".to_owned()), led: Some(LedColor::Orange), blink: 6, ..Cue::default() }),
+				(Event::Calibrated, Cue::tones(&[(660.0, 100, 30), (880.0, 100, 30), (1100.0, 200, 0)], LedColor::Green)),
+			]),
+			states: BTreeMap::from([
+				("drive follow".to_owned(), Cue::tones(&[(1100.0, 100, 0)], LedColor::Green)),
+				("drive exit".to_owned(), Cue::tones(&[(1100.0, 80, 40), (1100.0, 80, 0)], LedColor::Yellow)),
+			]),
+			player: None,
+		}
+	}
+}
+
+/// A cue, and what it is for.
+type Named = (String, Cue);
+
+/// The thread that plays, and how we reach it.
+#[derive(Debug)]
+struct Player {
+	sender: Option<SyncSender<Named>>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl Feedback {
+	/// Starts the thread that plays, if the feedback is enabled. With `simulated` it only logs the
+	/// cues.
+	pub(crate) fn start(&mut self, simulated: bool) -> Result<()> {
+		self.start_with(if simulated { Backend::Mock } else { Backend::Ev3 { led: None } })
+	}
+
+	fn start_with(&mut self, backend: Backend) -> Result<()> {
+		if !self.enabled || self.player.is_some() {
+			return Ok(());
+		}
+		for name in self.states.keys() {
+			if RobotState::from_name(name).is_none() {
+				log::warn!(target: HARDWARE, "feedback: there is no state {name:?}, see the menu for their names");
+			}
+		}
+
+		let low_battery = self.cues.get(&Event::LowBattery).cloned()
+			.map(|cue| (self.low_battery, (format!("{:?}", Event::LowBattery), cue)));
+		let (sender, receiver) = std::sync::mpsc::sync_channel(QUEUE);
+		let thread = std::thread::Builder::new()
+			.name("feedback".to_owned())
+			.spawn(move || play(receiver, backend, low_battery))
+			.context("Failed to start the feedback thread")?;
+		self.player = Some(Player { sender: Some(sender), thread: Some(thread) });
+		Ok(())
+	}
+
+	/// Plays the cue for `event`, if there is one.
+	pub(crate) fn play(&self, event: Event) {
+		if let Some(cue) = self.cues.get(&event) {
+			self.send(format!("{event:?}"), cue);
+		}
+	}
+
+	/// Plays the cue for entering `state`, if there is one.
+	pub(crate) fn enter(&self, state: &RobotState) {
+		let name = RobotState::ALL.iter().find(|(_, x)| x == state).map(|(name, _)| *name);
+		if let Some((name, cue)) = name.and_then(|name| Some((name, self.states.get(name)?))) {
+			self.send(name.to_owned(), cue);
+		}
+	}
+
+	/// Hands `cue` to the thread, or drops it if the thread is still busy with the ones before.
+	fn send(&self, name: String, cue: &Cue) {
+		let Some(sender) = self.player.as_ref().and_then(|x| x.sender.as_ref()) else { return };
+		match sender.try_send((name, cue.clone())) {
+			Ok(()) => {},
+			Err(TrySendError::Full((name, _))) => log::debug!(target: HARDWARE, "feedback: busy, dropping {name}"),
+			Err(TrySendError::Disconnected((name, _))) => log::warn!(target: HARDWARE, "feedback: the thread is gone, dropping {name}"),
+		}
+	}
+}
+
+impl Drop for Player {
+	/// Lets the thread play what is still queued.
+	fn drop(&mut self) {
+		self.sender = None;
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+/// Where the cues go.
+enum Backend {
+	/// The speaker and the LEDs of the brick. We get the LEDs with the first cue that needs them.
+	Ev3 {
+		led: Option<Led>,
+	},
+	/// Only the log, for the simulation.
+	Mock,
+	/// Keeps every cue, for the tests.
+	#[cfg(test)]
+	Recording(std::sync::Arc<std::sync::Mutex<Vec<Named>>>),
+}
+
+impl Backend {
+	fn play(&mut self, (name, cue): &Named) -> Result<()> {
+		#[cfg(test)]
+		if let Backend::Recording(cues) = self {
+			cues.lock().unwrap().push((name.clone(), cue.clone()));
+			return Ok(());
+		}
+		let Backend::Ev3 { led } = self else {
+			log::debug!(target: HARDWARE, "feedback: {name}: {cue:?}");
+			return Ok(());
+		};
+		log::debug!(target: HARDWARE, "feedback: {name}");
+
+		let color = match cue.led {
+			Some(color) => {
+				if led.is_none() {
+					*led = Some(Led::new().context("Failed to get the LEDs")?);
+				}
+				led.as_ref().map(|led| (led, color.ev3()))
+			},
+			None => None,
+		};
+		if let Some((led, color)) = color {
+			led.set_color(color).context("Failed to set the LEDs")?;
+		}
+
+		let tones = (!cue.tones.is_empty()).then(|| {
+			let tones: Vec<(f32, i32, i32)> = cue.tones.iter()
+				.map(|&(frequency, length, pause)| (frequency as f32, length as i32, pause as i32))
+				.collect();
+			sound::tone_sequence(&tones)
+		}).transpose().context("Failed to play the tones")?;
+
+		if let Some((led, color)) = color {
+			for _ in 0..cue.blink {
+				led.set_color(Led::COLOR_OFF).context("Failed to set the LEDs")?;
+				std::thread::sleep(BLINK_TIME);
+				led.set_color(color).context("Failed to set the LEDs")?;
+				std::thread::sleep(BLINK_TIME);
+			}
+		}
+
+		if let Some(mut tones) = tones {
+			tones.wait().context("Failed to play the tones")?;
+		}
+		if let Some(text) = &cue.speak {
+			sound::speak(text).context("Failed to run espeak")?
+				.wait().context("Failed to run espeak")?;
+		}
+		if let Some((led, _)) = color {
+			led.set_color(LED_IDLE.ev3()).context("Failed to set the LEDs")?;
+		}
+		Ok(())
+	}
+
+	/// The voltage of the battery, in `V`.
+	fn battery(&self) -> Result<Option<f64>> {
+		match self {
+			Backend::Ev3 { .. } => {
+				let battery = PowerSupply::new().context("Failed to get the battery")?;
+				let microvolts = battery.get_voltage_now().context("Failed to read the battery voltage")?;
+				Ok(Some(microvolts as f64 / 1_000_000.0))
+			},
+			Backend::Mock => Ok(None),
+			#[cfg(test)]
+			Backend::Recording(_) => Ok(None),
+		}
+	}
+}
+
+/// Plays every cue from `receiver` until the [Feedback] is gone, and meanwhile looks at the
+/// battery.
+fn play(receiver: Receiver<Named>, mut backend: Backend, low_battery: Option<(f64, Named)>) {
+	let mut checked: Option<Instant> = None;
+	let mut warned: Option<Instant> = None;
+	loop {
+		if let Some((minimum, cue)) = &low_battery {
+			if checked.is_none_or(|x| x.elapsed() >= BATTERY_EVERY) {
+				checked = Some(Instant::now());
+				match backend.battery() {
+					Ok(Some(voltage)) if voltage < *minimum && warned.is_none_or(|x| x.elapsed() >= LOW_BATTERY_AGAIN) => {
+						log::warn!(target: HARDWARE, "the battery is low, at {voltage:.2}V");
+						warned = Some(Instant::now());
+						if let Err(err) = backend.play(cue) {
+							log::warn!(target: HARDWARE, "Failed to play the feedback: {err:#}");
+						}
+					},
+					Ok(_) => {},
+					Err(err) => log::debug!(target: HARDWARE, "no battery voltage: {err:#}"),
+				}
+			}
+		}
+
+		match receiver.recv_timeout(BATTERY_EVERY) {
+			Ok(named) => if let Err(err) = backend.play(&named) {
+				log::warn!(target: HARDWARE, "Failed to play the feedback: {err:#}");
+			},
+			Err(RecvTimeoutError::Timeout) => {},
+			Err(RecvTimeoutError::Disconnected) => return,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+	use super::*;
+
+	/// Plays everything `f` plays into a recording, and returns it once the thread played all.
+	fn record(mut feedback: Feedback, f: impl FnOnce(&Feedback)) -> Vec<Named> {
+		let cues = Arc::new(Mutex::new(Vec::new()));
+		feedback.start_with(Backend::Recording(cues.clone())).unwrap();
+		f(&feedback);
+		// Waits for the thread.
+		drop(feedback);
+		Arc::try_unwrap(cues).unwrap().into_inner().unwrap()
+	}
+
+	#[test]
+	fn every_event_plays_its_cue() {
+		let feedback = Feedback::default();
+		let events = [Event::Ready, Event::LineLost, Event::LineFound, Event::GaveUp, Event::Stop, Event::Fault, Event::Calibrated];
+		let expected: Vec<Named> = events.iter()
+			.map(|event| (format!("{event:?}"), feedback.cues[event].clone()))
+			.collect();
+		let played = record(feedback, |feedback| events.iter().for_each(|&event| feedback.play(event)));
+		assert_eq!(played, expected);
+	}
+
+	#[test]
+	fn states_play_their_cue_by_the_name_in_the_menu() {
+		let feedback = Feedback::default();
+		let expected = vec![("drive follow".to_owned(), feedback.states["drive follow"].clone())];
+		let played = record(feedback, |feedback| {
+			feedback.enter(&RobotState::DriveFollow);
+			// Without a cue of its own.
+			feedback.enter(&RobotState::DriveEntry);
+			feedback.enter(&RobotState::InMenu);
+		});
+		assert_eq!(played, expected);
+	}
+
+	#[test]
+	fn events_without_a_cue_play_nothing() {
+		let feedback = Feedback { cues: BTreeMap::new(), states: BTreeMap::new(), ..Feedback::default() };
+		let played = record(feedback, |feedback| {
+			feedback.play(Event::Ready);
+			feedback.enter(&RobotState::DriveFollow);
+		});
+		assert!(played.is_empty());
+	}
+
+	#[test]
+	fn disabled_plays_nothing() {
+		let feedback = Feedback { enabled: false, ..Feedback::default() };
+		let played = record(feedback, |feedback| {
+			assert!(feedback.player.is_none());
+			feedback.play(Event::Ready);
+		});
+		assert!(played.is_empty());
+	}
+
+	#[test]
+	fn full_queue_drops_cues_without_waiting() {
+		// Nobody plays the cues in the queue.
+		let (sender, receiver) = std::sync::mpsc::sync_channel(QUEUE);
+		let feedback = Feedback {
+			player: Some(Player { sender: Some(sender), thread: None }),
+			..Feedback::default()
+		};
+		for _ in 0..QUEUE + 3 {
+			feedback.play(Event::Ready);
+		}
+		feedback.play(Event::Stop);
+		let queued: Vec<String> = receiver.try_iter().map(|(name, _)| name).collect();
+		assert_eq!(queued, vec!["Ready".to_owned(); QUEUE]);
+
+		// Without the thread, we drop them too.
+		drop(receiver);
+		feedback.play(Event::Stop);
+	}
+}
@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+
+/// What the [DistanceFilter] currently believes about the vehicle in front of us.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Estimate {
+	/// The smoothed distance in `cm`.
+	pub(crate) distance: f64,
+	/// The relative speed of the leader in `cm/s`, positive if it drives away from us.
+	pub(crate) speed: f64,
+	/// How much we trust the estimate, `0.0 ..= 1.0`.
+	pub(crate) confidence: f64,
+}
+
+/// An alpha-beta filter for the ultrasonic sensor.
+///
+/// The sensor sometimes reports a single echo that's far off, and without filtering that's enough
+/// to leave the follow state. Readings that are further than `gate` away from the prediction are
+/// rejected, unless we get more than `max_rejects` of them in a row, in which case the jump is real
+/// (for example the leader left the track) and we restart the filter on the new reading.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct DistanceFilter {
+	/// Weight of a new reading for the distance, `0.0 ..= 1.0`.
+	pub(crate) alpha: f64,
+	/// Weight of a new reading for the relative speed, `0.0 ..= 1.0`.
+	pub(crate) beta: f64,
+	/// Readings further than this (in `cm`) from the prediction are outliers.
+	pub(crate) gate: f64,
+	/// The amount of outliers in a row after which we accept the new distance.
+	pub(crate) max_rejects: usize,
+	/// How fast the confidence moves towards `1.0` for good and towards `0.0` for bad readings.
+	pub(crate) confidence_rate: f64,
+
+	#[serde(skip)]
+	estimate: Option<Estimate>,
+	#[serde(skip)]
+	rejects: usize,
+}
+
+impl Default for DistanceFilter {
+	fn default() -> Self {
+		Self {
+			alpha: 0.5,
+			beta: 0.1,
+			gate: 15.0,
+			max_rejects: 5,
+			confidence_rate: 0.2,
+
+			estimate: None,
+			rejects: 0,
+		}
+	}
+}
+
+impl DistanceFilter {
+	pub(crate) fn reset(&mut self) {
+		self.estimate = None;
+		self.rejects = 0;
+	}
+
+	/// Feeds one reading (or [None] if the sensor saw nothing) taken `dt` seconds after the last one.
+	pub(crate) fn update(&mut self, reading: Option<f64>, dt: f64) -> Option<Estimate> {
+		let Some(old) = self.estimate else {
+			// The first reading we get becomes the estimate, but we don't trust it much yet.
+			self.estimate = reading.map(|distance| Estimate {
+				distance,
+				speed: 0.0,
+				confidence: self.confidence_rate,
+			});
+			return self.estimate;
+		};
+
+		let lose_confidence = old.confidence * (1.0 - self.confidence_rate);
+
+		let Some(reading) = reading else {
+			// Without a reading we keep the distance, as moving it along the speed would drift off.
+			self.estimate = Some(Estimate { confidence: lose_confidence, ..old });
+			return self.estimate;
+		};
+
+		let predicted = old.distance + old.speed * dt;
+		let residual = reading - predicted;
+
+		if residual.abs() > self.gate {
+			self.rejects += 1;
+			self.estimate = if self.rejects > self.max_rejects {
+				self.rejects = 0;
+				Some(Estimate { distance: reading, speed: 0.0, confidence: self.confidence_rate })
+			} else {
+				Some(Estimate { distance: predicted, confidence: lose_confidence, ..old })
+			};
+			return self.estimate;
+		}
+
+		self.rejects = 0;
+		self.estimate = Some(Estimate {
+			distance: predicted + self.alpha * residual,
+			speed: old.speed + self.beta * residual / dt,
+			confidence: old.confidence + self.confidence_rate * (1.0 - old.confidence),
+		});
+		self.estimate
+	}
+}
+
+/// Only lets a condition through once it held for a given amount of ticks in a row.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Debounce {
+	ticks: usize,
+}
+
+impl Debounce {
+	pub(crate) fn reset(&mut self) {
+		self.ticks = 0;
+	}
+
+	/// Whether `condition` held for the last `required_ticks`, and at least in this tick.
+	pub(crate) fn update(&mut self, condition: bool, required_ticks: usize) -> bool {
+		if condition {
+			self.ticks += 1;
+		} else {
+			self.ticks = 0;
+		}
+		condition && self.ticks >= required_ticks.max(1)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DT: f64 = 0.01;
+
+	fn settled(distance: f64) -> DistanceFilter {
+		let mut filter = DistanceFilter::default();
+		for _ in 0..50 {
+			filter.update(Some(distance), DT);
+		}
+		filter
+	}
+
+	#[test]
+	fn the_first_reading_is_the_estimate_with_little_confidence() {
+		let mut filter = DistanceFilter::default();
+		assert_eq!(filter.update(None, DT), None);
+		let estimate = filter.update(Some(40.0), DT).unwrap();
+		assert_eq!(estimate.distance, 40.0);
+		assert_eq!(estimate.speed, 0.0);
+		assert_eq!(estimate.confidence, filter.confidence_rate);
+	}
+
+	#[test]
+	fn good_readings_build_confidence_and_follow_the_distance() {
+		let mut filter = settled(40.0);
+		let estimate = filter.update(Some(40.0), DT).unwrap();
+		assert!((estimate.distance - 40.0).abs() < 1e-9);
+		assert!(estimate.confidence > 0.99);
+
+		// A leader driving away at 10cm/s.
+		let mut estimate = estimate;
+		for tick in 1..=500 {
+			estimate = filter.update(Some(40.0 + 10.0 * tick as f64 * DT), DT).unwrap();
+		}
+		assert!((estimate.distance - 90.0).abs() < 0.5, "{estimate:?}");
+		assert!((estimate.speed - 10.0).abs() < 0.5, "{estimate:?}");
+	}
+
+	#[test]
+	fn outliers_are_rejected() {
+		let mut filter = settled(40.0);
+		let before = filter.update(Some(40.0), DT).unwrap();
+		let estimate = filter.update(Some(250.0), DT).unwrap();
+		assert!((estimate.distance - 40.0).abs() < 1e-9);
+		assert!(estimate.confidence < before.confidence);
+
+		// Back to normal, the filter goes on like nothing happened.
+		let estimate = filter.update(Some(40.0), DT).unwrap();
+		assert!((estimate.distance - 40.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn too_many_outliers_in_a_row_are_the_new_distance() {
+		let mut filter = settled(40.0);
+		for _ in 0..filter.max_rejects {
+			let estimate = filter.update(Some(250.0), DT).unwrap();
+			assert!((estimate.distance - 40.0).abs() < 1e-9);
+		}
+		let estimate = filter.update(Some(250.0), DT).unwrap();
+		assert_eq!(estimate.distance, 250.0);
+		assert_eq!(estimate.speed, 0.0);
+		assert_eq!(estimate.confidence, filter.confidence_rate);
+	}
+
+	#[test]
+	fn confidence_drops_without_readings() {
+		let mut filter = settled(40.0);
+		let mut last = filter.update(Some(40.0), DT).unwrap();
+		for _ in 0..20 {
+			let estimate = filter.update(None, DT).unwrap();
+			assert!(estimate.confidence < last.confidence);
+			assert_eq!(estimate.distance, last.distance);
+			last = estimate;
+		}
+		assert!(last.confidence < DistanceFilter::default().confidence_rate);
+
+		filter.reset();
+		assert_eq!(filter.update(None, DT), None);
+	}
+
+	#[test]
+	fn debounce_waits_for_the_ticks() {
+		let mut debounce = Debounce::default();
+		assert!(!debounce.update(true, 3));
+		assert!(!debounce.update(true, 3));
+		assert!(debounce.update(true, 3));
+		assert!(debounce.update(true, 3));
+	}
+
+	#[test]
+	fn debounce_starts_over_when_the_condition_breaks() {
+		let mut debounce = Debounce::default();
+		assert!(!debounce.update(true, 2));
+		assert!(!debounce.update(false, 2));
+		assert!(!debounce.update(true, 2));
+		assert!(debounce.update(true, 2));
+	}
+
+	#[test]
+	fn debounce_without_ticks_still_needs_the_condition() {
+		let mut debounce = Debounce::default();
+		assert!(!debounce.update(false, 0));
+		assert!(!debounce.update(false, 0));
+		assert!(debounce.update(true, 0));
+		assert!(!debounce.update(false, 0));
+		assert!(debounce.update(true, 1));
+	}
+
+	#[test]
+	fn debounce_reset() {
+		let mut debounce = Debounce::default();
+		debounce.update(true, 2);
+		debounce.reset();
+		assert!(!debounce.update(true, 2));
+	}
+}
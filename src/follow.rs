@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+use crate::filter::Estimate;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FollowMode {
+	/// Regulate the distance with the `[distance]` PID.
+	#[default]
+	Pid,
+	/// Keep a constant time gap to the leader, see [Follow::update].
+	TimeGap,
+}
+
+/// The controller for driving behind the leader in [crate::state::RobotState::DriveFollow].
+///
+/// With [FollowMode::TimeGap] the gap we want grows with our own speed:
+/// `standstill + time_gap * v`. We drive at the estimated speed of the leader, plus a correction for
+/// the gap error, and we only change our speed within the acceleration and jerk limits.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Follow {
+	pub(crate) mode: FollowMode,
+
+	/// The gap in `cm` we keep when the leader stands still, above `stop_distance`.
+	pub(crate) standstill: f64,
+	/// The time gap in `s`.
+	pub(crate) time_gap: f64,
+	/// How much speed in `cm/s` we add per `cm` of gap error.
+	pub(crate) k_gap: f64,
+	/// How fast in `1/s` we move our speed towards the speed we want.
+	pub(crate) k_speed: f64,
+	/// The maximum acceleration and deceleration in `cm/s²`.
+	pub(crate) max_accel: f64,
+	pub(crate) max_decel: f64,
+	/// The maximum change of the acceleration in `cm/s³`.
+	pub(crate) max_jerk: f64,
+	/// The speed in `cm/s` one percent of motor speed gives, see the table in the settings file.
+	pub(crate) cm_per_percent: f64,
+
+	#[serde(skip)]
+	speed: Option<f64>,
+	#[serde(skip)]
+	accel: f64,
+}
+
+impl Default for Follow {
+	fn default() -> Self {
+		Self {
+			mode: FollowMode::default(),
+
+			standstill: 30.0,
+			time_gap: 0.3,
+			k_gap: 1.0,
+			k_speed: 4.0,
+			max_accel: 40.0,
+			max_decel: 80.0,
+			max_jerk: 400.0,
+			cm_per_percent: 0.45,
+
+			speed: None,
+			accel: 0.0,
+		}
+	}
+}
+
+impl Follow {
+	pub(crate) fn reset(&mut self) {
+		self.speed = None;
+		self.accel = 0.0;
+	}
+
+	/// The gap in `cm` we want at our speed of `speed` in `cm/s`.
+	pub(crate) fn gap(&self, speed: f64) -> f64 {
+		self.standstill + self.time_gap * speed
+	}
+
+	/// Returns the relative speed correction for driving at `speed` percent.
+	///
+	/// Without an estimate we go back to `speed`, with the same limits.
+	pub(crate) fn update(&mut self, estimate: Option<Estimate>, speed: f64, correction_max: f64, dt: f64) -> f64 {
+		let base = speed * self.cm_per_percent;
+		if base <= 0.0 {
+			return 0.0;
+		}
+		let max = base * (1.0 + correction_max);
+		let own = *self.speed.get_or_insert(base);
+
+		let target = match estimate {
+			Some(estimate) => {
+				let leader = own + estimate.speed;
+				leader + self.k_gap * (estimate.distance - self.gap(own))
+			},
+			None => base,
+		};
+
+		let wanted_accel = (self.k_speed * (target - own)).clamp(-self.max_decel, self.max_accel);
+		let max_change = self.max_jerk * dt;
+		self.accel = wanted_accel.clamp(self.accel - max_change, self.accel + max_change);
+
+		let new = (own + self.accel * dt).clamp(0.0, max);
+		if new == 0.0 || new == max {
+			// We can't go any further, so don't build up acceleration we'd need to get rid of later.
+			self.accel = 0.0;
+		}
+		self.speed = Some(new);
+
+		new / base - 1.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DT: f64 = 0.01;
+
+	fn time_gap() -> Follow {
+		Follow { mode: FollowMode::TimeGap, ..Follow::default() }
+	}
+
+	fn estimate(distance: f64, speed: f64) -> Option<Estimate> {
+		Some(Estimate { distance, speed, confidence: 1.0 })
+	}
+
+	#[test]
+	fn nothing_to_correct_at_the_gap_we_want() {
+		let mut follow = time_gap();
+		let own = 40.0 * follow.cm_per_percent;
+		for _ in 0..100 {
+			let correction = follow.update(estimate(follow.gap(own), 0.0), 40.0, 0.4, DT);
+			assert!(correction.abs() < 1e-9, "{correction}");
+		}
+	}
+
+	#[test]
+	fn slower_when_too_close_and_faster_when_too_far() {
+		let mut follow = time_gap();
+		let own = 40.0 * follow.cm_per_percent;
+		let mut correction = 0.0;
+		for _ in 0..50 {
+			correction = follow.update(estimate(follow.gap(own) - 10.0, 0.0), 40.0, 0.4, DT);
+		}
+		assert!(correction < -0.1, "{correction}");
+
+		follow.reset();
+		for _ in 0..500 {
+			correction = follow.update(estimate(follow.gap(own) + 50.0, 0.0), 40.0, 0.4, DT);
+		}
+		// Never faster than the correction allows.
+		assert!((correction - 0.4).abs() < 1e-9, "{correction}");
+	}
+
+	#[test]
+	fn without_a_leader_we_go_back_to_our_speed() {
+		let mut follow = time_gap();
+		for _ in 0..50 {
+			follow.update(estimate(5.0, -10.0), 40.0, 0.4, DT);
+		}
+		let mut correction = -1.0;
+		for _ in 0..500 {
+			correction = follow.update(None, 40.0, 0.4, DT);
+		}
+		assert!(correction.abs() < 1e-3, "{correction}");
+	}
+
+	#[test]
+	fn speed_changes_within_the_acceleration_and_jerk_limits() {
+		let mut follow = time_gap();
+		let base = 40.0 * follow.cm_per_percent;
+		let mut last = base;
+		let mut last_accel = 0.0;
+		for _ in 0..200 {
+			let correction = follow.update(estimate(0.0, -30.0), 40.0, 0.4, DT);
+			let speed = base * (1.0 + correction);
+			if speed == 0.0 {
+				// Where we stop, we stop braking all at once.
+				break;
+			}
+			let accel = (speed - last) / DT;
+			assert!(accel >= -follow.max_decel - 1e-6, "{accel}");
+			assert!((accel - last_accel).abs() <= follow.max_jerk * DT + 1e-6, "{accel} after {last_accel}");
+			last = speed;
+			last_accel = accel;
+		}
+		assert!(last < base);
+		// We stopped, and hold there.
+		assert_eq!(follow.update(estimate(0.0, -30.0), 40.0, 0.4, DT), -1.0);
+	}
+
+	#[test]
+	fn the_gap_settles_behind_a_leader() {
+		let mut follow = time_gap();
+		let leader = 15.0;
+		let mut own = 40.0 * follow.cm_per_percent;
+		let mut gap = 60.0;
+		for _ in 0..3000 {
+			let correction = follow.update(estimate(gap, leader - own), 40.0, 0.4, DT);
+			own = 40.0 * follow.cm_per_percent * (1.0 + correction);
+			gap += (leader - own) * DT;
+			assert!(gap > follow.standstill, "{gap}");
+		}
+		assert!((own - leader).abs() < 0.1, "{own}");
+		assert!((gap - follow.gap(leader)).abs() < 0.5, "{gap}");
+	}
+
+	#[test]
+	fn standing_still_corrects_nothing() {
+		let mut follow = time_gap();
+		assert_eq!(follow.update(estimate(10.0, 0.0), 0.0, 0.4, DT), 0.0);
+	}
+}
@@ -0,0 +1,163 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use crate::robot::button::{Input, Timing};
+
+/// Which way a motor turns for a positive speed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Polarity {
+	#[default]
+	Normal,
+	Inversed,
+}
+
+impl Polarity {
+	/// The name in ev3dev.
+	pub(crate) fn name(&self) -> &'static str {
+		match self {
+			Polarity::Normal => "normal",
+			Polarity::Inversed => "inversed",
+		}
+	}
+}
+
+/// What a motor does when it stops.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StopAction {
+	/// Let it roll out.
+	Coast,
+	/// Short the motor, so it stops quickly.
+	#[default]
+	Brake,
+	/// Actively hold the position.
+	Hold,
+}
+
+impl StopAction {
+	/// The name in ev3dev.
+	pub(crate) fn name(&self) -> &'static str {
+		match self {
+			StopAction::Coast => "coast",
+			StopAction::Brake => "brake",
+			StopAction::Hold => "hold",
+		}
+	}
+}
+
+/// How one motor is connected.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct MotorConfig {
+	/// The motor port, `outA` to `outD`.
+	pub(crate) port: String,
+	#[serde(default)]
+	pub(crate) polarity: Polarity,
+	#[serde(default)]
+	pub(crate) stop_action: StopAction,
+	/// The highest speed we ever give the motor, in percent.
+	#[serde(default = "MotorConfig::full_speed")]
+	pub(crate) max_speed: f64,
+}
+
+impl MotorConfig {
+	fn full_speed() -> f64 {
+		100.0
+	}
+}
+
+/// Which device is on which port, so rewiring the robot doesn't need a new build.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Hardware {
+	pub(crate) left: MotorConfig,
+	pub(crate) right: MotorConfig,
+	pub(crate) top_arm: MotorConfig,
+
+	/// The sensor ports, `in1` to `in4`.
+	pub(crate) color: String,
+	pub(crate) touch: String,
+	pub(crate) distance: String,
+
+	/// Where the button presses come from, see [Input].
+	pub(crate) buttons: Input,
+	pub(crate) button_timing: Timing,
+}
+
+impl Hardware {
+	/// The fastest both wheels can go, in percent, see [crate::mixer::mix].
+	pub(crate) fn max_wheel_speed(&self) -> f64 {
+		self.left.max_speed.min(self.right.max_speed)
+	}
+}
+
+impl Default for Hardware {
+	fn default() -> Self {
+		Self {
+			left: MotorConfig {
+				port: "outB".to_owned(),
+				polarity: Polarity::Normal,
+				stop_action: StopAction::Brake,
+				max_speed: 100.0,
+			},
+			right: MotorConfig {
+				port: "outA".to_owned(),
+				polarity: Polarity::Normal,
+				stop_action: StopAction::Brake,
+				max_speed: 100.0,
+			},
+			top_arm: MotorConfig {
+				port: "outC".to_owned(),
+				polarity: Polarity::Inversed,
+				stop_action: StopAction::Coast,
+				max_speed: 100.0,
+			},
+
+			color: "in1".to_owned(),
+			touch: "in2".to_owned(),
+			distance: "in3".to_owned(),
+			buttons: Input::Brick,
+			button_timing: Timing::default(),
+		}
+	}
+}
+
+impl Hardware {
+	/// Fails if two devices share a port. A line sensor may share the port of the color sensor,
+	/// it's the same sensor then.
+	pub(crate) fn check_ports<'a>(&self, line_sensors: impl IntoIterator<Item = &'a str>) -> Result<()> {
+		let mut ports: Vec<&str> = vec![
+			&self.left.port, &self.right.port, &self.top_arm.port, &self.color, &self.touch, &self.distance,
+		];
+		for port in line_sensors {
+			if port != self.color {
+				ports.push(port);
+			}
+		}
+		for (i, port) in ports.iter().enumerate() {
+			if ports[..i].contains(port) {
+				bail!("The port {port} is used for more than one device");
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn every_port_once() {
+		let hardware = Hardware::default();
+		assert!(hardware.check_ports([]).is_ok());
+		// The color sensor, and a second one.
+		assert!(hardware.check_ports(["in1", "in4"]).is_ok());
+
+		for line_sensors in [["in4", "in4"], ["in1", "in2"], ["in3", "in4"], ["outB", "in4"]] {
+			assert!(hardware.check_ports(line_sensors).is_err(), "{line_sensors:?}");
+		}
+
+		let hardware = Hardware { touch: "in1".to_owned(), ..Hardware::default() };
+		assert!(hardware.check_ports([]).is_err());
+	}
+}
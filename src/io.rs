@@ -1,25 +1,302 @@
-use anyhow::{Context, Result};
-use std::path::Path;
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::autotune::Gains;
+use crate::logging::SETTINGS;
 use crate::program::Program;
 
-pub(crate) fn read() -> Result<Program> {
-	let path = Path::new("./robot_settings.toml");
+/// The name of the settings file of the robot.
+pub(crate) const SETTINGS_FILE: &str = "robot_settings.toml";
+/// Where the settings of every robot live, and the last place we search the one of the robot.
+const SYSTEM_DIR: &str = "/etc/roborace";
+/// The settings of every robot, below the ones of the robot.
+const SYSTEM_FILE: &str = "/etc/roborace/system.toml";
 
-	if path.exists() {
+/// Which files we read the settings from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Locations {
+	/// The settings file of the robot. Without it, we search it, see [find].
+	pub(crate) config: Option<PathBuf>,
+	/// The settings of this run, on top of the ones of the robot.
+	pub(crate) run: Option<PathBuf>,
+	/// The name of a `[profiles.<name>]` table from any of the files, on top of everything else.
+	pub(crate) profile: Option<String>,
+}
+
+/// Where the value of a setting came from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Source {
+	Default,
+	File(PathBuf),
+	/// The `[profiles.<name>]` table in a file.
+	Profile(String, PathBuf),
+}
+
+impl Display for Source {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Source::Default => write!(f, "defaults"),
+			Source::File(path) => write!(f, "{}", path.display()),
+			Source::Profile(name, path) => write!(f, "profile {name:?} in {}", path.display()),
+		}
+	}
+}
+
+/// The settings, and where every one of them came from.
+#[derive(Debug)]
+pub(crate) struct Settings {
+	/// With the source of every value in `sources`.
+	pub(crate) program: Program,
+	/// Every source we read, from the bottom to the top.
+	pub(crate) layers: Vec<Source>,
+}
+
+impl Settings {
+	/// Logs the settings every source set, in the order of the layers.
+	pub(crate) fn log_sources(&self) {
+		for layer in &self.layers {
+			let paths: Vec<&str> = self.program.sources.iter()
+				.filter(|(_, source)| *source == layer)
+				.map(|(path, _)| path.as_str())
+				.collect();
+			if paths.is_empty() {
+				log::info!(target: SETTINGS, "from {layer}: nothing");
+			} else {
+				log::info!(target: SETTINGS, "from {layer}: {}", paths.join(", "));
+			}
+		}
+	}
+}
+
+/// The settings file of the robot: `config` if given, or else the first [SETTINGS_FILE] in the
+/// working directory, next to the binary, or in [SYSTEM_DIR].
+pub(crate) fn find(config: Option<&Path>) -> Option<PathBuf> {
+	if let Some(config) = config {
+		return Some(config.to_owned());
+	}
+
+	let binary_dir = std::env::current_exe().ok()
+		.and_then(|x| x.parent().map(Path::to_owned));
+	[Some(PathBuf::from(".")), binary_dir, Some(PathBuf::from(SYSTEM_DIR))]
+		.into_iter()
+		.flatten()
+		.map(|dir| dir.join(SETTINGS_FILE))
+		.find(|path| path.exists())
+}
+
+/// Reads the settings in layers, every one on top of the ones before: the defaults, the
+/// [SYSTEM_FILE] if it exists, the settings file of the robot, the one of the run, and the
+/// profile.
+///
+/// A settings file of the robot we were given has to exist. Only if we didn't find one, we write one
+/// with the defaults into the working directory.
+pub(crate) fn read(locations: &Locations) -> Result<Settings> {
+	let defaults = Program::default();
+	let toml::Value::Table(mut settings) = toml::Value::try_from(&defaults)
+		.context("Failed to serialize the default settings")? else {
+		unreachable!("the settings are a table");
+	};
+	let mut sources = BTreeMap::new();
+	record(&settings, "", &Source::Default, &mut sources);
+	let mut layers = vec![Source::Default];
+
+	let path = match find(locations.config.as_deref()) {
+		Some(path) if path.exists() => path,
+		Some(path) => bail!("There is no settings file {path:?}"),
+		None => {
+			let path = PathBuf::from(".").join(SETTINGS_FILE);
+			log::warn!(target: SETTINGS, "No settings file found, writing new settings file to {path:?}");
+
+			let string = toml::to_string_pretty(&defaults)
+				.context("Failed to serialize the settings")?;
+			std::fs::write(&path, string)
+				.context("Failed to write settings file")?;
+			path
+		},
+	};
+
+	let system = Path::new(SYSTEM_FILE);
+	let files = [
+		system.exists().then(|| system.to_owned()),
+		Some(path.clone()),
+		locations.run.clone(),
+	];
+	let mut profiles: Vec<(PathBuf, toml::Table)> = Vec::new();
+	for layer in files.into_iter().flatten() {
+		let string = std::fs::read_to_string(&layer)
+			.with_context(|| format!("Failed to read settings file {layer:?}"))?;
+		let mut table: toml::Table = toml::from_str(&string)
+			.with_context(|| format!("Failed to parse settings file {layer:?}"))?;
+
+		if let Some(toml::Value::Table(more)) = table.remove("profiles") {
+			profiles.push((layer.clone(), more));
+		}
+		let source = Source::File(layer);
+		merge(&mut settings, &table, "", &source, &mut sources);
+		layers.push(source);
+	}
+
+	// Every file can have a part of the profile, and they go on top of each other like the files.
+	if let Some(profile) = &locations.profile {
+		let overlays: Vec<(&PathBuf, &toml::Table)> = profiles.iter()
+			.filter_map(|(file, tables)| Some((file, tables.get(profile)?.as_table()?)))
+			.collect();
+		if overlays.is_empty() {
+			let names: BTreeSet<&String> = profiles.iter().flat_map(|(_, tables)| tables.keys()).collect();
+			bail!("No profile {profile:?} in the settings, there are only {names:?}");
+		}
+		for (file, overlay) in overlays {
+			let source = Source::Profile(profile.clone(), file.clone());
+			merge(&mut settings, overlay, "", &source, &mut sources);
+			layers.push(source);
+		}
+	}
+
+	let mut program = Program::deserialize(toml::Value::Table(settings))
+		.context("Failed to parse settings")?;
+	program.settings_path = path;
+	program.sources = sources;
+	Ok(Settings { program, layers })
+}
+
+/// Writes new gains for the line PID, see [write_values].
+pub(crate) fn write_line_gains(settings_path: &Path, sources: &BTreeMap<String, Source>, gains: &Gains) -> Result<()> {
+	let values = BTreeMap::from([
+		("line.k_p".to_owned(), toml::Value::Float(gains.k_p)),
+		("line.k_i".to_owned(), toml::Value::Float(gains.k_i)),
+		("line.k_d".to_owned(), toml::Value::Float(gains.k_d)),
+	]);
+	write_values(settings_path, sources, &values)
+}
+
+/// Writes `values` by their paths like `line.k_p` into the settings files, but keeps everything
+/// else (including the comments) as it is.
+///
+/// Every value goes into the file it came from, by `sources`, so it still counts the next time we
+/// read the settings. A value from a profile goes into that profile, and the defaults go into
+/// `settings_path`, the settings file of the robot.
+pub(crate) fn write_values(settings_path: &Path, sources: &BTreeMap<String, Source>, values: &BTreeMap<String, toml::Value>) -> Result<()> {
+	let mut files: BTreeMap<&Path, Vec<(String, &toml::Value)>> = BTreeMap::new();
+	for (key, value) in values {
+		let (path, key) = match sources.get(key) {
+			Some(Source::File(path)) => (path.as_path(), key.clone()),
+			Some(Source::Profile(name, path)) => (path.as_path(), format!("profiles.{name}.{key}")),
+			Some(Source::Default) | None => (settings_path, key.clone()),
+		};
+		files.entry(path).or_default().push((key, value));
+	}
+
+	for (path, values) in files {
 		let string = std::fs::read_to_string(path)
-			.context("Failed to read settings file")?;
-		toml::from_str(&string)
-			.context("Failed to parse settings")
-	} else {
-		println!("No settings file found, writing new settings file to {path:?}");
+			.with_context(|| format!("Failed to read settings file {path:?}"))?;
+		let mut document = string.parse::<toml_edit::Document>()
+			.with_context(|| format!("Failed to parse settings file {path:?}"))?;
+
+		for (key, value) in &values {
+			let value = value.to_string().parse::<toml_edit::Value>()
+				.with_context(|| format!("Failed to write {key:?}"))?;
+			let item = key.split('.').fold(document.as_item_mut(), |item, key| &mut item[key]);
+			*item = toml_edit::Item::Value(value);
+		}
+
+		std::fs::write(path, document.to_string())
+			.with_context(|| format!("Failed to write settings file {path:?}"))?;
+		log::info!(target: SETTINGS, "wrote {:?} to {}", values.iter().map(|(key, _)| key).collect::<Vec<_>>(), path.display());
+	}
+	Ok(())
+}
+
+/// Puts every value of `overlay` into `base`, and goes into the tables that are in both. The
+/// `source` of every value we put in goes into `sources`, under its path after `prefix`.
+fn merge(base: &mut toml::Table, overlay: &toml::Table, prefix: &str, source: &Source, sources: &mut BTreeMap<String, Source>) {
+	for (key, value) in overlay {
+		let path = format!("{prefix}{key}");
+		match (base.get_mut(key), value) {
+			(Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+				merge(base, overlay, &format!("{path}."), source, sources);
+			},
+			_ => {
+				// Whatever was there before is gone now.
+				sources.retain(|x, _| x != &path && !x.starts_with(&format!("{path}.")));
+				if let toml::Value::Table(table) = value {
+					record(table, &format!("{path}."), source, sources);
+				} else {
+					sources.insert(path, source.clone());
+				}
+				base.insert(key.clone(), value.clone());
+			},
+		}
+	}
+}
+
+/// Records `source` for every value in `table`.
+fn record(table: &toml::Table, prefix: &str, source: &Source, sources: &mut BTreeMap<String, Source>) {
+	for (key, value) in table {
+		let path = format!("{prefix}{key}");
+		if let toml::Value::Table(table) = value {
+			record(table, &format!("{path}."), source, sources);
+		} else {
+			sources.insert(path, source.clone());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A fresh directory for the files of one test.
+	fn dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("roborace-io-{name}-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[test]
+	fn missing_config_is_an_error() {
+		let dir = dir("missing");
+		let config = dir.join("nope.toml");
+		assert!(read(&Locations { config: Some(config.clone()), ..Locations::default() }).is_err());
+		assert!(!config.exists());
+	}
+
+	#[test]
+	fn writes_back_where_the_value_came_from() {
+		let dir = dir("write");
+		let config = dir.join(SETTINGS_FILE);
+		let run = dir.join("run.toml");
+		std::fs::write(&config, "# the robot\n[line]\nk_p = -5.0\n\n[profiles.fast.line]\nk_d = 1.0\n").unwrap();
+		std::fs::write(&run, "[line]\nk_i = 0.5\n").unwrap();
+
+		let settings = read(&Locations {
+			config: Some(config.clone()),
+			run: Some(run.clone()),
+			profile: Some("fast".to_owned()),
+		}).unwrap();
+		let sources = &settings.program.sources;
+		assert_eq!(sources["line.k_p"], Source::File(config.clone()));
+		assert_eq!(sources["line.k_i"], Source::File(run.clone()));
+		assert_eq!(sources["line.k_d"], Source::Profile("fast".to_owned(), config.clone()));
+		assert_eq!(sources["speed"], Source::Default);
 
-		let settings = Program::default();
+		write_line_gains(&config, sources, &Gains { k_p: -4.0, k_i: 0.25, k_d: 2.0 }).unwrap();
+		write_values(&config, sources, &BTreeMap::from([("speed".to_owned(), toml::Value::Float(50.0))])).unwrap();
 
-		let string = toml::to_string_pretty(&settings)
-			.context("Failed to serialize the settings")?;
-		std::fs::write(path, string)
-			.context("Failed to write settings file")?;
+		let config: toml::Table = toml::from_str(&std::fs::read_to_string(dir.join(SETTINGS_FILE)).unwrap()).unwrap();
+		let run: toml::Table = toml::from_str(&std::fs::read_to_string(&run).unwrap()).unwrap();
+		assert_eq!(config["line"]["k_p"].as_float(), Some(-4.0));
+		assert_eq!(config["line"].get("k_d"), None);
+		assert_eq!(config["line"].get("k_i"), None);
+		assert_eq!(config["speed"].as_float(), Some(50.0));
+		assert_eq!(run["line"]["k_i"].as_float(), Some(0.25));
+		assert_eq!(run.get("speed"), None);
+		// The value from the profile changes in the profile.
+		assert_eq!(config["profiles"]["fast"]["line"]["k_d"].as_float(), Some(2.0));
+		assert!(std::fs::read_to_string(dir.join(SETTINGS_FILE)).unwrap().contains("# the robot\n"));
 
-		Ok(settings)
+		std::fs::remove_dir_all(dir).unwrap();
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,206 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::process::Stdio;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+
+/// How many presses wait for [next], before we drop the oldest.
+const QUEUE: usize = 100;
+
+/// A key from standard input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Key {
+	Up,
+	Down,
+	Left,
+	Right,
+	Enter,
+	Backspace,
+	Char(char),
+}
+
+#[derive(Default)]
+struct Keys {
+	/// When every key came the last time. A held key comes again and again with the key repeat
+	/// of the terminal.
+	last: HashMap<Key, Instant>,
+	/// Every press, until [next] takes it.
+	pressed: VecDeque<Key>,
+	/// Standard input ended, so no key comes anymore.
+	closed: bool,
+}
+
+static KEYS: Mutex<Option<Keys>> = Mutex::new(None);
+
+/// The settings of the terminal before we started, see [restore].
+static TERMINAL: OnceLock<Option<String>> = OnceLock::new();
+
+/// Reads the keys from standard input in a thread, from now on. On a terminal, we get every key
+/// right away, and don't echo them.
+pub(crate) fn start() -> Result<()> {
+	let mut keys = KEYS.lock().unwrap_or_else(|x| x.into_inner());
+	if keys.is_some() {
+		return Ok(());
+	}
+
+	TERMINAL.get_or_init(|| {
+		let saved = stty(&["-g"]).ok()?;
+		stty(&["-icanon", "-echo", "min", "1"]).ok()?;
+		Some(saved)
+	});
+
+	std::thread::Builder::new()
+		.name("keyboard".to_owned())
+		.spawn(read)
+		.context("Failed to start the keyboard thread")?;
+	*keys = Some(Keys::default());
+	Ok(())
+}
+
+/// Gives the terminal its settings from before [start] back.
+fn restore() {
+	if let Some(Some(saved)) = TERMINAL.get() {
+		let _ = stty(&[saved]);
+	}
+}
+
+/// Gives the terminal its settings back when it goes away, see [restore].
+#[must_use]
+pub(crate) struct Terminal(());
+
+impl Drop for Terminal {
+	fn drop(&mut self) {
+		restore();
+	}
+}
+
+/// Keeps the terminal as it is until the [Terminal] goes away, whichever way we leave, and gives
+/// it its settings back on a panic too, before the panic message.
+pub(crate) fn guard() -> Terminal {
+	let hook = std::panic::take_hook();
+	std::panic::set_hook(Box::new(move |info| {
+		restore();
+		hook(info);
+	}));
+	Terminal(())
+}
+
+/// Runs `stty` on standard input, which fails if that is no terminal.
+fn stty(args: &[&str]) -> Result<String> {
+	let output = std::process::Command::new("stty")
+		.args(args)
+		.stdin(Stdio::inherit())
+		.stderr(Stdio::null())
+		.output()
+		.context("Failed to run stty")?;
+	anyhow::ensure!(output.status.success(), "Standard input is no terminal");
+	Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// The keys in `bytes` from a terminal.
+fn keys(bytes: impl Iterator<Item = u8>) -> impl Iterator<Item = Key> {
+	let mut bytes = bytes.peekable();
+	std::iter::from_fn(move || loop {
+		let key = match bytes.next()? {
+			// The arrows are `ESC [ A` to `ESC [ D`. A lone ESC is nothing, and leaves the next
+			// byte alone.
+			0x1b if bytes.next_if_eq(&b'[').is_some() => match bytes.next()? {
+				b'A' => Key::Up,
+				b'B' => Key::Down,
+				b'C' => Key::Right,
+				b'D' => Key::Left,
+				_ => continue,
+			},
+			0x1b => continue,
+			b'\n' | b'\r' => Key::Enter,
+			0x7f | 0x08 => Key::Backspace,
+			byte => Key::Char(byte as char),
+		};
+		return Some(key);
+	})
+}
+
+fn read() {
+	for key in keys(std::io::stdin().lock().bytes().map_while(|x| x.ok())) {
+		let mut keys = KEYS.lock().unwrap_or_else(|x| x.into_inner());
+		if let Some(keys) = keys.as_mut() {
+			keys.last.insert(key, Instant::now());
+			if keys.pressed.len() >= QUEUE {
+				keys.pressed.pop_front();
+			}
+			keys.pressed.push_back(key);
+		}
+	}
+
+	let mut keys = KEYS.lock().unwrap_or_else(|x| x.into_inner());
+	if let Some(keys) = keys.as_mut() {
+		keys.closed = true;
+	}
+}
+
+/// The key came within the last `time`, so it is probably held.
+pub(crate) fn is_held(key: Key, time: Duration) -> bool {
+	let keys = KEYS.lock().unwrap_or_else(|x| x.into_inner());
+	keys.as_ref()
+		.and_then(|x| x.last.get(&key))
+		.is_some_and(|x| x.elapsed() <= time)
+}
+
+/// The oldest press we didn't take yet, or [None] without one, or if standard input ended.
+pub(crate) fn next() -> Option<Key> {
+	let mut keys = KEYS.lock().unwrap_or_else(|x| x.into_inner());
+	keys.as_mut()?.pressed.pop_front()
+}
+
+/// Takes the oldest press of `key` we didn't take yet, and leaves the other presses alone.
+pub(crate) fn take(key: Key) -> bool {
+	let mut keys = KEYS.lock().unwrap_or_else(|x| x.into_inner());
+	let Some(pressed) = keys.as_mut().map(|x| &mut x.pressed) else { return false };
+	match pressed.iter().position(|&x| x == key) {
+		Some(i) => pressed.remove(i).is_some(),
+		None => false,
+	}
+}
+
+/// Forgets every press we didn't take yet.
+pub(crate) fn clear() {
+	let mut keys = KEYS.lock().unwrap_or_else(|x| x.into_inner());
+	if let Some(keys) = keys.as_mut() {
+		keys.pressed.clear();
+	}
+}
+
+/// Standard input ended, and every press is taken.
+pub(crate) fn is_closed() -> bool {
+	let keys = KEYS.lock().unwrap_or_else(|x| x.into_inner());
+	keys.as_ref().is_some_and(|x| x.closed && x.pressed.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(bytes: &[u8]) -> Vec<Key> {
+		keys(bytes.iter().copied()).collect()
+	}
+
+	#[test]
+	fn arrows_and_keys() {
+		assert_eq!(parse(b"\x1b[A\x1b[B\x1b[C\x1b[D"), [Key::Up, Key::Down, Key::Right, Key::Left]);
+		assert_eq!(parse(b"a\r\n\x7f"), [Key::Char('a'), Key::Enter, Key::Enter, Key::Backspace]);
+	}
+
+	#[test]
+	fn a_lone_escape_keeps_the_next_keys() {
+		assert_eq!(parse(b"\x1b\r\x1b[A"), [Key::Enter, Key::Up]);
+		assert_eq!(parse(b"\x1b\x1b[B"), [Key::Down]);
+		assert_eq!(parse(b"\x1b"), []);
+	}
+
+	#[test]
+	fn other_sequences_are_nothing() {
+		assert_eq!(parse(b"\x1b[Hx"), [Key::Char('x')]);
+		assert_eq!(parse(b"\x1b["), []);
+	}
+}
@@ -0,0 +1,192 @@
+mod robot;
+mod autotune;
+mod cli;
+mod color;
+mod curvature;
+mod filter;
+mod feedback;
+mod follow;
+mod hardware;
+mod menu;
+mod mixer;
+mod pid;
+mod program;
+mod ramp;
+mod recorder;
+mod recovery;
+mod remote;
+mod runs;
+mod schedule;
+mod selftest;
+mod io;
+mod keyboard;
+mod line;
+mod logging;
+mod state;
+mod states;
+mod stream;
+mod sim;
+mod telemetry;
+mod teleop;
+mod tune;
+mod watch;
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser};
+
+use crate::cli::{Backend, Cli, Command, RunsCommand, Simulation};
+use crate::io::Locations;
+use crate::logging::Sink;
+use crate::program::Program;
+use crate::robot::Robot;
+use crate::state::RobotState;
+
+/// The program running on the robot.
+pub fn run() -> Result<()> {
+    // We want long stack traces.
+    std::env::set_var("RUST_BACKTRACE", "full");
+
+    let cli = Cli::parse();
+    // Until we know the settings, we log as the defaults say.
+    logging::init();
+
+    if let Some(Command::Completions { shell }) = cli.command {
+        clap_complete::generate(shell, &mut Cli::command(), "roborace2023", &mut std::io::stdout());
+        return Ok(());
+    }
+
+    // Only run this we there's no argument (first one is the program itself).
+    if std::env::args().len() == 1 {
+        #[cfg(target_arch = "arm")]
+        // setup the fonts
+        std::process::Command::new("setfont")
+            .arg("/usr/share/consolefonts/Lat2-Terminus14.psf.gz")
+            .status()?;
+    }
+
+    let settings = io::read(&Locations {
+        config: cli.config.clone(),
+        run: cli.run_config.clone(),
+        profile: cli.profile.clone(),
+    }).context("Failed to read the config file")?;
+    let mut logging = settings.program.logging().clone();
+    if let Some(level) = cli.log_level {
+        logging.level = level;
+    }
+    logging.targets.extend(cli.log_targets.iter().cloned());
+    // The flight recorder keeps its log lines there.
+    if settings.program.recorder().enabled && !logging.sinks.contains(&Sink::Memory) {
+        logging.sinks.push(Sink::Memory);
+    }
+    logging::configure(&logging, &settings.program.settings_path)
+        .context("Failed to set up logging")?;
+    recorder::configure(settings.program.recorder(), &settings.program.runs().dir(&settings.program.settings_path));
+    recorder::install_panic_hook();
+    // The keyboard takes the terminal, and we give it back however we leave.
+    let _terminal = keyboard::guard();
+
+    settings.log_sources();
+    let mut program = settings.program;
+
+    program.set_record_runs(true);
+    if let Some(buttons) = cli.buttons {
+        program.set_buttons(buttons);
+    }
+
+    let command = cli.command.unwrap_or(Command::Menu);
+
+    if !cli.dry_run && !matches!(command, Command::Runs { .. }) {
+        program.start_stream().context("Failed to start the telemetry stream")?;
+        program.start_remote().context("Failed to start the remote control")?;
+    }
+
+    if let Command::Runs { command } = &command {
+        return match command {
+            RunsCommand::List => program.runs().print_list(&program.settings_path),
+            RunsCommand::Show { name } => program.runs().show(&program.settings_path, name.as_deref()),
+        };
+    }
+
+    // The simulations don't need any hardware, so we can run them on any machine.
+    if cli.backend == Backend::Sim || matches!(command, Command::Sim { .. }) {
+        let state = match command {
+            Command::Sim { simulation: Simulation::Follow } => None,
+            Command::Sim { simulation: Simulation::Track } | Command::Drive | Command::Start => Some(RobotState::DriveEntry),
+            Command::DriveSimple => Some(RobotState::DriveSimpleOnly),
+            Command::Teleop => Some(RobotState::Teleop),
+            Command::Menu => Some(RobotState::InMenu),
+            other => bail!("The command {:?} needs the robot, it can't run with `--backend sim`", other.name()),
+        };
+        if cli.dry_run {
+            println!("dry run: would simulate {:?}", command.name());
+            return Ok(());
+        }
+        return match state {
+            None => sim::follow::follow(&mut program),
+            Some(state) => sim::track(&mut program, state),
+        };
+    }
+
+    let bot = Robot::new(program.hardware(), program.line_sensors()).context("Failed to create robot")?;
+
+    if cli.dry_run {
+        println!("dry run: found all devices, would run {:?}", command.name());
+        return Ok(());
+    }
+    program.start_feedback(&bot).context("Failed to start the feedback")?;
+
+    let res = run_command(&mut program, &bot, command);
+    // Before looking at the result, we stop all the motors.
+    // This ensures that when the program exits (besides panic), we stop the motors.
+    let _ = bot.left.stop();
+    let _ = bot.right.stop();
+    let _ = bot.top_arm.stop();
+    log::logger().flush();
+    res?;
+
+    Ok(())
+}
+
+fn run_command(program: &mut Program, bot: &Robot, command: Command) -> Result<()> {
+    if let Some(state) = command.state() {
+        return program.main(bot, state);
+    }
+
+    match command {
+        Command::L { rotations } => bot.left.step(rotations),
+        Command::R { rotations } => bot.right.step(rotations),
+        Command::Print => {
+            println!("{bot:#?}");
+
+            Ok(())
+        },
+        Command::Selftest => {
+            program.configure_sensors(bot)
+                .context("Failed to configure the color sensors")?;
+
+            let report = selftest::run(bot);
+            report.print();
+            if report.failed() > 0 {
+                bail!("The self test failed {} checks", report.failed());
+            }
+
+            Ok(())
+        },
+        other => bail!("The command {:?} can't run on the robot", other.name()),
+    }
+}
+
+/// Searches for good settings in the simulation, see `bin/tune.rs`.
+pub fn tune() -> Result<()> {
+    tune::main()
+}
+
+/// Gets and sets the settings of the robot while it runs, see `bin/remote.rs`.
+pub fn remote() -> Result<()> {
+    remote::client::main()
+}
+
+/// Watches the telemetry stream of the robot, see `bin/watch.rs`.
+pub fn watch() -> Result<()> {
+    watch::main()
+}
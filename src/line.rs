@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+/// A color sensor looking at the line.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct LineSensor {
+	/// The sensor port, `in1` to `in4`.
+	pub(crate) port: String,
+	/// How far to the right of the middle of the robot the sensor is, in `cm`.
+	pub(crate) offset: f64,
+}
+
+/// Where the robot is relative to the line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Position {
+	/// How far the robot is to the right of the line (or of the edge, with one sensor), in `cm`.
+	pub(crate) offset: f64,
+	/// How sure we are about the offset, `0.0 ..= 1.0`.
+	pub(crate) confidence: f64,
+}
+
+/// Estimates the position of the robot relative to the line from one or more color sensors.
+///
+/// With one sensor we follow the edge, the reflection minus `line.center` is the offset, scaled by
+/// `scale`. With more sensors, the line is where it's darkest under all of them, and we keep the
+/// side we last saw it on when it's under none of them.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct LinePosition {
+	/// The reflection of the line and of the floor, in percent.
+	pub(crate) black: f64,
+	pub(crate) white: f64,
+	/// The change of the reflection in percent for one `cm` at the edge. The line PID gets the
+	/// offset times this, so the same gains work with one or more sensors.
+	pub(crate) scale: f64,
+	/// Below this confidence we don't trust the sensors, and say the line is on the side we
+	/// last saw it on.
+	pub(crate) min_confidence: f64,
+
+	#[serde(skip)]
+	last_offset: f64,
+}
+
+impl Default for LinePosition {
+	fn default() -> Self {
+		Self {
+			black: 8.0,
+			white: 80.0,
+			scale: 36.0,
+			min_confidence: 0.3,
+
+			last_offset: 0.0,
+		}
+	}
+}
+
+impl LinePosition {
+	pub(crate) fn reset(&mut self) {
+		self.last_offset = 0.0;
+	}
+
+	/// `readings` has the offset of every sensor and its reflection. `center` is the reflection
+	/// of the edge we follow with a single sensor.
+	pub(crate) fn estimate(&mut self, readings: &[(f64, f64)], center: f64) -> Position {
+		let darkness = |reflection: f64| ((self.white - reflection) / (self.white - self.black)).clamp(0.0, 1.0);
+
+		let position = match readings {
+			[] => Position { offset: 0.0, confidence: 0.0 },
+			[(_, reflection)] => {
+				// Full confidence on the edge, none on pure black or white.
+				let confidence = 1.0 - (2.0 * darkness(*reflection) - 1.0).abs();
+				Position { offset: (reflection - center) / self.scale, confidence }
+			},
+			_ => {
+				let total: f64 = readings.iter().map(|(_, x)| darkness(*x)).sum();
+				let confidence = readings.iter().map(|(_, x)| darkness(*x)).fold(0.0, f64::max);
+				if total == 0.0 {
+					Position { offset: 0.0, confidence: 0.0 }
+				} else {
+					let line = readings.iter().map(|(offset, x)| offset * darkness(*x)).sum::<f64>() / total;
+					Position { offset: -line, confidence }
+				}
+			},
+		};
+
+		if readings.len() > 1 && position.confidence < self.min_confidence {
+			// We never saw the line, so we don't know which side it's on.
+			if self.last_offset == 0.0 {
+				return Position { offset: 0.0, confidence: 0.0 };
+			}
+			// We crossed the line or lost it: it's still on the side we saw it on last, and at
+			// least as far away as our outermost sensor.
+			let outermost = readings.iter().map(|(offset, _)| offset.abs()).fold(0.0, f64::max);
+			let offset = self.last_offset.signum() * outermost.max(self.last_offset.abs());
+			return Position { offset, confidence: position.confidence };
+		}
+
+		self.last_offset = position.offset;
+		position
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Three sensors, 2cm apart.
+	fn readings(reflections: [f64; 3]) -> [(f64, f64); 3] {
+		[(-2.0, reflections[0]), (0.0, reflections[1]), (2.0, reflections[2])]
+	}
+
+	#[test]
+	fn one_sensor_follows_the_edge() {
+		let mut position = LinePosition::default();
+		let on_edge = position.estimate(&[(0.0, 44.0)], 44.0);
+		assert_eq!(on_edge.offset, 0.0);
+		assert!(on_edge.confidence > 0.99);
+
+		let off = position.estimate(&[(0.0, 80.0)], 44.0);
+		assert_eq!(off.offset, 1.0);
+		assert_eq!(off.confidence, 0.0);
+	}
+
+	#[test]
+	fn the_line_is_where_it_is_darkest() {
+		let mut position = LinePosition::default();
+		let middle = position.estimate(&readings([80.0, 8.0, 80.0]), 44.0);
+		assert_eq!(middle, Position { offset: 0.0, confidence: 1.0 });
+
+		// The line under the right sensor means the robot is left of it.
+		let right = position.estimate(&readings([80.0, 80.0, 8.0]), 44.0);
+		assert_eq!(right, Position { offset: -2.0, confidence: 1.0 });
+
+		// Between two sensors.
+		let between = position.estimate(&readings([80.0, 44.0, 44.0]), 44.0);
+		assert!((between.offset + 1.0).abs() < 1e-9, "{between:?}");
+		assert!((between.confidence - 0.5).abs() < 1e-9, "{between:?}");
+	}
+
+	#[test]
+	fn a_lost_line_stays_on_the_side_we_saw_it_on() {
+		let mut position = LinePosition::default();
+		position.estimate(&readings([8.0, 80.0, 80.0]), 44.0);
+		let lost = position.estimate(&readings([80.0, 80.0, 80.0]), 44.0);
+		assert_eq!(lost, Position { offset: 2.0, confidence: 0.0 });
+
+		position.estimate(&readings([80.0, 80.0, 8.0]), 44.0);
+		let lost = position.estimate(&readings([80.0, 80.0, 80.0]), 44.0);
+		assert_eq!(lost, Position { offset: -2.0, confidence: 0.0 });
+	}
+
+	#[test]
+	fn a_line_never_seen_is_on_no_side() {
+		let mut position = LinePosition::default();
+		let lost = position.estimate(&readings([80.0, 80.0, 80.0]), 44.0);
+		assert_eq!(lost, Position { offset: 0.0, confidence: 0.0 });
+
+		// Also right after a reset, or with the line last seen right in the middle.
+		position.estimate(&readings([8.0, 80.0, 80.0]), 44.0);
+		position.reset();
+		assert_eq!(position.estimate(&readings([80.0, 80.0, 80.0]), 44.0).offset, 0.0);
+		position.estimate(&readings([80.0, 8.0, 80.0]), 44.0);
+		assert_eq!(position.estimate(&readings([80.0, 80.0, 80.0]), 44.0).offset, 0.0);
+	}
+}
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::Instant;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+
+// The targets we log to, e.g. `log::info!(target: CONTROL, ...)`.
+
+/// The line following and the drive loop.
+pub(crate) const CONTROL: &str = "control";
+/// The changes between the robot states.
+pub(crate) const STATE: &str = "state";
+/// The motors, sensors and buttons.
+pub(crate) const HARDWARE: &str = "hardware";
+/// The menu on the robot.
+pub(crate) const MENU: &str = "menu";
+/// Reading and writing the settings.
+pub(crate) const SETTINGS: &str = "settings";
+/// The run directories.
+pub(crate) const RUN: &str = "run";
+/// Everything that goes over the network.
+pub(crate) const NETWORK: &str = "network";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Level {
+	Off,
+	Error,
+	Warn,
+	#[default]
+	Info,
+	/// Every tick of a drive.
+	Debug,
+	Trace,
+}
+
+impl From<Level> for LevelFilter {
+	fn from(level: Level) -> Self {
+		match level {
+			Level::Off => LevelFilter::Off,
+			Level::Error => LevelFilter::Error,
+			Level::Warn => LevelFilter::Warn,
+			Level::Info => LevelFilter::Info,
+			Level::Debug => LevelFilter::Debug,
+			Level::Trace => LevelFilter::Trace,
+		}
+	}
+}
+
+/// Where the log lines go.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Sink {
+	/// Standard error, so it doesn't mix with what the commands print.
+	Console,
+	/// The end of [Logging::file].
+	File,
+	/// The last [Logging::memory] lines, in memory.
+	Memory,
+}
+
+/// How much we log, and where to.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Logging {
+	/// The level of every target without its own.
+	pub(crate) level: Level,
+	/// The levels of single targets, e.g. `control = "debug"`.
+	pub(crate) targets: BTreeMap<String, Level>,
+	pub(crate) sinks: Vec<Sink>,
+	/// The file of the `file` sink, relative to the settings file.
+	pub(crate) file: PathBuf,
+	/// How many lines the `memory` sink keeps.
+	pub(crate) memory: usize,
+}
+
+impl Default for Logging {
+	fn default() -> Self {
+		Self {
+			level: Level::Info,
+			targets: BTreeMap::new(),
+			sinks: vec![Sink::Console],
+			file: PathBuf::from("roborace.log"),
+			memory: 1000,
+		}
+	}
+}
+
+impl Logging {
+	/// The level of `target`. A level for `a::b` also counts for `a::b::c`.
+	fn level(&self, target: &str) -> Level {
+		self.targets.iter()
+			.filter(|(name, _)| target == *name || target.strip_prefix(name.as_str()).is_some_and(|x| x.starts_with("::")))
+			.max_by_key(|(name, _)| name.len())
+			.map_or(self.level, |(_, level)| *level)
+	}
+
+	/// The most we log for any target.
+	fn max_level(&self) -> Level {
+		self.targets.values().copied().chain([self.level]).max().unwrap_or_default()
+	}
+}
+
+struct Sinks {
+	console: bool,
+	file: Option<BufWriter<File>>,
+	/// The lines, with the time we logged them.
+	memory: VecDeque<(f64, String)>,
+	/// The size of `memory`, `0` without the `memory` sink.
+	memory_size: usize,
+	/// Gets every line too, while we keep it, see [capture].
+	capture: Option<BufWriter<File>>,
+}
+
+struct Logger {
+	logging: RwLock<Option<Logging>>,
+	sinks: Mutex<Sinks>,
+	start: OnceLock<Instant>,
+}
+
+static LOGGER: Logger = Logger {
+	logging: RwLock::new(None),
+	sinks: Mutex::new(Sinks {
+		console: true,
+		file: None,
+		memory: VecDeque::new(),
+		memory_size: 0,
+		capture: None,
+	}),
+	start: OnceLock::new(),
+};
+
+impl Log for Logger {
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		let Ok(logging) = self.logging.read() else { return false };
+		let level = logging.as_ref().map_or(Level::Info, |x| x.level(metadata.target()));
+		metadata.level() <= LevelFilter::from(level)
+	}
+
+	fn log(&self, record: &Record) {
+		if !self.enabled(record.metadata()) {
+			return;
+		}
+
+		let time = self.start.get_or_init(Instant::now).elapsed().as_secs_f64();
+		let line = format!("{:<5} {}: {}", record.level(), record.target(), record.args());
+		let Ok(mut sinks) = self.sinks.lock() else { return };
+		// We can't log that logging failed, so we drop those errors.
+		if sinks.console {
+			let _ = writeln!(std::io::stderr(), "{line}");
+		}
+		if let Some(file) = &mut sinks.file {
+			let _ = writeln!(file, "{time:>9.3}s {line}");
+		}
+		if let Some(capture) = &mut sinks.capture {
+			let _ = writeln!(capture, "{time:>9.3}s {line}");
+		}
+		if sinks.memory_size > 0 {
+			if sinks.memory.len() >= sinks.memory_size {
+				sinks.memory.pop_front();
+			}
+			sinks.memory.push_back((time, format!("{time:>9.3}s {line}")));
+		}
+	}
+
+	fn flush(&self) {
+		let Ok(mut sinks) = self.sinks.lock() else { return };
+		if let Some(file) = &mut sinks.file {
+			let _ = file.flush();
+		}
+		if let Some(capture) = &mut sinks.capture {
+			let _ = capture.flush();
+		}
+	}
+}
+
+/// Logs `info` and above to the console, until [configure].
+pub(crate) fn init() {
+	LOGGER.start.get_or_init(Instant::now);
+	// Only fails if it is already set.
+	if log::set_logger(&LOGGER).is_ok() {
+		log::set_max_level(LevelFilter::Info);
+	}
+}
+
+/// Logs as `logging` says, with the log file relative to `settings_path`.
+pub(crate) fn configure(logging: &Logging, settings_path: &Path) -> Result<()> {
+	init();
+
+	let file = if logging.sinks.contains(&Sink::File) {
+		let path = settings_path.parent().unwrap_or(Path::new(".")).join(&logging.file);
+		let file = OpenOptions::new().create(true).append(true).open(&path)
+			.with_context(|| format!("Failed to open the log file {path:?}"))?;
+		Some(BufWriter::new(file))
+	} else {
+		None
+	};
+
+	{
+		let mut sinks = LOGGER.sinks.lock().unwrap_or_else(|x| x.into_inner());
+		sinks.console = logging.sinks.contains(&Sink::Console);
+		if let Some(mut old) = std::mem::replace(&mut sinks.file, file) {
+			let _ = old.flush();
+		}
+		sinks.memory_size = if logging.sinks.contains(&Sink::Memory) { logging.memory } else { 0 };
+		while sinks.memory.len() > sinks.memory_size {
+			sinks.memory.pop_front();
+		}
+	}
+
+	log::set_max_level(logging.max_level().into());
+	*LOGGER.logging.write().unwrap_or_else(|x| x.into_inner()) = Some(logging.clone());
+	Ok(())
+}
+
+/// Writes every line into `path` too, until [end_capture].
+pub(crate) fn capture(path: &Path) -> Result<()> {
+	let file = File::create(path)
+		.with_context(|| format!("Failed to create {path:?}"))?;
+	let mut sinks = LOGGER.sinks.lock().unwrap_or_else(|x| x.into_inner());
+	sinks.capture = Some(BufWriter::new(file));
+	Ok(())
+}
+
+/// Stops [capture], and writes out the rest.
+pub(crate) fn end_capture() -> std::io::Result<()> {
+	let capture = LOGGER.sinks.lock().unwrap_or_else(|x| x.into_inner()).capture.take();
+	match capture {
+		Some(mut capture) => capture.flush(),
+		None => Ok(()),
+	}
+}
+
+/// The lines of the last `seconds` in the `memory` sink, the oldest first.
+pub(crate) fn recent(seconds: f64) -> Vec<String> {
+	let now = LOGGER.start.get_or_init(Instant::now).elapsed().as_secs_f64();
+	let sinks = LOGGER.sinks.lock().unwrap_or_else(|x| x.into_inner());
+	sinks.memory.iter()
+		.filter(|(time, _)| now - time <= seconds)
+		.map(|(_, line)| line.clone())
+		.collect()
+}
+
+/// Parses `TARGET=LEVEL` from the command line.
+pub(crate) fn parse_target(arg: &str) -> Result<(String, Level), String> {
+	let (target, level) = arg.split_once('=')
+		.ok_or_else(|| format!("expected TARGET=LEVEL, e.g. {CONTROL}=debug, but got {arg:?}"))?;
+	let level = Level::from_str(level, true)?;
+	Ok((target.to_owned(), level))
+}
@@ -1,32 +1,92 @@
-use anyhow::Result;
-use crate::robot::button::Button;
-use crate::robot::Robot;
+use crate::logging::MENU;
+use crate::robot::button::{Button, ButtonEvent};
 use crate::state::RobotState;
 
-pub(crate) fn select(bot: &Robot) -> Result<Option<RobotState>> {
-	let items = RobotState::ALL;
+/// The menu for selecting any robot state. It never waits for a press, but takes the button events
+/// of every tick, so the remote control still gets its answers meanwhile.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Menu {
+	cursor: usize,
+	/// We logged the items, since we came into the menu.
+	shown: bool,
+}
 
-	for (name, _) in items {
-		println!("- {}", name);
+impl Menu {
+	/// Moves the cursor with the clicks and long presses in `events`, and returns the state we
+	/// selected, if any.
+	pub(crate) fn update(&mut self, events: &[ButtonEvent]) -> Option<RobotState> {
+		let items = RobotState::ALL;
+
+		if !self.shown {
+			self.shown = true;
+			for (name, _) in items {
+				log::info!(target: MENU, "- {}", name);
+			}
+			self.log_selected();
+		}
+
+		for event in events {
+			let (ButtonEvent::Click(button) | ButtonEvent::LongPress(button)) = event else { continue };
+			self.cursor = match button {
+				Button::Enter => {
+					return Some(items[self.cursor].1.clone());
+				},
+				Button::Left => {
+					return Some(RobotState::Exit);
+				},
+				Button::Down if self.cursor + 1 >= items.len() => 0,
+				Button::Down => self.cursor + 1,
+				Button::Up if self.cursor == 0 => items.len() - 1,
+				Button::Up => self.cursor - 1,
+				Button::Right => self.cursor,
+			};
+			self.log_selected();
+		}
+
+		None
+	}
+
+	fn log_selected(&self) {
+		log::info!(target: MENU, "selected: {:?}", RobotState::ALL.get(self.cursor).map(|x| x.0).unwrap_or(""));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn click(button: Button) -> ButtonEvent {
+		ButtonEvent::Click(button)
+	}
+
+	#[test]
+	fn nothing_without_a_press() {
+		let mut menu = Menu::default();
+		assert_eq!(menu.update(&[]), None);
+		assert_eq!(menu.update(&[ButtonEvent::Press(Button::Enter), ButtonEvent::Release(Button::Enter)]), None);
+	}
+
+	#[test]
+	fn enter_selects_the_item_at_the_cursor() {
+		let mut menu = Menu::default();
+		assert_eq!(menu.update(&[click(Button::Down), click(Button::Down)]), None);
+		assert_eq!(menu.update(&[click(Button::Enter)]), Some(RobotState::ALL[2].1.clone()));
+	}
+
+	#[test]
+	fn the_cursor_wraps_around() {
+		let mut menu = Menu::default();
+		assert_eq!(menu.update(&[click(Button::Up), click(Button::Enter)]), Some(RobotState::ALL.last().unwrap().1.clone()));
+
+		let mut menu = Menu::default();
+		let mut events = vec![click(Button::Down); RobotState::ALL.len()];
+		events.push(ButtonEvent::LongPress(Button::Enter));
+		assert_eq!(menu.update(&events), Some(RobotState::ALL[0].1.clone()));
 	}
 
-	let mut cursor = 0;
-
-	loop {
-		println!("selected: {:?}", items.get(cursor).map(|x| x.0).unwrap_or(""));
-
-		cursor = match bot.buttons.await_press() {
-			Button::Enter => {
-				return Ok(Some(items[cursor].1.clone()));
-			},
-			Button::Left => {
-				return Ok(Some(RobotState::Exit));
-			},
-			Button::Down if cursor + 1 >= items.len() => 0,
-			Button::Down => cursor + 1,
-			Button::Up if cursor == 0 => items.len() - 1,
-			Button::Up => cursor - 1,
-			_ => cursor,
-		};
+	#[test]
+	fn left_exits() {
+		let mut menu = Menu::default();
+		assert_eq!(menu.update(&[click(Button::Down), click(Button::Left), click(Button::Enter)]), Some(RobotState::Exit));
 	}
-}
\ No newline at end of file
+}
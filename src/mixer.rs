@@ -0,0 +1,113 @@
+/// The fastest any motor can go, in percent, whatever `max_speed` of `[hardware]` says.
+pub(crate) const MAX_WHEEL_SPEED: f64 = 100.0;
+
+/// Which limit we ran into while mixing.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Saturation {
+	/// We wanted the faster wheel to go faster than the wheels can.
+	pub(crate) speed: bool,
+	/// We wanted more differential than the speed allows, so we steer less than the PID wants.
+	pub(crate) steering: bool,
+}
+
+impl Saturation {
+	pub(crate) fn any(&self) -> bool {
+		self.speed || self.steering
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Wheels {
+	pub(crate) left: f64,
+	pub(crate) right: f64,
+	/// The forward speed that was left after steering.
+	pub(crate) forward: f64,
+	pub(crate) saturation: Saturation,
+}
+
+/// Mixes the speed of the faster wheel and the steering differential into the two wheel speeds.
+///
+/// The faster wheel runs at `speed`, and the slower wheel at `speed - 2 * |differential|`, so we
+/// never ask a motor for more than `speed`, or more than `max_speed`, the slower of the two
+/// wheels' maximum. The motors don't have to clamp anything afterwards. The differential always
+/// comes first, if it's too large we lose forward speed and not steering.
+pub(crate) fn mix(speed: f64, differential: f64, max_speed: f64) -> Wheels {
+	let max_speed = max_speed.clamp(0.0, MAX_WHEEL_SPEED);
+	// When driving backwards, the faster wheel is the one with the larger negative speed.
+	let limit = speed.abs();
+	let saturation_speed = limit > max_speed;
+	let limit = limit.min(max_speed);
+
+	let saturation_steering = differential.abs() > limit;
+	let differential = differential.clamp(-limit, limit);
+
+	let forward = speed.signum() * (limit - differential.abs());
+
+	Wheels {
+		left: forward + differential,
+		right: forward - differential,
+		forward,
+		saturation: Saturation {
+			speed: saturation_speed,
+			steering: saturation_steering,
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn straight() {
+		let wheels = mix(50.0, 0.0, 100.0);
+		assert_eq!((wheels.left, wheels.right, wheels.forward), (50.0, 50.0, 50.0));
+		assert!(!wheels.saturation.any());
+	}
+
+	#[test]
+	fn steering_keeps_the_faster_wheel_at_speed() {
+		let wheels = mix(50.0, 10.0, 100.0);
+		assert_eq!((wheels.left, wheels.right), (50.0, 30.0));
+		let wheels = mix(50.0, -10.0, 100.0);
+		assert_eq!((wheels.left, wheels.right), (30.0, 50.0));
+	}
+
+	#[test]
+	fn too_much_steering_costs_speed() {
+		let wheels = mix(50.0, 80.0, 100.0);
+		assert_eq!((wheels.left, wheels.right, wheels.forward), (50.0, -50.0, 0.0));
+		assert!(wheels.saturation.steering);
+		assert!(!wheels.saturation.speed);
+	}
+
+	#[test]
+	fn zero_speed_doesnt_steer() {
+		let wheels = mix(0.0, 10.0, 100.0);
+		assert_eq!((wheels.left, wheels.right), (0.0, 0.0));
+		assert!(wheels.saturation.steering);
+	}
+
+	#[test]
+	fn backwards() {
+		let wheels = mix(-50.0, 10.0, 100.0);
+		assert_eq!((wheels.left, wheels.right), (-30.0, -50.0));
+	}
+
+	#[test]
+	fn the_configured_maximum_keeps_the_steering() {
+		let wheels = mix(90.0, 10.0, 60.0);
+		assert_eq!((wheels.left, wheels.right), (60.0, 40.0));
+		assert!(wheels.saturation.speed);
+		assert!(!wheels.saturation.steering);
+	}
+
+	#[test]
+	fn out_of_range_maximum() {
+		let wheels = mix(150.0, 0.0, 200.0);
+		assert_eq!((wheels.left, wheels.right), (MAX_WHEEL_SPEED, MAX_WHEEL_SPEED));
+		assert!(wheels.saturation.speed);
+		let wheels = mix(50.0, 10.0, -10.0);
+		assert_eq!((wheels.left, wheels.right), (0.0, 0.0));
+	}
+}
@@ -1,44 +1,155 @@
-use anyhow::{bail, Context, Result};
+use anyhow::{ensure, Context, Result};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
-use crate::menu;
+use crate::autotune::{Autotune, Gains, Relay, Rule};
+use crate::color::{self, ColorSettings, Marker, MarkerAction, Markers};
+use crate::curvature::Curvature;
+use crate::filter::{Debounce, DistanceFilter, Estimate};
+use crate::feedback::{Event, Feedback};
+use crate::follow::{Follow, FollowMode};
+use crate::hardware::Hardware;
+use crate::io::Source;
+use crate::keyboard;
+use crate::{io, mixer};
+use crate::line::{LinePosition, LineSensor, Position};
+use crate::menu::Menu;
+use crate::logging::{Logging, CONTROL, HARDWARE, MENU, RUN, SETTINGS, STATE};
 use crate::pid::Pid;
+use crate::ramp::{Ramp, RampMode};
+use crate::recovery::{Recovery, Step};
+use crate::recorder::{self, Recorder};
+use crate::remote::{self, Remote, Request, Response};
+use crate::runs::{Run, Runs};
+use crate::schedule::{self, GainPoint};
+use crate::robot::button::{Button, ButtonEvent, Input};
 use crate::robot::Robot;
 use crate::state::RobotState;
+use crate::states::{Blend, IntegralPolicy, Setpoint, States};
+use crate::stream::Stream;
+use crate::teleop::Teleop;
+use crate::telemetry::{Telemetry, TickRecord};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct Program {
-	log: bool,
+	#[serde(default)]
+	log: Logging,
+
+	#[serde(default)]
+	hardware: Hardware,
+
+	/// Where we read the settings from, and write new gains to.
+	#[serde(skip)]
+	pub(crate) settings_path: PathBuf,
+	/// Where every setting came from, by its path like `line.k_p`, so we write it back there.
+	/// Arrays count as one value.
+	#[serde(skip)]
+	pub(crate) sources: BTreeMap<String, Source>,
 
 	robot_wheel_width: f64,
-	diameter: f64,
+	pub(crate) diameter: f64,
+	#[serde(default)]
+	curvature: Curvature,
 
 	rotate_arm: bool,
 	rotate_arm_speed: f64,
 
 	line: Pid,
+	#[serde(default, deserialize_with = "color::checked")]
+	color: ColorSettings,
+	#[serde(default)]
+	markers: Vec<Marker>,
+	#[serde(default)]
+	line_sensors: Vec<LineSensor>,
+	#[serde(default)]
+	line_position: LinePosition,
+	#[serde(default)]
+	line_schedule: Vec<GainPoint>,
+	#[serde(default)]
+	autotune: Autotune,
 	low_ref_warn: f64,
-	speed: f64,
+	pub(crate) speed: f64,
 	speed_pid_turn_off: f64,
 
 	distance: Pid,
 	distance_trigger: f64,
 	stop_distance: f64,
 	speed_correction_max: f64,
+	#[serde(default)]
+	follow: Follow,
+
+	#[serde(default)]
+	distance_filter: DistanceFilter,
+	#[serde(default = "Program::default_min_confidence")]
+	min_confidence: f64,
+	#[serde(default = "Program::default_transition_debounce")]
+	transition_debounce: f64,
+
+	#[serde(default)]
+	states: States,
+	#[serde(default)]
+	ramp: Ramp,
+	#[serde(default)]
+	recovery: Recovery,
+	#[serde(default)]
+	runs: Runs,
+	#[serde(default)]
+	recorder: Recorder,
+	#[serde(default)]
+	stream: Stream,
+	#[serde(default)]
+	remote: Remote,
+	#[serde(default)]
+	teleop: Teleop,
+	#[serde(default)]
+	feedback: Feedback,
 
 	#[serde(skip)]
 	state: RobotState,
 	#[serde(skip)]
+	menu: Menu,
+	/// The left button stopped the drive as it went down, and whether it came up again since.
+	#[serde(skip)]
+	left_stop: Option<bool>,
+	#[serde(skip)]
+	blend: Blend,
+	#[serde(skip)]
+	seen_markers: Markers,
+	#[serde(skip)]
+	transition: Debounce,
+	#[serde(skip)]
+	telemetry: Telemetry,
+	#[serde(skip)]
 	top_arm_throttle: Option<usize>,
+	#[serde(skip)]
+	top_arm_running: bool,
+	// We reached the end of the exit, and brake along the ramp.
+	#[serde(skip)]
+	stopping: bool,
+	// Only the real drives get a run directory, and not the ones while tuning.
+	#[serde(skip)]
+	record_runs: bool,
+	#[serde(skip)]
+	run: Option<Run>,
+	/// The settings the remote control changed, by their path.
+	#[serde(skip)]
+	changed: BTreeMap<String, toml::Value>,
 }
 
 impl Default for Program {
 	fn default() -> Self {
 		Self {
-			log: true,
+			log: Logging::default(),
+
+			hardware: Hardware::default(),
+
+			settings_path: PathBuf::from(io::SETTINGS_FILE),
+			sources: BTreeMap::new(),
 
 			robot_wheel_width: 14.0,
 			diameter: 100.0,
+			curvature: Curvature::default(),
 
 			rotate_arm: true,
 			rotate_arm_speed: 100.0,
@@ -50,6 +161,12 @@ impl Default for Program {
 				k_d: 0.5,
 				last_error: 0f64, integral: 0f64,
 			},
+			color: ColorSettings::default(),
+			markers: Vec::new(),
+			line_sensors: Vec::new(),
+			line_position: LinePosition::default(),
+			line_schedule: Vec::new(),
+			autotune: Autotune::default(),
 			low_ref_warn: 17.0,
 			speed: 50.0,
 			speed_pid_turn_off: 10.0,
@@ -64,16 +181,51 @@ impl Default for Program {
 			distance_trigger: 40.0,
 			stop_distance: 20.0,
 			speed_correction_max: 0.1,
+			follow: Follow::default(),
+
+			distance_filter: DistanceFilter::default(),
+			min_confidence: Program::default_min_confidence(),
+			transition_debounce: Program::default_transition_debounce(),
+
+			states: States::default(),
+			ramp: Ramp::default(),
+			recovery: Recovery::default(),
+			runs: Runs::default(),
+			recorder: Recorder::default(),
+			stream: Stream::default(),
+			remote: Remote::default(),
+			teleop: Teleop::default(),
+			feedback: Feedback::default(),
 
 			state: RobotState::default(),
+			menu: Menu::default(),
+			left_stop: None,
+			blend: Blend::default(),
+			seen_markers: Markers::default(),
+			transition: Debounce::default(),
+			telemetry: Telemetry::default(),
 			top_arm_throttle: None,
+			top_arm_running: false,
+			stopping: false,
+			record_runs: false,
+			run: None,
+			changed: BTreeMap::new(),
 		}
 	}
 }
 
 impl Program {
+	// Older settings files don't have these yet.
+	fn default_min_confidence() -> f64 {
+		0.5
+	}
+
+	fn default_transition_debounce() -> f64 {
+		0.03
+	}
+
 	fn test(&self, bot: &Robot) -> Result<()> {
-		dbg!(&bot);
+		log::debug!(target: HARDWARE, "{bot:#?}");
 
 		bot.top_arm.start_with_full_power()?;
 		std::thread::sleep(Self::TICK_TIME * Self::SMALL_MOTOR_WARM_UP as u32);
@@ -94,7 +246,7 @@ impl Program {
 
 			let reflection = bot.color.get_color()?;
 			let distance = bot.distance.get_distance()?.unwrap_or(f64::NAN);
-			println!("ref: {reflection:>5.1} -- dst: {distance:>5.1}");
+			log::info!(target: HARDWARE, "ref: {reflection:>5.1} -- dst: {distance:>5.1}");
 
 			std::thread::sleep(Duration::from_millis(500));
 		}
@@ -102,18 +254,156 @@ impl Program {
 		Ok(())
 	}
 
-	fn prepare_drive(&mut self, bot: &Robot) -> Result<()> {
+	fn autotune(&mut self, bot: &Robot) -> Result<()> {
+		let dt = Self::TICK_TIME.as_secs_f64();
+		let direction = if self.line.k_p > 0.0 { 1.0 } else { -1.0 };
+		let mut relay = Relay::new(&self.autotune, direction);
+		let max_ticks = (self.autotune.timeout / dt) as usize;
+
+		bot.left.start()?;
+		bot.right.start()?;
+		self.feedback.play(Event::Ready);
+
+		for _ in 0..max_ticks {
+			let start = Instant::now();
+
+			// Like a drive, we stop as soon as left goes down, and the rest of that press doesn't count.
+			if bot.buttons.events(true).contains(&ButtonEvent::Press(Button::Left)) {
+				bot.left.stop()?;
+				bot.right.stop()?;
+				self.left_stop = Some(false);
+				log::info!(target: CONTROL, "autotune: stopped with the left button");
+				return Ok(());
+			}
+			if relay.is_done() || bot.touch.is_pressed()? {
+				break;
+			}
+
+			let error = bot.color.get_color()? - self.line.center;
+			let differential = relay.update(error);
+			let wheels = mixer::mix(self.autotune.speed, self.autotune.speed * differential, self.hardware.max_wheel_speed());
+			bot.left.set_speed(wheels.left)?;
+			bot.right.set_speed(wheels.right)?;
+
+			if let Some(dur) = Self::TICK_TIME.checked_sub(start.elapsed()) {
+				std::thread::sleep(dur)
+			}
+		}
+
+		bot.left.stop()?;
+		bot.right.stop()?;
+
+		let ultimate = match relay.result(dt) {
+			Ok(ultimate) => ultimate,
+			Err(err) => {
+				log::warn!(target: CONTROL, "autotune: no result: {err:#}");
+				self.feedback.play(Event::GaveUp);
+				return Ok(());
+			},
+		};
+		self.feedback.play(Event::Calibrated);
+
+		log::info!(target: CONTROL, "autotune: ultimate gain {:.3}, period {:.3}s, amplitude {:.1}",
+			ultimate.gain, ultimate.period, ultimate.amplitude
+		);
+		for &rule in Rule::ALL {
+			let gains = ultimate.gains(rule, direction, dt);
+			log::info!(target: CONTROL, "{rule:?}: k_p = {:.3}, k_i = {:.4}, k_d = {:.2}", gains.k_p, gains.k_i, gains.k_d);
+		}
+		log::info!(target: CONTROL, "Up: write ZieglerNichols, Down: write TyreusLuyben, any other button: discard");
+
+		let rule = match bot.buttons.await_press() {
+			Button::Up => Rule::ZieglerNichols,
+			Button::Down => Rule::TyreusLuyben,
+			_ => return Ok(()),
+		};
+
+		let gains = ultimate.gains(rule, direction, dt);
+		self.line.k_p = gains.k_p;
+		self.line.k_i = gains.k_i;
+		self.line.k_d = gains.k_d;
+		io::write_line_gains(&self.settings_path, &self.sources, &gains)
+			.context("Failed to write the line gains")?;
+		log::info!(target: CONTROL, "autotune: wrote {rule:?} gains to the settings file");
+
+		Ok(())
+	}
+
+	fn prepare_drive(&mut self, bot: &Robot, state: &RobotState) -> Result<()> {
 		// We set the last error of the line PID in order to remove a bump in the very first tick.
-		self.line.last_error = bot.color.get_color()? - self.line.center;
+		self.line_position.reset();
+		let reflection = bot.color.get_color()?;
+		self.line.last_error = self.line_error(bot, reflection)?.0;
 		self.distance.last_error = 0.0;
+		self.distance_filter.reset();
+		self.follow.reset();
+		self.transition.reset();
+		self.telemetry.reset();
+		self.curvature.reset();
+		self.blend.reset();
+		self.ramp.reset();
+		self.recovery.reset();
+		self.seen_markers.reset();
+		self.stopping = false;
 
+		let speed = self.states.get(state).and_then(|x| x.speed).unwrap_or(self.speed);
+		let speed = self.ramp.update(speed, Self::TICK_TIME.as_secs_f64());
+
+		bot.left.set_ramp(self.ramp.ev3_ramp())?;
 		bot.left.start()?;
-		bot.left.set_speed(self.speed)?;
+		bot.left.set_speed(speed)?;
 
+		bot.right.set_ramp(self.ramp.ev3_ramp())?;
 		bot.right.start()?;
-		bot.right.set_speed(self.speed)?;
+		bot.right.set_speed(speed)?;
+
+		self.update_top_arm(bot, state)?;
 
-		bot.beep()?;
+		self.feedback.play(Event::Ready);
+
+		Ok(())
+	}
+
+	/// Ends the drive, after braking along the ramp if we have one.
+	fn stop_drive(&mut self) -> Result<()> {
+		// With the software ramp we keep following the line until we stand still.
+		if self.ramp.mode == RampMode::Software {
+			self.stopping = true;
+		} else {
+			self.state = RobotState::Exit;
+		}
+		self.feedback.play(Event::Stop);
+		self.log_summary();
+		Ok(())
+	}
+
+	/// Switches between the drive states, and applies the settings of the new one.
+	fn enter_drive_state(&mut self, bot: &Robot, state: RobotState) -> Result<()> {
+		self.blend.transition();
+		if self.states.get(&state).is_some_and(|x| x.integral == IntegralPolicy::Reset) {
+			self.line.integral = 0.0;
+		}
+		self.update_top_arm(bot, &state)?;
+
+		self.state = state;
+		Ok(())
+	}
+
+	/// Starts or stops the top arm, as the drive `state` wants it.
+	fn update_top_arm(&mut self, bot: &Robot, state: &RobotState) -> Result<()> {
+		// By default we only rotate the arm while following.
+		let rotate = self.rotate_arm && self.states.get(state)
+			.and_then(|x| x.rotate_arm)
+			.unwrap_or(*state == RobotState::DriveFollow);
+
+		if rotate && !self.top_arm_running {
+			bot.top_arm.start_with_full_power()?;
+			self.top_arm_throttle = Some(Self::SMALL_MOTOR_WARM_UP);
+		} else if !rotate && self.top_arm_running {
+			bot.top_arm.stop()?;
+			self.top_arm_throttle = None;
+		}
+		self.top_arm_running = rotate;
 
 		Ok(())
 	}
@@ -121,92 +411,396 @@ impl Program {
 	// We need 100ms, i.e. 10 ticks, to start up the small motor.
 	const SMALL_MOTOR_WARM_UP: usize = 10;
 
+	pub(crate) fn logging(&self) -> &Logging {
+		&self.log
+	}
+
+	pub(crate) fn recorder(&self) -> &Recorder {
+		&self.recorder
+	}
+
+	/// Streams the telemetry, if `[stream]` says so.
+	pub(crate) fn start_stream(&mut self) -> Result<()> {
+		self.stream.start()
+	}
+
+	/// Plays the cues of `[feedback]`, through the speaker and the LEDs of `bot`, or only into the
+	/// log when it is simulated.
+	pub(crate) fn start_feedback(&mut self, bot: &Robot) -> Result<()> {
+		self.feedback.start(bot.is_simulated())
+	}
+
+	/// Listens for the remote control, if `[remote]` says so.
+	pub(crate) fn start_remote(&mut self) -> Result<()> {
+		self.remote.start()
+	}
+
+	/// Whether the remote control listens, which [Program::start_remote] does if it is enabled.
+	pub(crate) fn remote_running(&self) -> bool {
+		self.remote.is_running()
+	}
+
+	/// Answers all waiting requests of the remote control.
+	fn serve_remote(&mut self, bot: &Robot) {
+		while let Some((request, answer)) = self.remote.next() {
+			let response = Response::from(self.answer(bot, request));
+			// The client might be gone already.
+			let _ = answer.send(response);
+		}
+	}
+
+	fn answer(&mut self, bot: &Robot, request: Request) -> Result<serde_json::Value> {
+		match request {
+			Request::Get { path } => {
+				let settings = toml::Value::try_from(&*self)
+					.context("Failed to serialize the settings")?;
+				let value = match &path {
+					Some(path) => remote::get(&settings, path)?,
+					None => &settings,
+				};
+				Ok(serde_json::to_value(value)?)
+			},
+			Request::Set { path, value } => {
+				let value = toml::Value::try_from(&value)
+					.with_context(|| format!("{value} can't be a setting"))?;
+				let mut settings = toml::Value::try_from(&*self)
+					.context("Failed to serialize the settings")?;
+				remote::set(&mut settings, &path, value.clone())?;
+				// Only this fails if the value doesn't fit, and it leaves everything as it was.
+				Program::deserialize(settings.clone())
+					.with_context(|| format!("Failed to set {path:?} to {value}"))?;
+				// In place, so we keep everything that isn't a setting, like the integral of the PIDs.
+				Program::deserialize_in_place(settings, self)
+					.with_context(|| format!("Failed to set {path:?} to {value}"))?;
+
+				// As the setting has it, e.g. `30.0` for `30`.
+				let settings = toml::Value::try_from(&*self)
+					.context("Failed to serialize the settings")?;
+				let value = remote::get(&settings, &path)?.clone();
+				log::info!(target: SETTINGS, "{path} = {value}, from the remote control");
+				let json = serde_json::to_value(&value)?;
+				self.changed.insert(path, value);
+				Ok(json)
+			},
+			Request::State { state } => {
+				if let Some(name) = state {
+					let state = RobotState::from_name(&name).with_context(|| format!(
+						"No state {name:?}, there are only {:?}",
+						RobotState::ALL.iter().map(|(x, _)| x).collect::<Vec<_>>(),
+					))?;
+					self.next_state(bot, state)?;
+				}
+				Ok(format!("{:?}", self.state).into())
+			},
+			Request::Start => {
+				ensure!(!self.state.is_drive(), "Already driving in {:?}", self.state);
+				self.next_state(bot, RobotState::DriveEntry)?;
+				Ok(format!("{:?}", self.state).into())
+			},
+			Request::Stop => {
+				self.next_state(bot, RobotState::Idle)?;
+				Ok(format!("{:?}", self.state).into())
+			},
+			Request::Teleop(motors) => {
+				ensure!(self.state == RobotState::Teleop, "Only in the teleop state, not in {:?}", self.state);
+				self.teleop.set(motors);
+				Ok(serde_json::Value::Null)
+			},
+			Request::Save => {
+				log::info!(target: SETTINGS, "saving {:?}, from the remote control", self.changed.keys().collect::<Vec<_>>());
+				io::write_values(&self.settings_path, &self.sources, &self.changed)?;
+				Ok(self.changed.keys().cloned().collect())
+			},
+		}
+	}
+
+	/// Keep a run directory for every drive, see [Runs].
+	pub(crate) fn set_record_runs(&mut self, record_runs: bool) {
+		self.record_runs = record_runs;
+	}
+
+	pub(crate) fn runs(&self) -> &Runs {
+		&self.runs
+	}
+
+	/// Logs every state change since `previous`. Starts a run when we start driving, notes every
+	/// state change in it, and ends it when we stop driving.
+	pub(crate) fn follow_state(&mut self, tick: usize, previous: &RobotState) {
+		if *previous != self.state {
+			log::info!(target: STATE, "{previous:?} -> {:?} at tick {tick}", self.state);
+			self.feedback.enter(&self.state);
+		}
+
+		if self.state.is_run() && self.run.is_none() && self.record_runs && self.runs.enabled {
+			let run = toml::to_string_pretty(&*self)
+				.context("Failed to serialize the settings")
+				.and_then(|settings| self.runs.start(&self.settings_path, &settings, tick, &self.state));
+			match run {
+				Ok(run) => self.run = Some(run),
+				// We still drive without it.
+				Err(err) => log::warn!(target: RUN, "Failed to start a run: {err:#}"),
+			}
+			return;
+		}
+
+		if *previous != self.state {
+			if let Some(run) = &mut self.run {
+				run.transition(tick, previous, &self.state);
+			}
+			if !self.state.is_run() {
+				self.finish_run(None);
+			}
+		}
+	}
+
+	/// Dumps the flight recorder and ends the run, because we failed with `error`.
+	pub(crate) fn fail(&mut self, error: &anyhow::Error) {
+		self.feedback.play(Event::Fault);
+		recorder::dump(&format!("error: {error:#}"));
+		self.finish_run(Some(error));
+	}
+
+	/// Dumps the flight recorder when we stop a drive by hand.
+	fn emergency_stop(&self, reason: &str) {
+		if self.state.is_run() {
+			recorder::dump(reason);
+		}
+	}
+
+	/// Ends the run if we have one, with the `error` we failed with.
+	pub(crate) fn finish_run(&mut self, error: Option<&anyhow::Error>) {
+		if let Some(run) = self.run.take() {
+			run.finish(error);
+		}
+	}
+
+	pub(crate) fn hardware(&self) -> &Hardware {
+		&self.hardware
+	}
+
+	pub(crate) fn set_buttons(&mut self, buttons: Input) {
+		self.hardware.buttons = buttons;
+	}
+
+	pub(crate) fn line_sensors(&self) -> &[LineSensor] {
+		&self.line_sensors
+	}
+
+	/// Puts all color sensors into the mode from the settings.
+	pub(crate) fn configure_sensors(&self, bot: &Robot) -> Result<()> {
+		for sensor in std::iter::once(&bot.color).chain(&bot.line_sensors) {
+			sensor.configure(self.color)?;
+		}
+		Ok(())
+	}
+
+	/// The error for the line PID, and where we are relative to the line.
+	///
+	/// Without line sensors we follow the edge with the `reflection` of the color sensor,
+	/// otherwise we look at all of them.
+	fn line_error(&mut self, bot: &Robot, reflection: f64) -> Result<(f64, Position)> {
+		let readings = if bot.line_sensors.is_empty() {
+			vec![(0.0, reflection)]
+		} else {
+			self.line_sensors.iter().zip(&bot.line_sensors)
+				.map(|(settings, sensor)| Ok((settings.offset, sensor.get_color()?)))
+				.collect::<Result<Vec<_>>>()?
+		};
+
+		let position = self.line_position.estimate(&readings, self.line.center);
+		Ok((position.offset * self.line_position.scale, position))
+	}
+
+	pub(crate) fn state(&self) -> &RobotState {
+		&self.state
+	}
+
+	/// The distance to the leader we want while following at `speed` in `cm/s`.
+	pub(crate) fn follow_target(&self, speed: f64) -> f64 {
+		match self.follow.mode {
+			FollowMode::Pid => self.distance.center,
+			FollowMode::TimeGap => self.follow.gap(speed),
+		}
+	}
+
+	pub(crate) fn follow_mode(&self) -> FollowMode {
+		self.follow.mode
+	}
+
+	/// The distance at which we stop in front of anything.
+	pub(crate) fn stop_distance(&self) -> f64 {
+		self.stop_distance
+	}
+
+	/// The speed we follow at without the leader, the one of the follow state if it has one.
+	pub(crate) fn follow_speed(&self) -> f64 {
+		self.states.follow.speed.unwrap_or(self.speed)
+	}
+
+	/// The relative change of `speed`, the one we drive at without the leader, we need to keep the
+	/// distance to the leader.
+	pub(crate) fn follow_correction(&mut self, estimate: Option<Estimate>, speed: f64, dt: f64) -> f64 {
+		// Only with a sufficiently low distance we regulate the distance.
+		let estimate = estimate.filter(|x| x.distance < self.distance_trigger);
+
+		let speed_correction = match self.follow.mode {
+			FollowMode::Pid => estimate.map_or(0.0, |x| {
+				self.distance.update(x.distance) / 100.0
+			}),
+			FollowMode::TimeGap => self.follow.update(estimate, speed, self.speed_correction_max, dt),
+		};
+
+		// If our distance k_p is too large we can get a very large `speed_correction` value,
+		// and that makes the motors spin above our maximum speed again. Therefore we introduce
+		// a maximum speed correction value.
+		speed_correction.min(self.speed_correction_max)
+	}
+
+	/// Feeds a distance reading into the filter, and returns the estimate if we trust it.
+	pub(crate) fn filter_distance(&mut self, reading: Option<f64>, dt: f64) -> Option<Estimate> {
+		// A single bad echo must not be able to switch the state, so we only look at the filtered
+		// distance, and only once we trust it enough.
+		self.distance_filter.update(reading, dt)
+			.filter(|x| x.confidence >= self.min_confidence)
+	}
+
 	fn drive(&mut self, bot: &Robot, tick_counter: usize) -> Result<()> {
-		let distance = bot.distance.get_distance()?;
+		let dt = Self::TICK_TIME.as_secs_f64();
+		let raw_distance = bot.distance.get_distance()?;
 
-		if let Some(distance) = distance {
+		let estimate = self.filter_distance(raw_distance, dt);
+		let distance = estimate.map(|x| x.distance);
+
+		let condition = distance.is_some_and(|distance| match self.state {
+			RobotState::DriveExit => distance < self.stop_distance,
+			RobotState::DriveFollow => distance > self.distance_trigger,
+			RobotState::DriveEntry => distance < self.distance.center,
+			_ => false,
+		});
+		let debounce_ticks = (self.transition_debounce / dt).ceil() as usize;
+
+		if !self.stopping && self.transition.update(condition, debounce_ticks) {
+			self.transition.reset();
 			match self.state {
-				RobotState::DriveExit if distance < self.stop_distance => {
-					self.state = RobotState::Exit;
-					bot.beep()?;
-					println!("stopping because dst was: {distance:?}, which is less than {:?}",
+				RobotState::DriveExit => {
+					log::info!(target: CONTROL, "stopping because dst was: {distance:?}, which is less than {:?}",
 						self.stop_distance
 					);
+					self.stop_drive()?;
 				},
-				RobotState::DriveFollow if distance > self.distance_trigger => {
-					self.state = RobotState::DriveExit;
-					bot.beep()?;
-					bot.top_arm.stop()?;
+				RobotState::DriveFollow => {
+					self.enter_drive_state(bot, RobotState::DriveExit)?;
 				},
-				RobotState::DriveEntry if distance < self.distance.center => {
-					self.state = RobotState::DriveFollow;
-					bot.beep()?;
-					if self.rotate_arm {
-						bot.top_arm.start_with_full_power()?;
-						self.top_arm_throttle = Some(tick_counter + Self::SMALL_MOTOR_WARM_UP);
-					}
+				RobotState::DriveEntry => {
+					self.enter_drive_state(bot, RobotState::DriveFollow)?;
 				},
 				_ => {},
 			}
 		}
 
 		// When we have the throttle of the small motor scheduled, throttle it.
-		if self.top_arm_throttle.is_some_and(|x| x < tick_counter) {
-			self.top_arm_throttle = None;
-			bot.top_arm.set_speed(self.rotate_arm_speed)?;
+		match self.top_arm_throttle {
+			Some(0) => {
+				self.top_arm_throttle = None;
+				bot.top_arm.set_speed(self.rotate_arm_speed)?;
+			},
+			Some(ticks) => self.top_arm_throttle = Some(ticks - 1),
+			None => {},
 		}
 
-		// Only with a sufficiently low distance and the correct driving state,
-		// we regulate the distance.
-		let speed_correction = distance
-			.filter(|&x| x < self.distance_trigger && self.state == RobotState::DriveFollow)
-			.map_or(0.0, |x| {
-				self.distance.update(x) / 100.0
-			});
+		let settings = self.states.get(&self.state).cloned().unwrap_or_default();
+		let target_speed = settings.speed.unwrap_or(self.speed);
 
-		// If our distance k_p is too large we can get a very large `speed_correction` value,
-		// and that makes the motors spin above our maximum speed again. Therefore we introduce
-		// a maximum speed correction value.
-		let clamped_speed_correction = if speed_correction > self.speed_correction_max {
-			self.speed_correction_max
+		let speed_correction = if self.state == RobotState::DriveFollow {
+			let speed = self.blend.speed(target_speed, self.states.blend, dt);
+			self.follow_correction(estimate, speed, dt)
 		} else {
-			speed_correction
+			0.0
 		};
 
+		// With a gain schedule the gains follow the speed we drive at, and the curvature of the
+		// circle, instead of being the ones from `line`. The state can override both.
+		let curvature = self.curvature.curvature(self.diameter);
+		let base = schedule::gains(&self.line_schedule, target_speed * (1.0 + speed_correction), curvature)
+			.unwrap_or(Gains { k_p: self.line.k_p, k_i: self.line.k_i, k_d: self.line.k_d });
+		let target = Setpoint { speed: target_speed, gains: settings.gains(base) };
+
+		// After a state change we move to the new values over `states.blend` seconds.
+		let Setpoint { speed, gains } = self.blend.update(target, self.states.blend, dt);
+		// After the end of the exit we brake, and the ramp makes that smooth.
+		let speed = if self.stopping { 0.0 } else { speed * (1.0 + speed_correction) };
+		let speed = self.ramp.update(speed, dt);
+
 		let reflection = bot.color.get_color()?;
+		if let Some(rgb) = bot.color.get_rgb().filter(|_| !self.stopping) {
+			if let Some(i) = self.seen_markers.update(&self.markers, rgb, dt) {
+				log::info!(target: CONTROL, "marker {:?} at tick {tick_counter} with {rgb:.0?}", self.markers[i].name);
+				match self.markers[i].action {
+					MarkerAction::Log => {},
+					MarkerAction::Exit => if matches!(self.state, RobotState::DriveEntry | RobotState::DriveFollow) {
+						self.enter_drive_state(bot, RobotState::DriveExit)?;
+					},
+					MarkerAction::Stop => self.stop_drive()?,
+				}
+			}
+		}
+
+		let (error, position) = self.line_error(bot, reflection)?;
+		if self.recovery.enabled && self.recovery.is_lost(reflection, error, dt) {
+			log::info!(target: CONTROL, "lost the line at tick {tick_counter} in {:?}, searching it", self.state);
+			self.recovery.start(gains.k_p, self.state.clone());
+			self.state = RobotState::DriveRecover;
+			self.blend.transition();
+			self.feedback.play(Event::LineLost);
+			return Ok(());
+		}
+
 		let line_correction = {
-			let error = reflection - self.line.center;
 			let last_error = std::mem::replace(&mut self.line.last_error, error);
-			if self.speed * (1.0 + clamped_speed_correction) > self.speed_pid_turn_off {
+			if speed > self.speed_pid_turn_off && settings.integral != IntegralPolicy::Freeze {
 				self.line.integral += error;
 			}
-			(self.line.k_p * error
-				+ self.line.k_i * self.line.integral
-				+ self.line.k_d * (last_error - error)) / 1000.0
+			(gains.k_p * error
+				+ gains.k_i * self.line.integral
+				+ gains.k_d * (last_error - error)) / 1000.0
 		};
 
+		if self.state == RobotState::DriveFollow && (self.curvature.enabled || self.curvature.learn) {
+			let left = bot.left.get_rotations()?;
+			let right = bot.right.get_rotations()?;
+			self.curvature.update(left, right, error, dt, self.diameter);
+
+			if tick_counter.is_multiple_of(100) {
+				log::debug!(target: CONTROL, "curvature: {:.5} 1/cm from {} samples", self.curvature.curvature(self.diameter), self.curvature.samples());
+			}
+		}
+
 		// The other team calls this (in german) "Drall".
 		let spin = if self.state == RobotState::DriveFollow {
-			// In the actual competition we set `self.robot_wheel_width` to `0.0`,
-			// as that makes the spin zero as well, which removes constant left or right
-			// turn.
-			// This was originally created for the qualification, to ease driving one circle
-			// without any in or out.
-			self.robot_wheel_width / self.diameter
+			if self.curvature.enabled {
+				// The learned curvature replaces the fixed one from `self.diameter` once we know it.
+				self.curvature.feed_forward(self.diameter)
+			} else {
+				// In the actual competition we set `self.robot_wheel_width` to `0.0`,
+				// as that makes the spin zero as well, which removes constant left or right
+				// turn.
+				// This was originally created for the qualification, to ease driving one circle
+				// without any in or out.
+				self.robot_wheel_width / self.diameter
+			}
 		} else {
 			0.0
 		};
 
 		// PROBLEM:
-		// We attempt to set the right motor speed to a value larger than the maximum speed of
+		// We attempted to set the right motor speed to a value larger than the maximum speed of
 		// the motor, if `self.speed` is `100` (we use percents).
 		// FIX:
-		// For the future we learn that we need to use `self.speed` for the faster wheel,
-		// and use twice the offset for the other one. This ensures that the maximum speed of
-		// the faster wheel is `self.speed` and nothing above it, as that's impossible when
-		// `self.speed` is the maximum speed possible for the wheel.
-		let l = self.speed * (1.0 + clamped_speed_correction) * (1.0 + line_correction + spin);
-		let r = self.speed * (1.0 + clamped_speed_correction) * (1.0 - line_correction - spin);
+		// We use `self.speed` for the faster wheel, and use twice the offset for the other one.
+		// This ensures that the maximum speed of the faster wheel is `self.speed` and nothing above
+		// it, and we keep the steering even if that means driving slower.
+		let wheels = mixer::mix(speed, speed * (line_correction + spin), self.hardware.max_wheel_speed());
+		let (l, r) = (wheels.left, wheels.right);
 
 		// PROBLEM:
 		// When we stand still and are in the follow mode (or any mode really), we collect a large amount
@@ -218,50 +812,234 @@ impl Program {
 		// then ensure that for low velocities these terms are sufficiently small and the error collected
 		// stays reasonably stable.
 
-		let (l, r) = if r > 100.0 && l < 100.0 {
-			(l * 100.0 / r, 100.0)
-		} else if l > 100.0 && r < 100.0 {
-			(100.0, r * 100.0 / l)
-		} else {
-			(l, r)
-		};
-
 		bot.left.set_speed(l)?;
 		bot.right.set_speed(r)?;
 
-		if self.log {
-			match self.state {
-				RobotState::DriveSimpleOnly => print!("si "),
-				RobotState::DriveEntry      => print!("in "),
-				RobotState::DriveFollow     => print!("fo "),
-				RobotState::DriveExit       => print!("ex "),
-				_                           => print!(" ? "),
-			}
-			match distance {
-				Some(distance) => print!("{distance:>5.1} "),
-				None => print!("no dst"),
-			};
-			if distance.is_some_and(|x| x < self.distance_trigger) {
-				print!(" => dst trigger  -- ");
-			} else {
-				print!(" =>              -- ");
+		if self.stopping && self.ramp.is_stopped() {
+			self.stopping = false;
+			self.state = RobotState::Exit;
+		}
+
+		let record = TickRecord {
+			tick: tick_counter,
+			state: self.state.clone(),
+			raw_distance,
+			estimate,
+			speed_correction,
+			reflection,
+			line_position: position,
+			line_correction,
+			left: l,
+			right: r,
+			saturation: wheels.saturation,
+		};
+		if self.telemetry.record(&record) {
+			log::info!(target: CONTROL, "saturation started at tick {}: {:?} with forward speed {:.1}",
+				record.tick, wheels.saturation, wheels.forward
+			);
+		}
+
+		self.publish(&record);
+
+		Ok(())
+	}
+
+	/// Hands the record of a tick to everybody who keeps or shows it.
+	fn publish(&mut self, record: &TickRecord) {
+		if log::log_enabled!(target: CONTROL, log::Level::Debug) {
+			log::debug!(target: CONTROL, "{}", self.format_record(record));
+		}
+		recorder::record(record);
+		self.stream.send(record);
+		if let Some(run) = &mut self.run {
+			run.record(record);
+		}
+	}
+
+	/// One tick of driving by hand, in [RobotState::Teleop].
+	fn teleop(&mut self, bot: &Robot, tick_counter: usize) -> Result<()> {
+		let motors = self.teleop.motors();
+		bot.left.set_speed(motors.left)?;
+		bot.right.set_speed(motors.right)?;
+		if motors.top_arm == 0.0 {
+			if self.top_arm_running {
+				bot.top_arm.stop()?;
+				self.top_arm_running = false;
 			}
-			print!(" {speed_correction:>5.3} -- ref: {reflection:>5.1} -> l: {l:>5.1} r: {r:>5.1}");
-			if reflection < self.low_ref_warn {
-				print!(" low ref!");
+		} else {
+			if !self.top_arm_running {
+				bot.top_arm.start_with_full_power()?;
+				self.top_arm_running = true;
 			}
-			println!();
+			bot.top_arm.set_speed(motors.top_arm)?;
 		}
 
+		// We keep what the line controller would see, to compare manual laps with it.
+		let reflection = bot.color.get_color()?;
+		let (_, position) = self.line_error(bot, reflection)?;
+		let record = TickRecord {
+			tick: tick_counter,
+			state: self.state.clone(),
+			raw_distance: bot.distance.get_distance()?,
+			estimate: None,
+			speed_correction: 0.0,
+			reflection,
+			line_position: position,
+			line_correction: 0.0,
+			left: motors.left,
+			right: motors.right,
+			saturation: Default::default(),
+		};
+		self.publish(&record);
+
 		Ok(())
 	}
 
-	fn tick(&mut self, bot: &Robot, tick_counter: usize) -> Result<bool> {
-		if bot.buttons.is_left() {
-			std::thread::sleep(Duration::from_millis(300));
-			self.next_state(bot, RobotState::InMenu)?;
+	/// Gets the motors ready for driving by hand, standing still.
+	fn prepare_teleop(&mut self, bot: &Robot) -> Result<()> {
+		self.teleop.reset();
+		if self.teleop.keyboard {
+			keyboard::start()?;
+			log::info!(target: CONTROL, "teleop: drive with the arrow keys, the top arm with a and d");
+		}
+		self.line_position.reset();
+
+		for motor in [&bot.left, &bot.right] {
+			motor.set_ramp(None)?;
+			motor.start()?;
+			motor.set_speed(0.0)?;
+		}
+		self.feedback.play(Event::Ready);
+		Ok(())
+	}
+
+	fn log_summary(&self) {
+		if let Some(summary) = self.telemetry.summary() {
+			log::info!(target: CONTROL, "{summary}");
+		}
+		if let Some(diameter) = self.curvature.suggested_diameter() {
+			log::info!(target: CONTROL, "curvature: learned {:.5} 1/cm from {} samples, that's `diameter = {diameter:.1}`",
+				self.curvature.curvature(self.diameter), self.curvature.samples()
+			);
+		}
+	}
+
+	/// One tick of searching the line, in [RobotState::DriveRecover].
+	fn recover(&mut self, bot: &Robot) -> Result<()> {
+		let dt = Self::TICK_TIME.as_secs_f64();
+		let reflection = bot.color.get_color()?;
+
+		// We may search right in front of the leader or the wall, and stop there like at the end
+		// of the exit, but right away, as we're slow anyways.
+		let estimate = self.filter_distance(bot.distance.get_distance()?, dt);
+		if let Some(estimate) = estimate.filter(|x| x.distance < self.stop_distance) {
+			log::info!(target: CONTROL, "stopping while searching the line, dst was {:.1}, less than {:?}",
+				estimate.distance, self.stop_distance
+			);
+			bot.left.stop()?;
+			bot.right.stop()?;
+			self.feedback.play(Event::Stop);
+			self.log_summary();
+			self.state = RobotState::Exit;
+			return Ok(());
+		}
+
+		match self.recovery.update(reflection, dt, self.hardware.max_wheel_speed()) {
+			Step::Drive { left, right } => {
+				bot.left.set_speed(left)?;
+				bot.right.set_speed(right)?;
+			},
+			Step::Found => {
+				let state = self.recovery.resume();
+				log::info!(target: CONTROL, "found the line again, continuing with {state:?}");
+				// No bump from the derivative in the first tick after searching.
+				self.line.last_error = self.line_error(bot, reflection)?.0;
+				// We search slowly, so we get up to speed again.
+				self.ramp.reset();
+				self.state = state;
+				self.blend.transition();
+				self.feedback.play(Event::LineFound);
+			},
+			Step::GaveUp => {
+				log::warn!(target: CONTROL, "didn't find the line again, giving up");
+				self.feedback.play(Event::GaveUp);
+				self.next_state(bot, RobotState::InMenu)?;
+			},
 		}
-		if bot.touch.is_pressed()? {
+
+		Ok(())
+	}
+
+	fn format_record(&self, record: &TickRecord) -> String {
+		let mut line = String::new();
+		match record.state {
+			RobotState::DriveSimpleOnly => line += "si ",
+			RobotState::DriveEntry      => line += "in ",
+			RobotState::DriveFollow     => line += "fo ",
+			RobotState::DriveExit       => line += "ex ",
+			RobotState::DriveRecover    => line += "re ",
+			_                           => line += " ? ",
+		}
+		match record.raw_distance {
+			Some(distance) => line += &format!("{distance:>5.1} "),
+			None => line += "no dst",
+		};
+		match record.estimate {
+			Some(x) => line += &format!("~{:>5.1} {:>+5.1}cm/s {:>3.0}%", x.distance, x.speed, x.confidence * 100.0),
+			None => line += "~  ?                    ",
+		};
+		if record.estimate.is_some_and(|x| x.distance < self.distance_trigger) {
+			line += " => dst trigger  -- ";
+		} else {
+			line += " =>              -- ";
+		}
+		line += &format!(" {:>5.3} -- ref: {:>5.1} pos: {:>+5.1}cm {:>3.0}% lc: {:>+6.3} -> l: {:>5.1} r: {:>5.1}",
+			record.speed_correction, record.reflection, record.line_position.offset,
+			record.line_position.confidence * 100.0, record.line_correction, record.left, record.right
+		);
+		if record.saturation.any() {
+			line += " sat";
+		}
+		if record.reflection < self.low_ref_warn {
+			line += " low ref!";
+		}
+		line
+	}
+
+	pub(crate) fn tick(&mut self, bot: &Robot, tick_counter: usize) -> Result<bool> {
+		let previous = self.state.clone();
+		self.serve_remote(bot);
+		let done = self.tick_state(bot, tick_counter)?;
+		self.follow_state(tick_counter, &previous);
+		Ok(done)
+	}
+
+	fn tick_state(&mut self, bot: &Robot, tick_counter: usize) -> Result<bool> {
+		// The press that brought us into the menu doesn't count in it.
+		let in_menu = self.state == RobotState::InMenu;
+		let mut events = Vec::new();
+		// Teleop steers with the left arrow.
+		for event in bot.buttons.events(self.state != RobotState::Teleop) {
+			if !self.counts(event) {
+				continue;
+			}
+			match event {
+				// We stop as soon as the button goes down, and not only when it comes up again.
+				ButtonEvent::Press(Button::Left) if self.state.is_run() => {
+					self.emergency_stop("stopped with the left button");
+					self.left_stop = Some(false);
+					self.next_state(bot, RobotState::InMenu)?;
+				},
+				ButtonEvent::Click(Button::Left) | ButtonEvent::LongPress(Button::Left) if self.state != RobotState::InMenu => {
+					log::info!(target: STATE, "back to the menu with the left button");
+					self.next_state(bot, RobotState::InMenu)?;
+				},
+				_ => {},
+			}
+			events.push(event);
+		}
+		if self.state != RobotState::InMenu && bot.touch.is_pressed()? {
+			self.emergency_stop("stopped with the touch sensor");
 			self.next_state(bot, RobotState::InMenu)?;
 		}
 
@@ -270,17 +1048,27 @@ impl Program {
 				return Ok(true)
 			},
 			RobotState::InMenu => {
-				if let Some(new_state) = menu::select(bot)? {
+				if let Some(new_state) = self.menu.update(if in_menu { &events } else { &[] }) {
 					self.next_state(bot, new_state)?;
+				} else if !bot.buttons.can_press() && !self.remote_running() {
+					log::info!(target: MENU, "nothing can select anything in the menu, leaving it");
+					self.next_state(bot, RobotState::Exit)?;
 				}
 			},
 			RobotState::Test => {
 				self.test(bot)?;
+				bot.buttons.forget();
 				self.state = RobotState::Exit;
 			},
 			RobotState::Start => {},
 			RobotState::Measure => {
 				self.measure(bot)?;
+				// The presses meanwhile were for the measurement, and not for the menu.
+				bot.buttons.forget();
+				self.state = RobotState::InMenu;
+			},
+			RobotState::Autotune => {
+				self.autotune(bot)?;
 				self.state = RobotState::InMenu;
 			},
 			RobotState::DriveSimpleOnly |
@@ -289,20 +1077,61 @@ impl Program {
 			RobotState::DriveExit => {
 				self.drive(bot, tick_counter)?;
 			},
+			RobotState::DriveRecover => {
+				self.recover(bot)?;
+			},
+			RobotState::Teleop => {
+				self.teleop(bot, tick_counter)?;
+			},
+			RobotState::Idle => {},
 		}
 
 		Ok(false)
 	}
 
-	fn next_state(&mut self, bot: &Robot, new_state: RobotState) -> Result<()> {
+	/// Whether `event` counts, which it doesn't if it is the rest of the press of left that stopped
+	/// the drive, up to the click when it comes up again.
+	fn counts(&mut self, event: ButtonEvent) -> bool {
+		let Some(released) = self.left_stop else { return true };
+		match event {
+			ButtonEvent::LongPress(Button::Left) if !released => false,
+			ButtonEvent::Release(Button::Left) if !released => {
+				self.left_stop = Some(true);
+				false
+			},
+			ButtonEvent::Click(Button::Left) if released => {
+				self.left_stop = None;
+				false
+			},
+			// A long press has no click at the end, so this is something new.
+			_ if released => {
+				self.left_stop = None;
+				true
+			},
+			_ => true,
+		}
+	}
+
+	pub(crate) fn next_state(&mut self, bot: &Robot, new_state: RobotState) -> Result<()> {
 		match self.state {
+			RobotState::InMenu => {
+				// We show the menu again, the next time we come back.
+				self.menu = Menu::default();
+			},
 			RobotState::DriveSimpleOnly |
 			RobotState::DriveEntry |
 			RobotState::DriveFollow |
-			RobotState::DriveExit => {
+			RobotState::DriveExit |
+			RobotState::DriveRecover |
+			RobotState::Teleop => {
+				// The keys of teleop shouldn't move in the menu afterwards.
+				keyboard::clear();
 				bot.left.stop().context("Failed to end line drive")?;
 				bot.right.stop().context("Failed to end line drive")?;
 				bot.top_arm.stop().context("Failed to end line drive")?;
+				self.top_arm_running = false;
+				self.top_arm_throttle = None;
+				self.log_summary();
 			},
 			_ => {},
 		}
@@ -316,7 +1145,7 @@ impl Program {
 					std::thread::sleep(Duration::from_millis(100));
 				}
 				self.state = RobotState::DriveEntry;
-				self.prepare_drive(bot)
+				self.prepare_drive(bot, &RobotState::DriveEntry)
 					.context("Failed to prepare for line drive")?;
 				return Ok(());
 			},
@@ -324,9 +1153,13 @@ impl Program {
 			RobotState::DriveEntry |
 			RobotState::DriveFollow |
 			RobotState::DriveExit => {
-				self.prepare_drive(bot)
+				self.prepare_drive(bot, &new_state)
 					.context("Failed to prepare for line drive")?;
 			},
+			RobotState::Teleop => {
+				self.prepare_teleop(bot)
+					.context("Failed to prepare for driving by hand")?;
+			},
 			_ => {},
 		}
 
@@ -335,53 +1168,24 @@ impl Program {
 	}
 
 	// we do 100 ticks per second
-	const TICK_TIME: Duration = Duration::from_millis(10);
-
-	pub(crate) fn main(&mut self, bot: &Robot) -> Result<()> {
-		let initial_state = if let Some(arg) = std::env::args().skip(1).next() {
-			match arg.as_str() {
-				"help" => {
-					eprintln!("{}", RobotState::HELP_TEXT);
-
-					return Ok(())
-				},
-				"exit" => RobotState::Exit,
-				"menu" => RobotState::InMenu,
-				"test" => RobotState::Test,
-				"measure" => RobotState::Measure,
-				"drive" => RobotState::DriveEntry,
-				"driveS" => RobotState::DriveSimpleOnly,
-				"start" => RobotState::Start,
-				"l" => {
-					let amount = std::env::args().skip(2)
-						.next().map(|x| x.parse::<f64>()).context("You're missing an argument")?
-						.context("Your second argument needs to be a floating point number")?;
-
-					return bot.left.step(amount);
-				},
-				"r" => {
-					let amount = std::env::args().skip(2)
-						.next().map(|x| x.parse::<f64>()).context("You're missing an argument")?
-						.context("Your second argument needs to be a floating point number")?;
+	pub(crate) const TICK_TIME: Duration = Duration::from_millis(10);
 
-					return bot.right.step(amount);
-				},
-				"print" => {
-					println!("{bot:#?}");
+	pub(crate) fn main(&mut self, bot: &Robot, initial_state: RobotState) -> Result<()> {
+		let result = self.run_states(bot, initial_state);
+		match &result {
+			Ok(()) => self.finish_run(None),
+			Err(err) => self.fail(err),
+		}
+		result
+	}
 
-					return Ok(());
-				},
-				_ => {
-					eprintln!("{}", RobotState::HELP_TEXT);
-
-					bail!("Failed to parse command line arguments: No sub-command {arg:?} known");
-				},
-			}
-		} else {
-			RobotState::InMenu
-		};
+	fn run_states(&mut self, bot: &Robot, initial_state: RobotState) -> Result<()> {
+		self.configure_sensors(bot)
+			.context("Failed to configure the color sensors")?;
 
+		let previous = self.state.clone();
 		self.next_state(bot, initial_state)?;
+		self.follow_state(0, &previous);
 
 		// 31bit are sufficient for 99h of incrementing this ever 10ms,
 		// so this should not fail in the time frame we need.
@@ -395,8 +1199,8 @@ impl Program {
 
 			let end = start.elapsed();
 
-			if self.log && counter % 100 == 0 {
-				println!("tick took: {:?}", end);
+			if counter.is_multiple_of(100) {
+				log::trace!(target: CONTROL, "tick took: {:?}", end);
 			}
 			counter += 1;
 
@@ -407,4 +1211,136 @@ impl Program {
 
 		Ok(())
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use std::io::{BufRead, BufReader, Write};
+	use std::net::{SocketAddr, TcpListener, TcpStream};
+	use crate::sim::{self, world::Scenario};
+	use super::*;
+
+	/// Sends every request to the remote control at `address`, with a pause after it, and at the
+	/// end switches to the exit, so the simulation ends in any case.
+	fn client(address: SocketAddr, requests: Vec<(Request, Duration)>) -> std::thread::JoinHandle<Vec<Response>> {
+		std::thread::spawn(move || {
+			let stream = TcpStream::connect(address).unwrap();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let mut writer = stream;
+			let mut call = |request: &Request| -> Result<Response> {
+				writeln!(writer, "{}", serde_json::to_string(request)?)?;
+				let mut line = String::new();
+				reader.read_line(&mut line)?;
+				Ok(serde_json::from_str(&line)?)
+			};
+
+			let mut responses = Vec::new();
+			for (request, pause) in &requests {
+				responses.push(call(request).unwrap_or_else(|err| Response::from(Err(err))));
+				std::thread::sleep(*pause);
+			}
+			let _ = call(&Request::State { state: Some("exit".to_owned()) });
+			responses
+		})
+	}
+
+	/// A program with the remote control on a free port of the loopback interface.
+	fn remote_program() -> (Program, SocketAddr) {
+		let mut program = Program::default();
+		program.remote.enabled = true;
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		program.remote.listen(listener).unwrap();
+		(program, address)
+	}
+
+	fn value(response: &Response) -> &serde_json::Value {
+		assert!(response.ok, "{response:?}");
+		response.value.as_ref().unwrap()
+	}
+
+	#[test]
+	fn searching_the_line_stops_in_front_of_the_leader() {
+		let mut program = Program::default();
+		program.recovery.enabled = true;
+		// The line is never found.
+		program.recovery.found_reflection = -1.0;
+		let scenario = Scenario { leader_gap: program.stop_distance - 5.0, leader_speed: 0.0, ..Scenario::new(program.diameter) };
+		let outcome = sim::run_from(&mut program, scenario, RobotState::DriveRecover).unwrap();
+		assert_eq!(*program.state(), RobotState::Exit);
+		assert!(!outcome.crashed);
+		assert!(outcome.travelled < 5.0, "{outcome:?}");
+	}
+
+	#[test]
+	fn following_keeps_the_time_gap_in_the_simulation() {
+		let mut program = Program::default();
+		program.follow.mode = FollowMode::TimeGap;
+		program.speed_correction_max = 0.4;
+		sim::follow::follow(&mut program).unwrap();
+	}
+
+	#[test]
+	fn the_rest_of_the_press_that_stopped_does_not_count() {
+		use ButtonEvent::*;
+		let mut program = Program::default();
+		assert!(program.counts(Click(Button::Left)));
+
+		// A short press, with other buttons in between.
+		program.left_stop = Some(false);
+		let counted: Vec<bool> = [Press(Button::Up), Release(Button::Left), Click(Button::Left), Click(Button::Left)]
+			.into_iter().map(|x| program.counts(x)).collect();
+		assert_eq!(counted, [true, false, false, true]);
+		assert_eq!(program.left_stop, None);
+
+		// A long press, which has no click at the end.
+		program.left_stop = Some(false);
+		let counted: Vec<bool> = [LongPress(Button::Left), Release(Button::Left), Press(Button::Left), Release(Button::Left)]
+			.into_iter().map(|x| program.counts(x)).collect();
+		assert_eq!(counted, [false, false, true, true]);
+		assert_eq!(program.left_stop, None);
+	}
+
+	#[test]
+	fn the_menu_leaves_without_buttons_and_remote_control() {
+		let mut program = Program::default();
+		let scenario = Scenario::new(program.diameter);
+		sim::run_from(&mut program, scenario, RobotState::InMenu).unwrap();
+		assert_eq!(*program.state(), RobotState::Exit);
+	}
+
+	#[test]
+	fn remote_control_answers_in_the_menu_and_while_driving() {
+		let (mut program, address) = remote_program();
+		let speed = program.speed;
+		let client = client(address, vec![
+			(Request::State { state: None }, Duration::ZERO),
+			(Request::Get { path: Some("speed".to_owned()) }, Duration::ZERO),
+			(Request::Set { path: "speed".to_owned(), value: 35.into() }, Duration::ZERO),
+			(Request::Set { path: "speed".to_owned(), value: "fast".into() }, Duration::ZERO),
+			(Request::Get { path: Some("nothing".to_owned()) }, Duration::ZERO),
+			(Request::Start, Duration::from_millis(100)),
+			(Request::State { state: None }, Duration::ZERO),
+			(Request::Start, Duration::ZERO),
+			(Request::Stop, Duration::ZERO),
+			(Request::State { state: Some("menu".to_owned()) }, Duration::ZERO),
+		]);
+
+		let scenario = Scenario::new(program.diameter);
+		sim::run_from(&mut program, scenario, RobotState::InMenu).unwrap();
+		let responses = client.join().unwrap();
+
+		assert_eq!(value(&responses[0]), "InMenu");
+		assert_eq!(value(&responses[1]).as_f64(), Some(speed));
+		assert_eq!(value(&responses[2]).as_f64(), Some(35.0));
+		assert!(!responses[3].ok);
+		assert!(!responses[4].ok);
+		assert_eq!(value(&responses[5]), "DriveEntry");
+		assert!(value(&responses[6]).as_str().is_some_and(|x| x.starts_with("Drive")), "{:?}", responses[6]);
+		assert!(!responses[7].ok, "already driving");
+		assert_eq!(value(&responses[8]), "Idle");
+		assert_eq!(value(&responses[9]), "InMenu");
+
+		assert_eq!(program.speed, 35.0);
+		assert_eq!(*program.state(), RobotState::Exit);
+	}
+}
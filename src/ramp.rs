@@ -0,0 +1,155 @@
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RampMode {
+	/// The motors get every speed right away.
+	#[default]
+	Off,
+	/// We limit the change of the speed every tick.
+	Software,
+	/// The motors limit it themselves with `ramp_up_sp` and `ramp_down_sp`. For that they run
+	/// speed regulated instead of with a duty cycle.
+	Ev3,
+}
+
+/// Limits how fast the speed changes, between the controller and the motors.
+///
+/// In [RampMode::Software] we only limit the forward speed, and not the steering, so we still
+/// follow the line while accelerating or braking.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Ramp {
+	pub(crate) mode: RampMode,
+	/// The acceleration and deceleration limits in percent of the maximum speed per second.
+	pub(crate) accel: f64,
+	pub(crate) decel: f64,
+
+	#[serde(skip)]
+	current: f64,
+}
+
+impl Default for Ramp {
+	fn default() -> Self {
+		Self {
+			mode: RampMode::Off,
+			accel: 300.0,
+			decel: 400.0,
+
+			current: 0.0,
+		}
+	}
+}
+
+impl Ramp {
+	/// Starts again from standing still.
+	pub(crate) fn reset(&mut self) {
+		self.current = 0.0;
+	}
+
+	/// The ramp times for the motors, from zero to the full speed and back, in [RampMode::Ev3].
+	pub(crate) fn ev3_ramp(&self) -> Option<(Duration, Duration)> {
+		(self.mode == RampMode::Ev3).then(|| (
+			Duration::from_secs_f64(100.0 / self.accel.max(1.0)),
+			Duration::from_secs_f64(100.0 / self.decel.max(1.0)),
+		))
+	}
+
+	/// The speed we drive with this tick, on the way to `speed`.
+	pub(crate) fn update(&mut self, speed: f64, dt: f64) -> f64 {
+		if self.mode == RampMode::Software {
+			// Getting faster in the same direction is accelerating, everything else is braking.
+			let faster = speed.abs() > self.current.abs() && speed * self.current >= 0.0;
+			// Like the motors in [RampMode::Ev3], we always get there in the end.
+			let rate = if faster { self.accel } else { self.decel }.max(1.0);
+			let step = rate * dt.max(0.0);
+			self.current += (speed - self.current).clamp(-step, step);
+		} else {
+			self.current = speed;
+		}
+
+		self.current
+	}
+
+	/// We stand still, as far as we told the motors to.
+	pub(crate) fn is_stopped(&self) -> bool {
+		self.current == 0.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn software(accel: f64, decel: f64) -> Ramp {
+		Ramp { mode: RampMode::Software, accel, decel, ..Ramp::default() }
+	}
+
+	#[test]
+	fn off_and_ev3_pass_the_speed() {
+		for mode in [RampMode::Off, RampMode::Ev3] {
+			let mut ramp = Ramp { mode, ..Ramp::default() };
+			assert_eq!(ramp.update(80.0, 0.01), 80.0);
+			assert_eq!(ramp.update(-80.0, 0.01), -80.0);
+		}
+	}
+
+	#[test]
+	fn accelerates_and_brakes_with_their_rates() {
+		let mut ramp = software(100.0, 200.0);
+		assert_eq!(ramp.update(50.0, 0.1), 10.0);
+		assert_eq!(ramp.update(50.0, 0.1), 20.0);
+		assert_eq!(ramp.update(0.0, 0.05), 10.0);
+		assert_eq!(ramp.update(0.0, 0.1), 0.0);
+		assert!(ramp.is_stopped());
+	}
+
+	#[test]
+	fn reversing_brakes() {
+		let mut ramp = software(100.0, 200.0);
+		ramp.update(10.0, 0.1);
+		assert_eq!(ramp.update(-50.0, 0.1), -10.0);
+		// Getting faster backwards is accelerating again.
+		assert_eq!(ramp.update(-50.0, 0.1), -20.0);
+	}
+
+	#[test]
+	fn does_not_overshoot() {
+		let mut ramp = software(100.0, 100.0);
+		assert_eq!(ramp.update(5.0, 1.0), 5.0);
+		assert_eq!(ramp.update(5.0, 1.0), 5.0);
+	}
+
+	#[test]
+	fn zero_dt_keeps_the_speed() {
+		let mut ramp = software(100.0, 100.0);
+		assert_eq!(ramp.update(50.0, 0.0), 0.0);
+		assert_eq!(ramp.update(50.0, -0.1), 0.0);
+		assert!(ramp.is_stopped());
+	}
+
+	#[test]
+	fn zero_or_negative_rates_still_get_there() {
+		let mut ramp = software(0.0, -100.0);
+		assert_eq!(ramp.update(50.0, 1.0), 1.0);
+		assert_eq!(ramp.update(0.0, 1.0), 0.0);
+		assert_eq!(ramp.ev3_ramp(), None);
+	}
+
+	#[test]
+	fn ev3_ramp_times() {
+		let ramp = Ramp { mode: RampMode::Ev3, accel: 200.0, decel: 0.0, ..Ramp::default() };
+		assert_eq!(ramp.ev3_ramp(), Some((Duration::from_millis(500), Duration::from_secs(100))));
+	}
+
+	#[test]
+	fn reset_stands_still() {
+		let mut ramp = software(100.0, 100.0);
+		ramp.update(50.0, 0.1);
+		assert!(!ramp.is_stopped());
+		ramp.reset();
+		assert!(ramp.is_stopped());
+		assert_eq!(ramp.update(50.0, 0.1), 10.0);
+	}
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::logging::{self, RUN};
+use crate::program::Program;
+use crate::runs;
+use crate::telemetry::TickRecord;
+
+/// The names of the files we write into the run.
+pub(crate) const LOG: &str = "recorder.log";
+pub(crate) const TELEMETRY: &str = "recorder.csv";
+
+/// Keeps the last seconds of tick records and log lines in memory, and writes them out when a
+/// drive fails, panics, or is stopped by hand.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Recorder {
+	pub(crate) enabled: bool,
+	/// How far back we keep everything, in `s`.
+	pub(crate) seconds: f64,
+}
+
+impl Default for Recorder {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			seconds: 5.0,
+		}
+	}
+}
+
+struct State {
+	seconds: f64,
+	records: VecDeque<TickRecord>,
+	/// The size of `records`.
+	size: usize,
+	/// Where we write without a run.
+	dir: PathBuf,
+	/// The directory of the current run.
+	run: Option<PathBuf>,
+}
+
+/// In a static, so the panic hook gets to it too.
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// Starts recording as `recorder` says. Without a run, we write into `dir`.
+pub(crate) fn configure(recorder: &Recorder, dir: &Path) {
+	let mut state = STATE.lock().unwrap_or_else(|x| x.into_inner());
+	*state = recorder.enabled.then(|| {
+		let size = (recorder.seconds / Program::TICK_TIME.as_secs_f64()).ceil() as usize;
+		State {
+			seconds: recorder.seconds,
+			records: VecDeque::with_capacity(size),
+			size,
+			dir: dir.to_owned(),
+			run: None,
+		}
+	});
+}
+
+/// Keeps `record`, and forgets the oldest one.
+pub(crate) fn record(record: &TickRecord) {
+	let mut state = STATE.lock().unwrap_or_else(|x| x.into_inner());
+	let Some(state) = state.as_mut() else { return };
+	if state.records.len() >= state.size {
+		state.records.pop_front();
+	}
+	state.records.push_back(record.clone());
+}
+
+/// From now on we write into the directory of this run, or into the one of all runs again.
+pub(crate) fn set_run(run: Option<&Path>) {
+	let mut state = STATE.lock().unwrap_or_else(|x| x.into_inner());
+	if let Some(state) = state.as_mut() {
+		state.run = run.map(Path::to_owned);
+	}
+}
+
+/// Writes everything we have, because of `reason`, and starts over. Doesn't wait if somebody
+/// else has the recorder, so it works in a panic too.
+pub(crate) fn dump(reason: &str) {
+	let Ok(mut state) = STATE.try_lock() else { return };
+	let Some(state) = state.as_mut() else { return };
+	match write(state, reason) {
+		Ok(path) => log::warn!(target: RUN, "{reason}, wrote the last {}s to {}", state.seconds, path.display()),
+		Err(err) => log::warn!(target: RUN, "{reason}, but failed to write the last {}s: {err:#}", state.seconds),
+	}
+	state.records.clear();
+}
+
+/// Writes the log lines and the records, and returns where the log went.
+fn write(state: &State, reason: &str) -> Result<PathBuf> {
+	let (log, telemetry) = match &state.run {
+		Some(run) => (run.join(LOG), run.join(TELEMETRY)),
+		None => {
+			let name = format!("recorder-{}", runs::timestamp(SystemTime::now()));
+			(state.dir.join(format!("{name}.log")), state.dir.join(format!("{name}.csv")))
+		},
+	};
+	if let Some(dir) = log.parent() {
+		std::fs::create_dir_all(dir)
+			.with_context(|| format!("Failed to create {dir:?}"))?;
+	}
+
+	let mut string = format!("{reason}\n");
+	for line in logging::recent(state.seconds) {
+		string += &line;
+		string += "\n";
+	}
+	std::fs::write(&log, string)
+		.with_context(|| format!("Failed to write {log:?}"))?;
+
+	let mut string = format!("{}\n", TickRecord::CSV_HEADER);
+	for record in &state.records {
+		let _ = writeln!(string, "{}", record.csv());
+	}
+	std::fs::write(&telemetry, string)
+		.with_context(|| format!("Failed to write {telemetry:?}"))?;
+
+	Ok(log)
+}
+
+/// Dumps the recorder when we panic, before the usual panic message.
+pub(crate) fn install_panic_hook() {
+	let hook = std::panic::take_hook();
+	std::panic::set_hook(Box::new(move |info| {
+		dump(&format!("panic: {}", info.to_string().replace('\n', " ")));
+		log::logger().flush();
+		hook(info);
+	}));
+}
@@ -0,0 +1,281 @@
+use serde::{Deserialize, Serialize};
+use crate::mixer;
+use crate::state::RobotState;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Phase {
+	#[default]
+	Arc,
+	/// Turning on the spot, the number counts the sweeps we did.
+	Sweep(usize),
+}
+
+/// What the recovery wants to do in this tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Step {
+	Drive { left: f64, right: f64 },
+	/// We see the line again.
+	Found,
+	/// We didn't find the line in time.
+	GaveUp,
+}
+
+/// Notices that we lost the line, and searches it again.
+///
+/// The line is lost, if we only see white for `lost_time`. We then drive an arc to the side
+/// the line was last seen on, and if that doesn't find it, turn on the spot to both sides: first
+/// for `sweep_time` to one side, then for twice that to the other side and back, and so on.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Recovery {
+	pub(crate) enabled: bool,
+
+	/// A reflection of at least this is white.
+	pub(crate) lost_reflection: f64,
+	/// The time in `s` we need to see white, before the line is lost.
+	pub(crate) lost_time: f64,
+	/// The time in `s` over which we average the line error, for the side the line was last seen on.
+	pub(crate) memory: f64,
+	/// A reflection of at most this is the line again.
+	pub(crate) found_reflection: f64,
+
+	/// The speed in percent and the relative differential of the arc.
+	pub(crate) arc_speed: f64,
+	pub(crate) arc_turn: f64,
+	/// The time in `s` we drive the arc, before sweeping.
+	pub(crate) arc_timeout: f64,
+
+	/// The speed in percent of the wheels while turning on the spot.
+	pub(crate) sweep_speed: f64,
+	/// The time in `s` of the first sweep, every following one takes twice as long, so it covers
+	/// the other side as well.
+	pub(crate) sweep_time: f64,
+	/// After this many sweeps we give up.
+	pub(crate) sweeps: usize,
+
+	#[serde(skip)]
+	lost_for: f64,
+	#[serde(skip)]
+	recent_error: f64,
+	#[serde(skip)]
+	phase: Phase,
+	#[serde(skip)]
+	elapsed: f64,
+	#[serde(skip)]
+	direction: f64,
+	#[serde(skip)]
+	resume: RobotState,
+}
+
+impl Default for Recovery {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+
+			lost_reflection: 70.0,
+			lost_time: 0.2,
+			memory: 0.3,
+			found_reflection: 55.0,
+
+			arc_speed: 30.0,
+			arc_turn: 0.4,
+			arc_timeout: 1.0,
+
+			sweep_speed: 20.0,
+			sweep_time: 0.5,
+			sweeps: 4,
+
+			lost_for: 0.0,
+			recent_error: 0.0,
+			phase: Phase::default(),
+			elapsed: 0.0,
+			direction: 1.0,
+			resume: RobotState::default(),
+		}
+	}
+}
+
+impl Recovery {
+	pub(crate) fn reset(&mut self) {
+		self.lost_for = 0.0;
+		self.recent_error = 0.0;
+	}
+
+	/// Looks at the reflection of this tick, and tells whether we lost the line.
+	pub(crate) fn is_lost(&mut self, reflection: f64, error: f64, dt: f64) -> bool {
+		if reflection < self.lost_reflection {
+			// Only while we still see the line the error says where it is.
+			let rate = (dt / self.memory.max(dt)).min(1.0);
+			self.recent_error += (error - self.recent_error) * rate;
+			self.lost_for = 0.0;
+			return false;
+		}
+
+		self.lost_for += dt;
+		self.lost_for >= self.lost_time
+	}
+
+	/// Starts searching, towards the side the line PID with `k_p` steered to before, and continues
+	/// with `resume` once we found the line.
+	pub(crate) fn start(&mut self, k_p: f64, resume: RobotState) {
+		// If we saw white before, the line is where the PID steers to anyways. If we saw black, we
+		// crossed the line, and it's on the other side.
+		self.direction = if k_p * self.recent_error < 0.0 { -1.0 } else { 1.0 };
+		self.phase = Phase::Arc;
+		self.elapsed = 0.0;
+		self.lost_for = 0.0;
+		self.resume = resume;
+	}
+
+	/// The state we continue with.
+	pub(crate) fn resume(&self) -> RobotState {
+		self.resume.clone()
+	}
+
+	/// The next step of the search, with wheels up to `max_speed`.
+	pub(crate) fn update(&mut self, reflection: f64, dt: f64, max_speed: f64) -> Step {
+		if reflection <= self.found_reflection {
+			return Step::Found;
+		}
+
+		self.elapsed += dt;
+		match self.phase {
+			Phase::Arc if self.elapsed < self.arc_timeout => {
+				let wheels = mixer::mix(self.arc_speed, self.arc_speed * self.arc_turn * self.direction, max_speed);
+				Step::Drive { left: wheels.left, right: wheels.right }
+			},
+			Phase::Arc => {
+				self.phase = Phase::Sweep(0);
+				self.elapsed = 0.0;
+				self.update(reflection, 0.0, max_speed)
+			},
+			Phase::Sweep(sweep) if sweep >= self.sweeps => Step::GaveUp,
+			Phase::Sweep(sweep) => {
+				let time = if sweep == 0 { self.sweep_time } else { 2.0 * self.sweep_time };
+				if self.elapsed >= time {
+					self.phase = Phase::Sweep(sweep + 1);
+					self.elapsed = 0.0;
+					self.direction = -self.direction;
+					return self.update(reflection, 0.0, max_speed);
+				}
+				let max_speed = max_speed.clamp(0.0, mixer::MAX_WHEEL_SPEED);
+				let speed = self.sweep_speed.clamp(-max_speed, max_speed) * self.direction;
+				Step::Drive { left: speed, right: -speed }
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DT: f64 = 0.1;
+	const WHITE: f64 = 90.0;
+	const BLACK: f64 = 10.0;
+
+	fn recovery() -> Recovery {
+		Recovery {
+			enabled: true,
+			arc_timeout: 0.3,
+			sweep_time: 0.2,
+			sweeps: 2,
+			..Recovery::default()
+		}
+	}
+
+	/// The steps until the search is over, at most `limit`.
+	fn search(recovery: &mut Recovery, max_speed: f64, limit: usize) -> Vec<Step> {
+		let mut steps = Vec::new();
+		while steps.len() < limit {
+			let step = recovery.update(WHITE, DT, max_speed);
+			steps.push(step);
+			if !matches!(step, Step::Drive { .. }) {
+				break;
+			}
+		}
+		steps
+	}
+
+	#[test]
+	fn lost_after_lost_time_of_white() {
+		let mut recovery = recovery();
+		assert!(!recovery.is_lost(WHITE, 0.0, DT));
+		assert!(recovery.is_lost(WHITE, 0.0, DT));
+		// Seeing the line starts over.
+		assert!(!recovery.is_lost(BLACK, 0.0, DT));
+		assert!(!recovery.is_lost(WHITE, 0.0, DT));
+	}
+
+	#[test]
+	fn zero_lost_time_or_memory() {
+		let mut recovery = Recovery { lost_time: 0.0, memory: 0.0, ..recovery() };
+		assert!(!recovery.is_lost(BLACK, 5.0, 0.0));
+		assert_eq!(recovery.recent_error, 5.0);
+		assert!(recovery.is_lost(WHITE, 0.0, 0.0));
+	}
+
+	#[test]
+	fn found_at_once() {
+		let mut recovery = recovery();
+		recovery.start(1.0, RobotState::DriveFollow);
+		assert_eq!(recovery.update(BLACK, DT, 100.0), Step::Found);
+		assert_eq!(recovery.resume(), RobotState::DriveFollow);
+	}
+
+	#[test]
+	fn arcs_towards_the_line() {
+		let mut recovery = recovery();
+		recovery.is_lost(BLACK, 10.0, DT);
+		recovery.start(1.0, RobotState::DriveEntry);
+		let Step::Drive { left, right } = recovery.update(WHITE, DT, 100.0) else { panic!() };
+		assert!(left > right);
+
+		// With the opposite sign of `k_p` the line is on the other side.
+		recovery.start(-1.0, RobotState::DriveEntry);
+		let Step::Drive { left, right } = recovery.update(WHITE, DT, 100.0) else { panic!() };
+		assert!(left < right);
+	}
+
+	#[test]
+	fn arc_then_sweeps_then_gives_up() {
+		let mut recovery = recovery();
+		recovery.start(1.0, RobotState::DriveEntry);
+		let steps = search(&mut recovery, 100.0, 100);
+
+		// The arc, then a short sweep to one side, and a long one to the other.
+		let turns: Vec<f64> = steps.iter().filter_map(|x| match x {
+			Step::Drive { left, right } if *left == -*right => Some(*left),
+			_ => None,
+		}).collect();
+		assert_eq!(steps.len(), 2 + 2 + 4 + 1);
+		assert_eq!(turns, [20.0, 20.0, -20.0, -20.0, -20.0, -20.0]);
+		assert_eq!(steps.last(), Some(&Step::GaveUp));
+		assert_eq!(recovery.update(WHITE, DT, 100.0), Step::GaveUp);
+	}
+
+	#[test]
+	fn zero_sweeps_or_times() {
+		let mut recovery = Recovery { sweeps: 0, ..recovery() };
+		recovery.start(1.0, RobotState::DriveEntry);
+		assert_eq!(search(&mut recovery, 100.0, 100).len(), 2 + 1);
+
+		let mut recovery = Recovery { arc_timeout: 0.0, sweep_time: 0.0, ..self::recovery() };
+		recovery.start(1.0, RobotState::DriveEntry);
+		assert_eq!(search(&mut recovery, 100.0, 100), [Step::GaveUp]);
+	}
+
+	#[test]
+	fn wheels_stay_below_max_speed() {
+		let mut recovery = Recovery { arc_speed: 80.0, sweep_speed: 80.0, ..recovery() };
+		for max_speed in [0.0, 50.0, -10.0, 1000.0_f64] {
+			recovery.start(1.0, RobotState::DriveEntry);
+			let limit = max_speed.clamp(0.0, mixer::MAX_WHEEL_SPEED);
+			for step in search(&mut recovery, max_speed, 100) {
+				if let Step::Drive { left, right } = step {
+					assert!(left.abs() <= limit && right.abs() <= limit, "{left} {right} above {max_speed}");
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,193 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use crate::remote::{Request, Response, PORT};
+use crate::teleop::Motors;
+
+/// How often `teleop` repeats its command, well within the timeout of `[teleop]`.
+const TELEOP_EVERY: Duration = Duration::from_millis(100);
+
+/// Gets and sets the settings of the robot while it runs, with `[remote]` enabled.
+///
+/// Without a command, it reads one command per line from standard input, e.g.
+/// `set line.k_p -4.5`.
+#[derive(Debug, Parser)]
+#[command(name = "remote")]
+struct Args {
+	/// The robot, e.g. `ev3dev:7701`.
+	#[arg(long, short, default_value_t = format!("ev3dev:{PORT}"))]
+	address: String,
+
+	#[command(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(Debug, Parser)]
+#[command(name = "", no_binary_name = true)]
+struct Line {
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+	/// Print the setting at `PATH` like `line.k_p`, or all of them without it.
+	Get {
+		path: Option<String>,
+	},
+	/// Change the setting at `PATH` to `VALUE`, which is JSON like `-4.5`, `true` or `"log"`.
+	Set {
+		path: String,
+		#[arg(allow_negative_numbers = true)]
+		value: String,
+	},
+	/// Switch to the state with the name from the menu, e.g. `"drive follow"`, or print the
+	/// state without it.
+	State {
+		state: Option<String>,
+	},
+	/// Start a drive.
+	Start,
+	/// Stop the drive.
+	Stop,
+	/// Write every changed setting into the settings file on the robot it came from.
+	Save,
+	/// Set the motors in the `teleop` state, in percent, and keep them there for `--time`.
+	Teleop {
+		#[arg(allow_negative_numbers = true)]
+		left: f64,
+		#[arg(allow_negative_numbers = true)]
+		right: f64,
+		#[arg(allow_negative_numbers = true, default_value_t = 0.0)]
+		top_arm: f64,
+		/// How long, in `s`. The robot stops by itself after the timeout of `[teleop]`.
+		#[arg(long, short, default_value_t = 0.0)]
+		time: f64,
+	},
+}
+
+impl Command {
+	fn request(self) -> Request {
+		match self {
+			Command::Get { path } => Request::Get { path },
+			Command::Set { path, value } => Request::Set {
+				path,
+				// Without quotes, a word is a string too.
+				value: serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value)),
+			},
+			Command::State { state } => Request::State { state },
+			Command::Start => Request::Start,
+			Command::Stop => Request::Stop,
+			Command::Save => Request::Save,
+			Command::Teleop { left, right, top_arm, .. } => Request::Teleop(Motors { left, right, top_arm }),
+		}
+	}
+}
+
+struct Connection {
+	reader: BufReader<TcpStream>,
+	writer: TcpStream,
+}
+
+impl Connection {
+	/// Sends the command, and repeats `teleop` until its time is over.
+	fn run(&mut self, command: Command) -> Result<Response> {
+		let time = match command {
+			Command::Teleop { time, .. } => Duration::from_secs_f64(time.max(0.0)),
+			_ => Duration::ZERO,
+		};
+		let request = command.request();
+		let started = Instant::now();
+		loop {
+			let response = self.send(&request)?;
+			if !response.ok || started.elapsed() + TELEOP_EVERY > time {
+				return Ok(response);
+			}
+			std::thread::sleep(TELEOP_EVERY);
+		}
+	}
+
+	fn send(&mut self, request: &Request) -> Result<Response> {
+		writeln!(self.writer, "{}", serde_json::to_string(request)?)
+			.context("Failed to send the request")?;
+		let mut line = String::new();
+		self.reader.read_line(&mut line)
+			.context("Failed to receive the answer")?;
+		anyhow::ensure!(!line.is_empty(), "The robot closed the connection");
+		serde_json::from_str(&line)
+			.context("Failed to parse the answer")
+	}
+}
+
+/// Prints the value, or the error.
+fn print(response: &Response) {
+	match (&response.value, &response.error) {
+		(_, Some(error)) => eprintln!("error: {error}"),
+		(Some(serde_json::Value::String(value)), _) => println!("{value}"),
+		(Some(value), _) => println!("{}", serde_json::to_string_pretty(value).unwrap_or_default()),
+		(None, None) => println!("ok"),
+	}
+}
+
+pub(crate) fn main() -> Result<()> {
+	let args = Args::parse();
+
+	let stream = TcpStream::connect(&args.address)
+		.with_context(|| format!("Failed to connect to {}", args.address))?;
+	let mut connection = Connection {
+		reader: BufReader::new(stream.try_clone()?),
+		writer: stream,
+	};
+
+	if let Some(command) = args.command {
+		let response = connection.run(command)?;
+		print(&response);
+		anyhow::ensure!(response.ok, "The robot refused");
+		return Ok(());
+	}
+
+	for line in std::io::stdin().lock().lines() {
+		let line = line?;
+		let words: Vec<String> = split(&line);
+		if words.is_empty() {
+			continue;
+		}
+		match Line::try_parse_from(words) {
+			Ok(Line { command }) => print(&connection.run(command)?),
+			Err(err) => eprintln!("{err}"),
+		}
+	}
+	Ok(())
+}
+
+/// Splits at spaces, but keeps what is in double quotes together, like `state "drive follow"`.
+fn split(line: &str) -> Vec<String> {
+	let mut words = Vec::new();
+	let mut word = String::new();
+	let mut quoted = false;
+	let mut started = false;
+	for char in line.chars() {
+		match char {
+			'"' => {
+				quoted = !quoted;
+				started = true;
+			},
+			' ' | '\t' if !quoted => {
+				if started {
+					words.push(std::mem::take(&mut word));
+				}
+				started = false;
+			},
+			_ => {
+				word.push(char);
+				started = true;
+			},
+		}
+	}
+	if started {
+		words.push(word);
+	}
+	words
+}
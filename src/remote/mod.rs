@@ -0,0 +1,210 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::Duration;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::logging::NETWORK;
+use crate::teleop::Motors;
+
+pub(crate) mod client;
+
+/// The default port of the remote control, and of `bin/remote.rs`.
+pub(crate) const PORT: u16 = 7701;
+
+/// How long a client waits for the robot to answer. The robot answers between two ticks, in every
+/// state, so only a tick that takes very long, like autotune or measure, runs into this.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One line of JSON from the client, e.g. `{"command": "set", "path": "line.k_p", "value": -4.5}`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub(crate) enum Request {
+	/// The setting at `path` like `line.k_p`, or all of them without it.
+	Get {
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		path: Option<String>,
+	},
+	/// Changes the setting at `path`, from the next tick on.
+	Set {
+		path: String,
+		value: serde_json::Value,
+	},
+	/// Switches to the state with the name from the menu, or only says the state without it.
+	State {
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		state: Option<String>,
+	},
+	/// Starts a drive, like the `drive` command.
+	Start,
+	/// Stops the drive, and waits in the `idle` state.
+	Stop,
+	/// Writes every setting we changed into the settings file, and keeps its comments.
+	Save,
+	/// Sets the motors in the `teleop` state, until the timeout of `[teleop]`, e.g.
+	/// `{"command": "teleop", "left": 30, "right": 30, "top_arm": 0}`.
+	Teleop(Motors),
+}
+
+/// One line of JSON back to the client, with the value or the error.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct Response {
+	pub(crate) ok: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub(crate) value: Option<serde_json::Value>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub(crate) error: Option<String>,
+}
+
+impl From<Result<serde_json::Value>> for Response {
+	fn from(result: Result<serde_json::Value>) -> Self {
+		match result {
+			Ok(value) => Response { ok: true, value: Some(value), error: None },
+			Err(err) => Response { ok: false, value: None, error: Some(format!("{err:#}")) },
+		}
+	}
+}
+
+/// A request, and where its answer goes.
+pub(crate) type Call = (Request, Sender<Response>);
+
+/// Lets a client on the network get and set the settings, switch the state, and start and stop
+/// drives, with one line of JSON for every [Request] and [Response]. See `bin/remote.rs` for the
+/// client.
+///
+/// The clients have their own threads, and the control loop answers all waiting requests
+/// between two ticks, so nothing changes in the middle of one.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Remote {
+	pub(crate) enabled: bool,
+	/// Where we listen for clients.
+	pub(crate) address: String,
+
+	#[serde(skip)]
+	receiver: Option<Receiver<Call>>,
+}
+
+impl Default for Remote {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			address: format!("0.0.0.0:{PORT}"),
+			receiver: None,
+		}
+	}
+}
+
+impl Remote {
+	/// Listens for clients, if the remote control is enabled.
+	pub(crate) fn start(&mut self) -> Result<()> {
+		if !self.enabled || self.receiver.is_some() {
+			return Ok(());
+		}
+
+		let listener = TcpListener::bind(&self.address)
+			.with_context(|| format!("Failed to listen for remote control clients on {}", self.address))?;
+		self.listen(listener)
+	}
+
+	/// Takes the clients from `listener`.
+	pub(crate) fn listen(&mut self, listener: TcpListener) -> Result<()> {
+		log::info!(target: NETWORK, "remote control on {}", listener.local_addr()?);
+
+		let (sender, receiver) = std::sync::mpsc::channel();
+		std::thread::Builder::new()
+			.name("remote control".to_owned())
+			.spawn(move || accept(listener, sender))
+			.context("Failed to start the remote control thread")?;
+		self.receiver = Some(receiver);
+		Ok(())
+	}
+
+	pub(crate) fn is_running(&self) -> bool {
+		self.receiver.is_some()
+	}
+
+	/// The next waiting request, if there is one.
+	pub(crate) fn next(&mut self) -> Option<Call> {
+		match self.receiver.as_ref()?.try_recv() {
+			Ok(call) => Some(call),
+			Err(TryRecvError::Empty) => None,
+			Err(TryRecvError::Disconnected) => {
+				log::warn!(target: NETWORK, "the remote control thread is gone, stopping the remote control");
+				self.receiver = None;
+				None
+			},
+		}
+	}
+}
+
+/// Gives every client a thread of its own.
+fn accept(listener: TcpListener, sender: Sender<Call>) {
+	for client in listener.incoming() {
+		let client = match client {
+			Ok(client) => client,
+			Err(err) => {
+				log::warn!(target: NETWORK, "Failed to accept a remote control client: {err}");
+				continue;
+			},
+		};
+		let sender = sender.clone();
+		let spawned = std::thread::Builder::new()
+			.name("remote control client".to_owned())
+			.spawn(move || {
+				let address = client.peer_addr().ok();
+				if let Err(err) = serve(client, sender) {
+					log::info!(target: NETWORK, "remote control client {address:?} is gone: {err:#}");
+				}
+			});
+		if let Err(err) = spawned {
+			log::warn!(target: NETWORK, "Failed to start a remote control client thread: {err}");
+		}
+	}
+}
+
+/// Answers every line from `client`, until it goes away.
+fn serve(client: TcpStream, sender: Sender<Call>) -> Result<()> {
+	let address: Option<SocketAddr> = client.peer_addr().ok();
+	log::info!(target: NETWORK, "remote control client {address:?} connected");
+	let mut writer = client.try_clone()?;
+	for line in BufReader::new(client).lines() {
+		let line = line?;
+		if line.trim().is_empty() {
+			continue;
+		}
+
+		let response = match serde_json::from_str::<Request>(&line) {
+			Ok(request) => {
+				log::debug!(target: NETWORK, "{address:?}: {request:?}");
+				let (answer, answered) = std::sync::mpsc::channel();
+				sender.send((request, answer)).context("The robot is gone")?;
+				match answered.recv_timeout(ANSWER_TIMEOUT) {
+					Ok(response) => response,
+					Err(RecvTimeoutError::Timeout) => Response::from(Err(anyhow!(
+						"No answer within {}s, the robot might be busy with autotune or measure", ANSWER_TIMEOUT.as_secs(),
+					))),
+					Err(RecvTimeoutError::Disconnected) => bail!("The robot is gone"),
+				}
+			},
+			Err(err) => Response::from(Err(anyhow::Error::new(err).context("Failed to parse the request"))),
+		};
+		writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+	}
+	Ok(())
+}
+
+/// The value at `path` in `settings`, like `line.k_p`.
+pub(crate) fn get<'a>(settings: &'a toml::Value, path: &str) -> Result<&'a toml::Value> {
+	path.split('.').try_fold(settings, |value, key| value.get(key)
+		.with_context(|| format!("No setting {path:?}")))
+}
+
+/// Puts `value` at `path` in `settings`, where there already is a value that is no table.
+pub(crate) fn set(settings: &mut toml::Value, path: &str, value: toml::Value) -> Result<()> {
+	let current = path.split('.').try_fold(settings, |value, key| value.get_mut(key)
+		.with_context(|| format!("No setting {path:?}")))?;
+	ensure!(!current.is_table(), "{path:?} is a table, set the values in it one by one");
+	*current = value;
+	Ok(())
+}
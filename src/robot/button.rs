@@ -1,73 +1,425 @@
 use anyhow::{Context, Result};
-use std::time::Duration;
+use std::cell::RefCell;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+use clap::ValueEnum;
 use ev3dev_lang_rust::Button as Ev3Button;
+use serde::{Deserialize, Serialize};
+use crate::keyboard::{self, Key};
+use crate::logging::HARDWARE;
 
-macro_rules! button_function {
-	($self:ident, $name:ident, $ret:path) => {
-		if $self.inner.$name() {
-			while $self.inner.$name() {
-				std::thread::sleep(Duration::from_millis(10));
-				$self.inner.process();
-			}
-			return $ret;
+/// Where the button presses come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Input {
+	/// The buttons of the brick, or none in the simulation.
+	#[default]
+	Brick,
+	/// The keys on standard input, e.g. over SSH: the arrows, Enter, and Backspace for left. The
+	/// left arrow is left too, but not in teleop, which steers with it.
+	Keyboard,
+}
+
+/// How long presses are, in `s`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Timing {
+	/// Held this long, a press is a [ButtonEvent::LongPress].
+	pub(crate) long_press: f64,
+	/// A second click within this long after the first one is a [ButtonEvent::DoublePress].
+	pub(crate) double_press: f64,
+}
+
+impl Default for Timing {
+	fn default() -> Self {
+		Self {
+			long_press: 1.0,
+			double_press: 0.3,
 		}
 	}
 }
 
+/// What the buttons did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ButtonEvent {
+	Press(Button),
+	Release(Button),
+	/// Pressed and released again, neither long nor as a chord.
+	Click(Button),
+	/// Held for `long_press`, while still held.
+	LongPress(Button),
+	/// A second click right after the first one, which came as a [ButtonEvent::Click] too.
+	DoublePress(Button),
+	/// The second button pressed while the first one is held, e.g. up and down.
+	Chord(Button, Button),
+}
+
+#[derive(Debug)]
+enum Inner {
+	/// A thread polls the brick and sends the events, we only poll it directly for [Buttons::is_up]
+	/// and the like.
+	Brick {
+		button: Ev3Button,
+		events: Receiver<ButtonEvent>,
+	},
+	Keyboard(RefCell<Detector>),
+	/// No button is ever pressed.
+	None,
+}
+
+/// The buttons of the brick, or the keyboard, see [Input], as a stream of [ButtonEvent]s. In the
+/// simulation without the keyboard no button is ever pressed, and waiting for a press returns
+/// [Button::Left] right away, so that we go back. The same happens when standard input ends.
+///
+/// A terminal doesn't say when a key goes up, so with the keyboard every key is a click, and there
+/// are no long presses or chords.
 #[derive(Debug)]
 pub(crate) struct Buttons {
-	inner: Ev3Button,
+	inner: Inner,
 }
 
 impl Buttons {
-	pub(crate) fn new() -> Result<Buttons> {
-		Ok(Buttons {
-			inner: Ev3Button::new()
-				.context("Failed to create buttons")?,
-		})
+	pub(crate) fn new(input: Input, timing: Timing) -> Result<Buttons> {
+		let inner = match input {
+			Input::Brick => {
+				let button = Ev3Button::new()
+					.context("Failed to create buttons")?;
+				let (sender, events) = std::sync::mpsc::channel();
+				let (started, result) = std::sync::mpsc::channel();
+				std::thread::Builder::new()
+					.name("buttons".to_owned())
+					.spawn(move || poll(timing, sender, started))
+					.context("Failed to start the button thread")?;
+				result.recv().context("The button thread is gone")??;
+				Inner::Brick { button, events }
+			},
+			Input::Keyboard => {
+				keyboard::start()?;
+				Inner::Keyboard(RefCell::new(Detector::new(timing)))
+			},
+		};
+		Ok(Buttons { inner })
 	}
 
+	pub(crate) fn simulated(input: Input, timing: Timing) -> Result<Buttons> {
+		match input {
+			Input::Brick => Ok(Buttons { inner: Inner::None }),
+			Input::Keyboard => Buttons::new(input, timing),
+		}
+	}
+
+	/// Waits for a click or a long press, and forgets everything before it.
 	pub(crate) fn await_press(&self) -> Button {
+		let events = match &self.inner {
+			Inner::Brick { events, .. } => events,
+			Inner::Keyboard(_) => return await_key(),
+			Inner::None => return Button::Left,
+		};
+		events.try_iter().for_each(drop);
 		loop {
-			self.inner.process();
+			match events.recv() {
+				Ok(ButtonEvent::Click(button) | ButtonEvent::LongPress(button)) => return button,
+				Ok(_) => {},
+				Err(_) => return Button::Left,
+			}
+		}
+	}
 
-			button_function!(self, is_up,        Button::Up       );
-			button_function!(self, is_down,      Button::Down     );
-			button_function!(self, is_left,      Button::Left     );
-			button_function!(self, is_right,     Button::Right    );
-			button_function!(self, is_enter,     Button::Enter    );
+	/// Every event since the last time, without waiting. Without `left_arrow`, the left arrow of
+	/// the keyboard is no [Button::Left].
+	pub(crate) fn events(&self, left_arrow: bool) -> Vec<ButtonEvent> {
+		match &self.inner {
+			Inner::Brick { events, .. } => events.try_iter().collect(),
+			Inner::Keyboard(detector) => {
+				let mut detector = detector.borrow_mut();
+				let mut events = Vec::new();
+				while let Some(key) = keyboard::next() {
+					let button = match key {
+						Key::Up => Button::Up,
+						Key::Down => Button::Down,
+						Key::Right => Button::Right,
+						Key::Enter => Button::Enter,
+						Key::Backspace => Button::Left,
+						Key::Left if left_arrow => Button::Left,
+						Key::Left | Key::Char(_) => continue,
+					};
+					let now = Instant::now();
+					detector.update(now, &[button], &mut events);
+					detector.update(now, &[], &mut events);
+				}
+				events
+			},
+			Inner::None => Vec::new(),
+		}
+	}
 
-			std::thread::sleep(Duration::from_millis(10));
+	/// Forgets every event since the last time, e.g. after a loop that didn't look at them.
+	pub(crate) fn forget(&self) {
+		self.events(true);
+	}
+
+	/// Whether a button can still be pressed, which it can't in the simulation without the
+	/// keyboard, or after standard input ended.
+	pub(crate) fn can_press(&self) -> bool {
+		match &self.inner {
+			Inner::Brick { .. } => true,
+			Inner::Keyboard(_) => !keyboard::is_closed(),
+			Inner::None => false,
+		}
+	}
+
+	/// Whether the button is down right now. The keyboard doesn't know that, so there it's whether
+	/// its key was pressed since the last time, once for every press however long we don't look.
+	fn is(&self, f: impl Fn(&Ev3Button) -> bool, key: Key) -> bool {
+		match &self.inner {
+			Inner::Brick { button, .. } => {
+				button.process();
+				f(button)
+			},
+			Inner::Keyboard(_) => keyboard::take(key),
+			Inner::None => false,
 		}
 	}
 
 	pub(crate) fn is_up(&self) -> bool {
-		self.inner.process();
-		self.inner.is_up()
+		self.is(Ev3Button::is_up, Key::Up)
 	}
 
 	pub(crate) fn is_down(&self) -> bool {
-		self.inner.process();
-		self.inner.is_down()
+		self.is(Ev3Button::is_down, Key::Down)
 	}
 
 	pub(crate) fn is_left(&self) -> bool {
-		self.inner.process();
-		self.inner.is_left()
+		self.is(Ev3Button::is_left, Key::Backspace)
 	}
 
 	pub(crate) fn is_right(&self) -> bool {
-		self.inner.process();
-		self.inner.is_right()
+		self.is(Ev3Button::is_right, Key::Right)
 	}
 
 	pub(crate) fn is_enter(&self) -> bool {
-		self.inner.process();
-		self.inner.is_enter()
+		self.is(Ev3Button::is_enter, Key::Enter)
+	}
+}
+
+/// How often the thread looks at the buttons of the brick.
+const POLL_TIME: Duration = Duration::from_millis(10);
+
+/// Polls the buttons of the brick in the thread, and sends every event, until the [Buttons] are
+/// gone.
+fn poll(timing: Timing, sender: Sender<ButtonEvent>, started: Sender<Result<()>>) {
+	// The buttons of the brick can't move between threads, so we have our own.
+	let button = match Ev3Button::new() {
+		Ok(button) => {
+			let _ = started.send(Ok(()));
+			button
+		},
+		Err(err) => {
+			let _ = started.send(Err(anyhow::Error::new(err).context("Failed to create buttons")));
+			return;
+		},
+	};
+
+	let mut detector = Detector::new(timing);
+	let mut events = Vec::new();
+	loop {
+		button.process();
+		let held: Vec<Button> = [
+			(button.is_up(), Button::Up),
+			(button.is_down(), Button::Down),
+			(button.is_left(), Button::Left),
+			(button.is_right(), Button::Right),
+			(button.is_enter(), Button::Enter),
+		].into_iter().filter(|x| x.0).map(|x| x.1).collect();
+
+		detector.update(Instant::now(), &held, &mut events);
+		for event in events.drain(..) {
+			if sender.send(event).is_err() {
+				return;
+			}
+		}
+		std::thread::sleep(POLL_TIME);
 	}
 }
 
+/// Waits for the next key that is a button, or gives [Button::Left] when standard input ended.
+fn await_key() -> Button {
+	loop {
+		match keyboard::next() {
+			Some(Key::Up) => return Button::Up,
+			Some(Key::Down) => return Button::Down,
+			Some(Key::Left | Key::Backspace) => return Button::Left,
+			Some(Key::Right) => return Button::Right,
+			Some(Key::Enter) => return Button::Enter,
+			Some(Key::Char(_)) => {},
+			None if keyboard::is_closed() => return Button::Left,
+			None => std::thread::sleep(Duration::from_millis(10)),
+		}
+	}
+}
+
+/// A button that is down.
+#[derive(Debug)]
+struct Down {
+	button: Button,
+	since: Instant,
+	/// It already was a long press, or part of a chord, so it's no click anymore.
+	used: bool,
+}
+
+/// Turns which buttons are held, again and again, into [ButtonEvent]s.
 #[derive(Debug)]
+struct Detector {
+	timing: Timing,
+	down: Vec<Down>,
+	last_click: Option<(Button, Instant)>,
+}
+
+impl Detector {
+	fn new(timing: Timing) -> Detector {
+		Detector { timing, down: Vec::new(), last_click: None }
+	}
+
+	fn update(&mut self, now: Instant, held: &[Button], events: &mut Vec<ButtonEvent>) {
+		let start = events.len();
+
+		let (released, down): (Vec<Down>, Vec<Down>) = std::mem::take(&mut self.down)
+			.into_iter()
+			.partition(|x| !held.contains(&x.button));
+		self.down = down;
+		for Down { button, used, .. } in released {
+			events.push(ButtonEvent::Release(button));
+			if used {
+				continue;
+			}
+			events.push(ButtonEvent::Click(button));
+			let double = self.last_click.is_some_and(|(last, time)| last == button
+				&& now.duration_since(time).as_secs_f64() <= self.timing.double_press);
+			if double {
+				events.push(ButtonEvent::DoublePress(button));
+				self.last_click = None;
+			} else {
+				self.last_click = Some((button, now));
+			}
+		}
+
+		for &button in held {
+			if self.down.iter().any(|x| x.button == button) {
+				continue;
+			}
+			events.push(ButtonEvent::Press(button));
+			let other = self.down.iter_mut().find(|x| !x.used);
+			let chord = other.is_some();
+			if let Some(other) = other {
+				other.used = true;
+				events.push(ButtonEvent::Chord(other.button, button));
+			}
+			self.down.push(Down { button, since: now, used: chord });
+		}
+
+		for down in &mut self.down {
+			if !down.used && now.duration_since(down.since).as_secs_f64() >= self.timing.long_press {
+				down.used = true;
+				events.push(ButtonEvent::LongPress(down.button));
+			}
+		}
+
+		for event in &events[start..] {
+			log::debug!(target: HARDWARE, "button: {event:?}");
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Button {
 	Up, Down, Left, Right, Enter
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn detector() -> Detector {
+		Detector::new(Timing { long_press: 1.0, double_press: 0.3 })
+	}
+
+	fn after(start: Instant, s: f64) -> Instant {
+		start + Duration::from_secs_f64(s)
+	}
+
+	#[test]
+	fn a_short_press_is_a_click_on_release() {
+		let mut detector = detector();
+		let start = Instant::now();
+		let mut events = Vec::new();
+		detector.update(start, &[Button::Enter], &mut events);
+		assert_eq!(events, [ButtonEvent::Press(Button::Enter)]);
+
+		events.clear();
+		detector.update(after(start, 0.2), &[Button::Enter], &mut events);
+		assert_eq!(events, []);
+
+		detector.update(after(start, 0.3), &[], &mut events);
+		assert_eq!(events, [ButtonEvent::Release(Button::Enter), ButtonEvent::Click(Button::Enter)]);
+	}
+
+	#[test]
+	fn a_long_press_comes_while_held_and_is_no_click() {
+		let mut detector = detector();
+		let start = Instant::now();
+		let mut events = Vec::new();
+		detector.update(start, &[Button::Up], &mut events);
+		detector.update(after(start, 0.9), &[Button::Up], &mut events);
+		assert_eq!(events, [ButtonEvent::Press(Button::Up)]);
+
+		events.clear();
+		detector.update(after(start, 1.0), &[Button::Up], &mut events);
+		assert_eq!(events, [ButtonEvent::LongPress(Button::Up)]);
+
+		events.clear();
+		detector.update(after(start, 2.0), &[Button::Up], &mut events);
+		detector.update(after(start, 2.1), &[], &mut events);
+		assert_eq!(events, [ButtonEvent::Release(Button::Up)]);
+	}
+
+	#[test]
+	fn a_second_click_soon_after_is_a_double_press() {
+		let mut detector = detector();
+		let start = Instant::now();
+		let mut events = Vec::new();
+		for (time, held) in [(0.0, true), (0.1, false), (0.2, true), (0.3, false)] {
+			detector.update(after(start, time), if held { &[Button::Down] } else { &[] }, &mut events);
+		}
+		assert_eq!(events, [
+			ButtonEvent::Press(Button::Down), ButtonEvent::Release(Button::Down), ButtonEvent::Click(Button::Down),
+			ButtonEvent::Press(Button::Down), ButtonEvent::Release(Button::Down), ButtonEvent::Click(Button::Down),
+			ButtonEvent::DoublePress(Button::Down),
+		]);
+
+		// Too late, or a third click, is only a click.
+		events.clear();
+		for (time, held) in [(0.4, true), (0.5, false), (1.0, true), (1.1, false)] {
+			detector.update(after(start, time), if held { &[Button::Down] } else { &[] }, &mut events);
+		}
+		assert!(!events.contains(&ButtonEvent::DoublePress(Button::Down)));
+		assert_eq!(events.iter().filter(|&&x| x == ButtonEvent::Click(Button::Down)).count(), 2);
+	}
+
+	#[test]
+	fn two_buttons_together_are_a_chord_and_no_clicks() {
+		let mut detector = detector();
+		let start = Instant::now();
+		let mut events = Vec::new();
+		detector.update(start, &[Button::Up], &mut events);
+		detector.update(after(start, 0.1), &[Button::Up, Button::Down], &mut events);
+		assert_eq!(events, [
+			ButtonEvent::Press(Button::Up),
+			ButtonEvent::Press(Button::Down), ButtonEvent::Chord(Button::Up, Button::Down),
+		]);
+
+		events.clear();
+		detector.update(after(start, 1.5), &[Button::Up, Button::Down], &mut events);
+		detector.update(after(start, 1.6), &[], &mut events);
+		assert_eq!(events, [ButtonEvent::Release(Button::Up), ButtonEvent::Release(Button::Down)]);
+	}
+}
@@ -1,9 +1,16 @@
-use anyhow::{Context, Result};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use ev3dev_lang_rust::{Ev3Result, Port};
 use ev3dev_lang_rust::motors::MotorPort;
 use ev3dev_lang_rust::sensors::SensorPort;
+use crate::hardware::{Hardware, MotorConfig};
 use crate::robot::button::Buttons;
 use crate::robot::motor::{Ev3LargeMotor, Ev3SmallMotor, LargeMotor, SmallMotor};
 use crate::robot::sensors::{ColorSensor, DistanceSensor, Ev3ColorSensor, Ev3DistanceSensor, Ev3TouchSensor, TouchSensor};
+use crate::line::LineSensor;
+use crate::logging::HARDWARE;
+use crate::sim::SimWorld;
+use crate::sim::world::Side;
 
 pub(crate) mod motor;
 pub(crate) mod button;
@@ -14,6 +21,9 @@ pub(crate) struct Robot {
 	pub(crate) buttons: Buttons,
 
 	pub(crate) color: ColorSensor,
+	/// The sensors for the line position, in the order of the settings. Without any, we follow
+	/// the edge with `color`.
+	pub(crate) line_sensors: Vec<ColorSensor>,
 	pub(crate) distance: DistanceSensor,
 	pub(crate) touch: TouchSensor,
 
@@ -21,62 +31,157 @@ pub(crate) struct Robot {
 	pub(crate) right: LargeMotor,
 
 	pub(crate) top_arm: SmallMotor,
+
+	world: Option<SimWorld>,
+}
+
+/// Parses a sensor port like `in1`.
+pub(crate) fn sensor_port(name: &str) -> Result<SensorPort> {
+	Ok(match name {
+		"in1" => SensorPort::In1,
+		"in2" => SensorPort::In2,
+		"in3" => SensorPort::In3,
+		"in4" => SensorPort::In4,
+		_ => bail!("No sensor port {name:?}, it needs to be one of in1, in2, in3 or in4"),
+	})
+}
+
+/// Parses a motor port like `outA`.
+pub(crate) fn motor_port(name: &str) -> Result<MotorPort> {
+	Ok(match name {
+		"outA" => MotorPort::OutA,
+		"outB" => MotorPort::OutB,
+		"outC" => MotorPort::OutC,
+		"outD" => MotorPort::OutD,
+		_ => bail!("No motor port {name:?}, it needs to be one of outA, outB, outC or outD"),
+	})
+}
+
+/// The drivers of the devices connected to `port`, e.g. `lego-ev3-touch`, or `nothing`.
+pub(crate) fn found_on(port: &dyn Port) -> String {
+	let address = port.address();
+	let mut found = Vec::new();
+	for class in ["tacho-motor", "dc-motor", "lego-sensor"] {
+		let Ok(devices) = std::fs::read_dir(Path::new("/sys/class").join(class)) else { continue };
+		for device in devices.flatten() {
+			let read = |name: &str| std::fs::read_to_string(device.path().join(name))
+				.map(|x| x.trim().to_owned());
+			if read("address").is_ok_and(|x| x.ends_with(&address)) {
+				found.push(read("driver_name").unwrap_or_else(|_| "an unknown device".to_owned()));
+			}
+		}
+	}
+
+	if found.is_empty() {
+		"nothing".to_owned()
+	} else {
+		found.join(", ")
+	}
+}
+
+/// Gets the device on `port`, or says what's there instead.
+fn get_device<T>(device: Ev3Result<T>, port: &dyn Port, desc: &str, expected: &str) -> Result<T> {
+	if device.is_ok() {
+		log::debug!(target: HARDWARE, "found the {desc} on {}", port.address());
+	}
+	device.with_context(|| format!(
+		"Failed to get the {desc} on {}: expected a {expected}, but found {}",
+		port.address(), found_on(port),
+	))
+}
+
+fn get_motor(config: &MotorConfig, desc: &str) -> Result<Ev3LargeMotor> {
+	let port = motor_port(&config.port)?;
+	let motor = get_device(Ev3LargeMotor::get(port), &port, desc, "lego-ev3-l-motor")?;
+	motor.set_polarity(config.polarity.name())
+		.with_context(|| format!("Failed to set the polarity of the {desc}"))?;
+	motor.set_stop_action(config.stop_action.name())
+		.with_context(|| format!("Failed to set the stop action of the {desc}"))?;
+	motor.set_speed_sp(motor.get_max_speed()?)?;
+	Ok(motor)
+}
+
+fn get_color(port: &str, desc: &str) -> Result<Ev3ColorSensor> {
+	let port = sensor_port(port)?;
+	get_device(Ev3ColorSensor::get(port), &port, desc, "lego-ev3-color")
 }
 
 impl Robot {
-	pub(crate) fn new() -> Result<Robot> {
+	pub(crate) fn new(hardware: &Hardware, line_sensors: &[LineSensor]) -> Result<Robot> {
+		hardware.check_ports(line_sensors.iter().map(|x| x.port.as_str()))?;
+
 		Ok(Robot {
-			buttons: Buttons::new()
+			buttons: Buttons::new(hardware.buttons, hardware.button_timing)
 				.context("Failed to get the robot buttons")?,
 
 			color: {
-				let color = Ev3ColorSensor::get(SensorPort::In1)
-					.context("Failed to get the color sensor")?;
+				let color = get_color(&hardware.color, "color sensor")?;
 				color.set_mode_col_reflect().context("Failed to set color mode")?;
 				ColorSensor::new(color)
 			},
+			line_sensors: line_sensors.iter()
+				.map(|x| Ok(ColorSensor::new(get_color(&x.port, "line sensor")?)))
+				.collect::<Result<_>>()?,
 			distance: {
-				let distance = Ev3DistanceSensor::get(SensorPort::In3)
-					.context("Failed to get the ultrasonic sensor")?;
+				let port = sensor_port(&hardware.distance)?;
+				let distance = get_device(Ev3DistanceSensor::get(port), &port, "ultrasonic sensor", "lego-ev3-us")?;
 				distance.set_mode_us_dist_cm().context("Failed to set distance mode")?;
 				DistanceSensor::new(distance)
 			},
 			touch: {
-				let touch = Ev3TouchSensor::get(SensorPort::In2)
-					.context("Failed to get the touch sensor")?;
+				let port = sensor_port(&hardware.touch)?;
+				let touch = get_device(Ev3TouchSensor::get(port), &port, "touch sensor", "lego-ev3-touch")?;
 				TouchSensor::new(touch)
 			},
 
-			left: {
-				let motor = Ev3LargeMotor::get(MotorPort::OutB)
-					.context("Failed to get the left motor")?;
-				//motor.set_polarity(Ev3LargeMotor::POLARITY_INVERSED)?;
-				motor.set_stop_action(Ev3LargeMotor::STOP_ACTION_BRAKE)?;
-				motor.set_speed_sp(motor.get_max_speed()?)?;
-				LargeMotor::new(motor, "left")
-			},
-			right: {
-				let motor = Ev3LargeMotor::get(MotorPort::OutA)
-					.context("Failed to get the right motor")?;
-				//motor.set_polarity(Ev3LargeMotor::POLARITY_INVERSED)?;
-				motor.set_stop_action(Ev3LargeMotor::STOP_ACTION_BRAKE)?;
-				motor.set_speed_sp(motor.get_max_speed()?)?;
-				LargeMotor::new(motor, "right")
-			},
+			left: LargeMotor::new(get_motor(&hardware.left, "left motor")?, "left", hardware.left.max_speed),
+			right: LargeMotor::new(get_motor(&hardware.right, "right motor")?, "right", hardware.right.max_speed),
 
 			top_arm: {
-				let motor = Ev3SmallMotor::get(MotorPort::OutC)
-					.context("Failed to get the medium motor")?;
-				motor.set_polarity(Ev3SmallMotor::POLARITY_INVERSED)?;
-				motor.set_stop_action(Ev3SmallMotor::STOP_ACTION_COAST)?;
+				let port = motor_port(&hardware.top_arm.port)?;
+				let motor = get_device(Ev3SmallMotor::get(port), &port, "medium motor", "lego-ev3-m-motor")?;
+				motor.set_polarity(hardware.top_arm.polarity.name())
+					.context("Failed to set the polarity of the medium motor")?;
+				motor.set_stop_action(hardware.top_arm.stop_action.name())
+					.context("Failed to set the stop action of the medium motor")?;
 				motor.set_speed_sp(motor.get_max_speed()?)?;
-				SmallMotor::new(motor, "top")
+				SmallMotor::new(motor, "top", hardware.top_arm.max_speed)
 			},
+
+			world: None,
+		})
+	}
+
+	/// A robot that drives in the simulated `world` instead.
+	pub(crate) fn simulated(world: SimWorld, hardware: &Hardware, line_sensors: &[LineSensor]) -> Result<Robot> {
+		Ok(Robot {
+			buttons: Buttons::simulated(hardware.buttons, hardware.button_timing)
+				.context("Failed to get the keyboard buttons")?,
+
+			color: ColorSensor::simulated(world.clone(), 0.0),
+			line_sensors: line_sensors.iter()
+				.map(|x| ColorSensor::simulated(world.clone(), x.offset))
+				.collect(),
+			distance: DistanceSensor::simulated(world.clone()),
+			touch: TouchSensor::simulated(),
+
+			left: LargeMotor::simulated(world.clone(), Side::Left, "left", hardware.left.max_speed),
+			right: LargeMotor::simulated(world.clone(), Side::Right, "right", hardware.right.max_speed),
+
+			top_arm: SmallMotor::simulated("top"),
+
+			world: Some(world),
 		})
 	}
 
+	pub(crate) fn is_simulated(&self) -> bool {
+		self.world.is_some()
+	}
+
 	pub(crate) fn beep(&self) -> Result<()> {
-		ev3dev_lang_rust::sound::beep()?;
+		if self.world.is_none() {
+			ev3dev_lang_rust::sound::beep()?;
+		}
 		Ok(())
 	}
 }
\ No newline at end of file
@@ -1,9 +1,17 @@
+use std::cell::Cell;
 use std::fmt::{Debug, Formatter};
-use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
 pub(crate) use ev3dev_lang_rust::motors::LargeMotor as Ev3LargeMotor;
 pub(crate) use ev3dev_lang_rust::motors::MediumMotor as Ev3SmallMotor;
+use crate::sim::SimWorld;
+use crate::sim::world::Side;
 
-fn fmt<'a, T: Debug, E>(value: &'a Result<T, E>) -> &'a dyn Debug {
+// The tacho motors of the EV3 have one count per degree. Reading `count_per_rot` every tick
+// would be one more file read, and in the debug output it seems to crash.
+const COUNT_PER_ROT: f64 = 360.0;
+
+fn fmt<T: Debug, E>(value: &Result<T, E>) -> &dyn Debug {
 	if let Ok(v) = value {
 		v
 	} else {
@@ -11,117 +19,220 @@ fn fmt<'a, T: Debug, E>(value: &'a Result<T, E>) -> &'a dyn Debug {
 	}
 }
 
+#[derive(Clone)]
+enum LargeInner {
+	Ev3(Ev3LargeMotor),
+	Sim(SimWorld, Side),
+}
+
 #[derive(Clone)]
 pub(crate) struct LargeMotor {
-	inner: Ev3LargeMotor,
+	inner: LargeInner,
 	desc: &'static str,
+	// The maximum speed in counts per second, if the motor runs speed regulated.
+	regulated: Cell<Option<i32>>,
+	// The highest speed we give the motor, in percent.
+	max_speed: i32,
 }
 
 impl Debug for LargeMotor {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let inner = match &self.inner {
+			LargeInner::Ev3(inner) => inner,
+			LargeInner::Sim(_, side) => {
+				return f.debug_struct("Motor")
+					.field("desc", &self.desc)
+					.field("simulated", side)
+					.finish();
+			},
+		};
 		f.debug_struct("Motor")
 			.field("desc", &self.desc)
-			.field("position", fmt(&self.inner.get_position()))
-			.field("position_sp", fmt(&self.inner.get_position_sp()))
-			.field("speed", fmt(&self.inner.get_speed()))
-			.field("speed_sp", fmt(&self.inner.get_speed_sp()))
-			.field("max_speed", fmt(&self.inner.get_max_speed()))
-			.field("duty_cycle", fmt(&self.inner.get_duty_cycle()))
-			.field("duty_cycle_sp", fmt(&self.inner.get_duty_cycle_sp()))
-			.field("polarity", fmt(&self.inner.get_polarity()))
-			.field("time_sp", fmt(&self.inner.get_time_sp()))
-			.field("stop_action", fmt(&self.inner.get_stop_action()))
+			.field("position", fmt(&inner.get_position()))
+			.field("position_sp", fmt(&inner.get_position_sp()))
+			.field("speed", fmt(&inner.get_speed()))
+			.field("speed_sp", fmt(&inner.get_speed_sp()))
+			.field("max_speed", fmt(&inner.get_max_speed()))
+			.field("duty_cycle", fmt(&inner.get_duty_cycle()))
+			.field("duty_cycle_sp", fmt(&inner.get_duty_cycle_sp()))
+			.field("polarity", fmt(&inner.get_polarity()))
+			.field("time_sp", fmt(&inner.get_time_sp()))
+			.field("stop_action", fmt(&inner.get_stop_action()))
 			// seems to crash here
-			//.field("count_per_m", fmt(&self.inner.get_count_per_m()))
-			//.field("count_per_rot", fmt(&self.inner.get_count_per_rot()))
-			//.field("full_travel_count", fmt(&self.inner.get_full_travel_count()))
-			.field("ramp_down_sp", fmt(&self.inner.get_ramp_down_sp()))
-			.field("ramp_up_sp", fmt(&self.inner.get_ramp_up_sp()))
+			//.field("count_per_m", fmt(&inner.get_count_per_m()))
+			//.field("count_per_rot", fmt(&inner.get_count_per_rot()))
+			//.field("full_travel_count", fmt(&inner.get_full_travel_count()))
+			.field("ramp_down_sp", fmt(&inner.get_ramp_down_sp()))
+			.field("ramp_up_sp", fmt(&inner.get_ramp_up_sp()))
 			.finish()
 	}
 }
 
 impl LargeMotor {
-	pub(crate) fn new(inner: Ev3LargeMotor, desc: &'static str) -> LargeMotor {
-		LargeMotor { inner, desc }
+	pub(crate) fn new(inner: Ev3LargeMotor, desc: &'static str, max_speed: f64) -> LargeMotor {
+		LargeMotor { inner: LargeInner::Ev3(inner), desc, regulated: Cell::new(None), max_speed: max_speed as i32 }
+	}
+
+	pub(crate) fn simulated(world: SimWorld, side: Side, desc: &'static str, max_speed: f64) -> LargeMotor {
+		LargeMotor { inner: LargeInner::Sim(world, side), desc, regulated: Cell::new(None), max_speed: max_speed as i32 }
 	}
 
 	pub(crate) fn start(&self) -> Result<()> {
-		self.inner.run_direct().with_context(|| anyhow!("Failed to run motor {}", self.desc))
+		match &self.inner {
+			LargeInner::Ev3(_) if self.regulated.get().is_some() => Ok(()),
+			LargeInner::Ev3(inner) => inner.run_direct().with_context(|| anyhow!("Failed to run motor {}", self.desc)),
+			LargeInner::Sim(..) => Ok(()),
+		}
+	}
+
+	/// With `Some((up, down))` the motor runs speed regulated and takes `up` to get from zero to
+	/// the maximum speed and `down` back. With `None` it runs with a duty cycle again.
+	///
+	/// The simulated motor ignores the ramp.
+	pub(crate) fn set_ramp(&self, ramp: Option<(Duration, Duration)>) -> Result<()> {
+		let LargeInner::Ev3(inner) = &self.inner else { return Ok(()) };
+
+		let Some((up, down)) = ramp else {
+			self.regulated.set(None);
+			return Ok(());
+		};
+		inner.set_ramp_up_sp(up.as_millis() as i32)
+			.with_context(|| anyhow!("Failed to set the ramp up of motor {}", self.desc))?;
+		inner.set_ramp_down_sp(down.as_millis() as i32)
+			.with_context(|| anyhow!("Failed to set the ramp down of motor {}", self.desc))?;
+		let max_speed = inner.get_max_speed()
+			.with_context(|| anyhow!("Failed to get the maximum speed of motor {}", self.desc))?;
+		self.regulated.set(Some(max_speed));
+		Ok(())
 	}
 
 	pub(crate) fn set_speed(&self, speed: f64) -> Result<()> {
-		let velocity = (speed as i32).clamp(-100, 100);
-		self.inner.set_duty_cycle_sp(velocity).with_context(|| anyhow!("Failed to set speed {velocity} (from {speed}) for {}", self.desc))
+		let velocity = (speed as i32).clamp(-self.max_speed, self.max_speed);
+		match &self.inner {
+			LargeInner::Ev3(inner) => if let Some(max_speed) = self.regulated.get() {
+				// A new `speed_sp` only counts with the next command.
+				inner.set_speed_sp(velocity * max_speed / 100)
+					.and_then(|_| inner.run_forever())
+					.with_context(|| anyhow!("Failed to set regulated speed {velocity} (from {speed}) for {}", self.desc))
+			} else {
+				inner.set_duty_cycle_sp(velocity).with_context(|| anyhow!("Failed to set speed {velocity} (from {speed}) for {}", self.desc))
+			},
+			LargeInner::Sim(world, side) => {
+				world.borrow_mut().set_duty(*side, velocity as f64);
+				Ok(())
+			},
+		}
 	}
 
 	pub(crate) fn stop(&self) -> Result<()> {
-		self.inner.stop().with_context(|| anyhow!("Failed to stop motor {}", self.desc))
+		match &self.inner {
+			LargeInner::Ev3(inner) => inner.stop().with_context(|| anyhow!("Failed to stop motor {}", self.desc)),
+			LargeInner::Sim(world, side) => {
+				world.borrow_mut().set_duty(*side, 0.0);
+				Ok(())
+			},
+		}
+	}
+
+	/// The position of the motor in rotations.
+	pub(crate) fn get_rotations(&self) -> Result<f64> {
+		match &self.inner {
+			LargeInner::Ev3(inner) => {
+				let position = inner.get_position()
+					.with_context(|| anyhow!("Failed to get position of motor {}", self.desc))?;
+				Ok(position as f64 / COUNT_PER_ROT)
+			},
+			LargeInner::Sim(world, side) => Ok(world.borrow().rotations(*side)),
+		}
 	}
 
 	pub(crate) fn step(&self, rotations: f64) -> Result<()> {
-		let count_per_rot = self.inner.get_count_per_rot()? as f64;
+		let LargeInner::Ev3(inner) = &self.inner else {
+			bail!("Can't step the simulated motor {}", self.desc);
+		};
+
+		let count_per_rot = inner.get_count_per_rot()? as f64;
 
 		let delta_pos = count_per_rot * rotations;
 
-		self.inner.run_to_rel_pos(Some(delta_pos as i32))?;
+		inner.run_to_rel_pos(Some(delta_pos as i32))?;
 
 		Ok(())
 	}
 }
 
+/// The top arm, which does nothing in the simulation.
 #[derive(Clone)]
 pub(crate) struct SmallMotor {
-	inner: Ev3SmallMotor,
+	inner: Option<Ev3SmallMotor>,
 	desc: &'static str,
+	// The highest speed we give the motor, in percent.
+	max_speed: i32,
 }
 
 impl Debug for SmallMotor {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let Some(inner) = &self.inner else {
+			return f.debug_struct("Motor")
+				.field("desc", &self.desc)
+				.field("simulated", &true)
+				.finish();
+		};
 		f.debug_struct("Motor")
 			.field("desc", &self.desc)
-			.field("position", fmt(&self.inner.get_position()))
-			.field("position_sp", fmt(&self.inner.get_position_sp()))
-			.field("speed", fmt(&self.inner.get_speed()))
-			.field("speed_sp", fmt(&self.inner.get_speed_sp()))
-			.field("max_speed", fmt(&self.inner.get_max_speed()))
-			.field("duty_cycle", fmt(&self.inner.get_duty_cycle()))
-			.field("duty_cycle_sp", fmt(&self.inner.get_duty_cycle_sp()))
-			.field("polarity", fmt(&self.inner.get_polarity()))
-			.field("time_sp", fmt(&self.inner.get_time_sp()))
-			.field("stop_action", fmt(&self.inner.get_stop_action()))
+			.field("position", fmt(&inner.get_position()))
+			.field("position_sp", fmt(&inner.get_position_sp()))
+			.field("speed", fmt(&inner.get_speed()))
+			.field("speed_sp", fmt(&inner.get_speed_sp()))
+			.field("max_speed", fmt(&inner.get_max_speed()))
+			.field("duty_cycle", fmt(&inner.get_duty_cycle()))
+			.field("duty_cycle_sp", fmt(&inner.get_duty_cycle_sp()))
+			.field("polarity", fmt(&inner.get_polarity()))
+			.field("time_sp", fmt(&inner.get_time_sp()))
+			.field("stop_action", fmt(&inner.get_stop_action()))
 			// seems to crash here
-			//.field("count_per_m", fmt(&self.inner.get_count_per_m()))
-			//.field("count_per_rot", fmt(&self.inner.get_count_per_rot()))
-			//.field("full_travel_count", fmt(&self.inner.get_full_travel_count()))
-			.field("ramp_down_sp", fmt(&self.inner.get_ramp_down_sp()))
-			.field("ramp_up_sp", fmt(&self.inner.get_ramp_up_sp()))
+			//.field("count_per_m", fmt(&inner.get_count_per_m()))
+			//.field("count_per_rot", fmt(&inner.get_count_per_rot()))
+			//.field("full_travel_count", fmt(&inner.get_full_travel_count()))
+			.field("ramp_down_sp", fmt(&inner.get_ramp_down_sp()))
+			.field("ramp_up_sp", fmt(&inner.get_ramp_up_sp()))
 			.finish()
 	}
 }
 
 impl SmallMotor {
-	pub(crate) fn new(inner: Ev3SmallMotor, desc: &'static str) -> SmallMotor {
-		SmallMotor { inner, desc }
+	pub(crate) fn new(inner: Ev3SmallMotor, desc: &'static str, max_speed: f64) -> SmallMotor {
+		SmallMotor { inner: Some(inner), desc, max_speed: max_speed as i32 }
+	}
+
+	pub(crate) fn simulated(desc: &'static str) -> SmallMotor {
+		SmallMotor { inner: None, desc, max_speed: 100 }
 	}
 
 	pub(crate) fn start_with_full_power(&self) -> Result<()> {
-		self.inner.run_direct().with_context(|| anyhow!("Failed to run motor {}", self.desc))?;
-		self.inner.set_duty_cycle_sp(100).with_context(|| anyhow!("Failed to set speed 100 for {}", self.desc))
+		let Some(inner) = &self.inner else { return Ok(()) };
+		inner.run_direct().with_context(|| anyhow!("Failed to run motor {}", self.desc))?;
+		inner.set_duty_cycle_sp(self.max_speed).with_context(|| anyhow!("Failed to set speed {} for {}", self.max_speed, self.desc))
 	}
 
 	pub(crate) fn set_speed(&self, speed: f64) -> Result<()> {
-		let mut speed = speed as i32;
-		if speed > 100 {
-			speed = 100;
-		}
-		if speed < -100 {
-			speed = -100;
-		}
-		self.inner.set_duty_cycle_sp(speed).with_context(|| anyhow!("Failed to set speed {speed} for {}", self.desc))
+		let Some(inner) = &self.inner else { return Ok(()) };
+		let speed = (speed as i32).clamp(-self.max_speed, self.max_speed);
+		inner.set_duty_cycle_sp(speed).with_context(|| anyhow!("Failed to set speed {speed} for {}", self.desc))
 	}
 
 	pub(crate) fn stop(&self) -> Result<()> {
-		self.inner.stop().with_context(|| anyhow!("Failed to stop motor {}", self.desc))
+		let Some(inner) = &self.inner else { return Ok(()) };
+		inner.stop().with_context(|| anyhow!("Failed to stop motor {}", self.desc))
 	}
-}
\ No newline at end of file
+
+	/// The position of the motor in rotations.
+	pub(crate) fn get_rotations(&self) -> Result<f64> {
+		let Some(inner) = &self.inner else {
+			bail!("Can't get the position of the simulated motor {}", self.desc);
+		};
+		let position = inner.get_position()
+			.with_context(|| anyhow!("Failed to get position of motor {}", self.desc))?;
+		Ok(position as f64 / COUNT_PER_ROT)
+	}
+}
@@ -1,12 +1,15 @@
+use std::cell::Cell;
 use std::fmt::{Debug, Formatter};
 use anyhow::{Context, Result};
+use crate::color::{ColorMode, ColorSettings};
+use crate::sim::SimWorld;
 pub(crate) use ev3dev_lang_rust::sensors::{
 	ColorSensor as Ev3ColorSensor,
 	TouchSensor as Ev3TouchSensor,
 	UltrasonicSensor as Ev3DistanceSensor
 };
 
-fn fmt<'a, T: Debug, E>(value: &'a Result<T, E>) -> &'a dyn Debug {
+fn fmt<T: Debug, E>(value: &Result<T, E>) -> &dyn Debug {
 	if let Ok(v) = value {
 		v
 	} else {
@@ -14,55 +17,148 @@ fn fmt<'a, T: Debug, E>(value: &'a Result<T, E>) -> &'a dyn Debug {
 	}
 }
 
+enum ColorInner {
+	Ev3(Ev3ColorSensor),
+	/// With the offset of the sensor to the right, in `cm`.
+	Sim(SimWorld, f64),
+}
+
 pub(crate) struct ColorSensor {
-	inner: Ev3ColorSensor,
+	inner: ColorInner,
+	settings: Cell<ColorSettings>,
+	// Red, green and blue of the last reading in `RGB-RAW`, in percent.
+	rgb: Cell<Option<[f64; 3]>>,
 }
 
 impl Debug for ColorSensor {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let ColorInner::Ev3(inner) = &self.inner else {
+			return f.debug_struct("Color")
+				.field("simulated", &true)
+				.finish();
+		};
 		f.debug_struct("Color")
-			.field("color", fmt(&self.inner.get_color()))
-			.field("red", fmt(&self.inner.get_red()))
-			.field("green", fmt(&self.inner.get_green()))
-			.field("blue", fmt(&self.inner.get_blue()))
+			.field("mode", &self.settings.get().mode)
+			.field("color", fmt(&inner.get_color()))
+			.field("red", fmt(&inner.get_red()))
+			.field("green", fmt(&inner.get_green()))
+			.field("blue", fmt(&inner.get_blue()))
 			.finish()
 	}
 }
 
 impl ColorSensor {
 	pub(crate) fn new(inner: Ev3ColorSensor) -> ColorSensor {
-		ColorSensor { inner }
+		ColorSensor::with(ColorInner::Ev3(inner))
+	}
+
+	pub(crate) fn simulated(world: SimWorld, offset: f64) -> ColorSensor {
+		ColorSensor::with(ColorInner::Sim(world, offset))
+	}
+
+	fn with(inner: ColorInner) -> ColorSensor {
+		ColorSensor {
+			inner,
+			settings: Cell::new(ColorSettings::default()),
+			rgb: Cell::new(None),
+		}
+	}
+
+	/// Switches the sensor into the mode of the `settings`, and uses them for the reflection.
+	pub(crate) fn configure(&self, settings: ColorSettings) -> Result<()> {
+		if let ColorInner::Ev3(inner) = &self.inner {
+			match settings.mode {
+				ColorMode::ColReflect => inner.set_mode_col_reflect(),
+				ColorMode::RefRaw => inner.set_mode_ref_raw(),
+				ColorMode::RgbRaw => inner.set_mode_rgb_raw(),
+			}.with_context(|| format!("Failed to set color mode {}", settings.mode.name()))?;
+		}
+		self.settings.set(settings);
+		self.rgb.set(None);
+		Ok(())
 	}
 
+	/// The reflection in percent, in every mode.
 	pub(crate) fn get_color(&self) -> Result<f64> {
-		let color = self.inner.get_color()
-			.context("Failed to get color from sensor")?;
-		Ok(color as f64)
+		let settings = self.settings.get();
+		let inner = match &self.inner {
+			ColorInner::Ev3(inner) => inner,
+			ColorInner::Sim(world, offset) => {
+				// The simulated track is only black and white.
+				let reflection = world.borrow_mut().reflection(*offset);
+				if settings.mode == ColorMode::RgbRaw {
+					self.rgb.set(Some([reflection; 3]));
+				}
+				return Ok(reflection);
+			},
+		};
+
+		match settings.mode {
+			ColorMode::ColReflect => {
+				let color = inner.get_color()
+					.context("Failed to get color from sensor")?;
+				Ok(color as f64)
+			},
+			ColorMode::RefRaw => {
+				let raw = inner.get_color()
+					.context("Failed to get raw reflection from sensor")?;
+				Ok(settings.scale_raw(raw as f64))
+			},
+			ColorMode::RgbRaw => {
+				let (red, green, blue) = inner.get_rgb()
+					.context("Failed to get rgb from sensor")?;
+				let rgb = settings.scale_rgb([red as f64, green as f64, blue as f64]);
+				self.rgb.set(Some(rgb));
+				Ok(settings.mix_rgb(rgb))
+			},
+		}
 	}
+
+	/// Red, green and blue in percent of white, from the last [ColorSensor::get_color] in `RGB-RAW`.
+	pub(crate) fn get_rgb(&self) -> Option<[f64; 3]> {
+		self.rgb.get()
+	}
+}
+
+enum DistanceInner {
+	Ev3(Ev3DistanceSensor),
+	Sim(SimWorld),
 }
 
 pub(crate) struct DistanceSensor {
-	inner: Ev3DistanceSensor,
+	inner: DistanceInner,
 }
 
 impl Debug for DistanceSensor {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let DistanceInner::Ev3(inner) = &self.inner else {
+			return f.debug_struct("Distance")
+				.field("simulated", &true)
+				.finish();
+		};
 		f.debug_struct("Distance")
-			.field("distance", fmt(&self.inner.get_distance_centimeters()))
+			.field("distance", fmt(&inner.get_distance_centimeters()))
 			.finish()
 	}
 }
 
 impl DistanceSensor {
 	pub(crate) fn new(inner: Ev3DistanceSensor) -> DistanceSensor {
-		DistanceSensor { inner }
+		DistanceSensor { inner: DistanceInner::Ev3(inner) }
+	}
+
+	pub(crate) fn simulated(world: SimWorld) -> DistanceSensor {
+		DistanceSensor { inner: DistanceInner::Sim(world) }
 	}
 
 	/// Gets the distance in `cm`, or [None] if either too far away or too close.
 	/// `0 ..= 254.0`
 	pub(crate) fn get_distance(&self) -> Result<Option<f64>> {
-		let distance = self.inner.get_distance_centimeters()
-			.context("Failed to get the distance from sensor")?;
+		let distance = match &self.inner {
+			DistanceInner::Ev3(inner) => inner.get_distance_centimeters()
+				.context("Failed to get the distance from sensor")?,
+			DistanceInner::Sim(world) => return Ok(world.borrow_mut().distance()),
+		};
 		if distance == 255.0 {
 			Ok(None)
 		} else {
@@ -71,25 +167,36 @@ impl DistanceSensor {
 	}
 }
 
+/// The touch sensor, which is never pressed in the simulation.
 pub(crate) struct TouchSensor {
-	inner: Ev3TouchSensor,
+	inner: Option<Ev3TouchSensor>,
 }
 
 impl Debug for TouchSensor {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let Some(inner) = &self.inner else {
+			return f.debug_struct("Touch")
+				.field("simulated", &true)
+				.finish();
+		};
 		f.debug_struct("Touch")
-			.field("pressed", fmt(&self.inner.get_pressed_state()))
+			.field("pressed", fmt(&inner.get_pressed_state()))
 			.finish()
 	}
 }
 
 impl TouchSensor {
 	pub(crate) fn new(inner: Ev3TouchSensor) -> TouchSensor {
-		TouchSensor { inner }
+		TouchSensor { inner: Some(inner) }
+	}
+
+	pub(crate) fn simulated() -> TouchSensor {
+		TouchSensor { inner: None }
 	}
 
 	pub(crate) fn is_pressed(&self) -> Result<bool> {
-		self.inner.get_pressed_state()
+		let Some(inner) = &self.inner else { return Ok(false) };
+		inner.get_pressed_state()
 			.context("Failed to get press state from sensor")
 	}
 }
\ No newline at end of file
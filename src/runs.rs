@@ -0,0 +1,314 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::logging::{self, RUN};
+use crate::program::Program;
+use crate::recorder;
+use crate::state::RobotState;
+use crate::telemetry::TickRecord;
+
+const SETTINGS: &str = "settings.toml";
+const TELEMETRY: &str = "telemetry.csv";
+const LOG: &str = "log.txt";
+const EVENTS: &str = "events.log";
+const ERROR: &str = "error.log";
+const SUMMARY: &str = "summary.toml";
+
+/// Where we keep a directory for every drive, see [Run].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Runs {
+	pub(crate) enabled: bool,
+	/// The directory of all runs, relative to the one of the settings file.
+	pub(crate) dir: PathBuf,
+	/// How many runs we keep, the oldest ones go first. With `0` we keep all of them.
+	pub(crate) keep: usize,
+}
+
+impl Default for Runs {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			dir: PathBuf::from("runs"),
+			keep: 50,
+		}
+	}
+}
+
+impl Runs {
+	/// The directory of all runs, for the settings from `settings_path`.
+	pub(crate) fn dir(&self, settings_path: &Path) -> PathBuf {
+		settings_path.parent().unwrap_or(Path::new(".")).join(&self.dir)
+	}
+
+	/// All runs, from the oldest to the newest.
+	pub(crate) fn list(&self, settings_path: &Path) -> Result<Vec<PathBuf>> {
+		let dir = self.dir(settings_path);
+		if !dir.exists() {
+			return Ok(Vec::new());
+		}
+		let mut runs = std::fs::read_dir(&dir)
+			.with_context(|| format!("Failed to read the runs in {dir:?}"))?
+			.filter_map(|x| x.ok())
+			.map(|x| x.path())
+			.filter(|x| x.join(SETTINGS).exists())
+			.collect::<Vec<_>>();
+		// The names start with the time, so this sorts by it.
+		runs.sort();
+		Ok(runs)
+	}
+
+	/// Creates the directory of a new run with the effective `settings`, which starts at `tick` in
+	/// `state`, and removes the oldest runs over the limit.
+	pub(crate) fn start(&self, settings_path: &Path, settings: &str, tick: usize, state: &RobotState) -> Result<Run> {
+		let dir = self.dir(settings_path);
+		std::fs::create_dir_all(&dir)
+			.with_context(|| format!("Failed to create the runs directory {dir:?}"))?;
+
+		let started = timestamp(SystemTime::now());
+		let mut path = dir.join(&started);
+		for i in 2.. {
+			if !path.exists() {
+				break;
+			}
+			path = dir.join(format!("{started}-{i}"));
+		}
+		std::fs::create_dir(&path)
+			.with_context(|| format!("Failed to create the run directory {path:?}"))?;
+		std::fs::write(path.join(SETTINGS), settings)
+			.context("Failed to write the settings of the run")?;
+
+		let create = |name: &str| File::create(path.join(name))
+			.map(BufWriter::new)
+			.with_context(|| format!("Failed to create {name} of the run"));
+		let mut telemetry = create(TELEMETRY)?;
+		writeln!(telemetry, "{}", TickRecord::CSV_HEADER)
+			.context("Failed to write the telemetry of the run")?;
+		let mut events = create(EVENTS)?;
+		writeln!(events, "{:>8.2}s start in {state:?}", 0.0)
+			.context("Failed to write the events of the run")?;
+		let run = Run {
+			started,
+			start_tick: tick,
+			last_tick: tick,
+			ticks: 0,
+			states: vec![format!("{state:?}")],
+			telemetry,
+			events,
+			failed: false,
+			path,
+		};
+
+		if self.keep > 0 {
+			let runs = self.list(settings_path)?;
+			for old in runs.iter().take(runs.len().saturating_sub(self.keep)) {
+				std::fs::remove_dir_all(old)
+					.with_context(|| format!("Failed to remove the old run {old:?}"))?;
+			}
+		}
+
+		// Only now, so they don't stay with a run that failed to start.
+		logging::capture(&run.path.join(LOG))?;
+		recorder::set_run(Some(&run.path));
+		Ok(run)
+	}
+
+	/// Prints one line for every run.
+	pub(crate) fn print_list(&self, settings_path: &Path) -> Result<()> {
+		let runs = self.list(settings_path)?;
+		if runs.is_empty() {
+			println!("no runs in {:?}", self.dir(settings_path));
+		}
+		for run in runs {
+			let name = run.file_name().unwrap_or_default().to_string_lossy();
+			match read_summary(&run) {
+				Some(summary) => println!("{name}  {:>6.1}s  {:>6} ticks  {:<5}  {}",
+					summary.time, summary.ticks, summary.result, summary.states.join(" -> "),
+				),
+				None => println!("{name}  unfinished"),
+			}
+		}
+		Ok(())
+	}
+
+	/// Prints everything we know about the run `name`, or about the newest one.
+	pub(crate) fn show(&self, settings_path: &Path, name: Option<&str>) -> Result<()> {
+		let runs = self.list(settings_path)?;
+		let run = match name {
+			Some(name) => runs.iter()
+				.find(|x| x.file_name().is_some_and(|x| x == name))
+				.with_context(|| format!("No run {name:?} in {:?}", self.dir(settings_path)))?,
+			None => runs.last()
+				.with_context(|| format!("No runs in {:?}", self.dir(settings_path)))?,
+		};
+
+		println!("run {}", run.display());
+		match read_summary(run) {
+			Some(summary) => {
+				println!("started {}, {} ticks in {:.1}s, {}", summary.started, summary.ticks, summary.time, summary.result);
+				println!("states: {}", summary.states.join(" -> "));
+			},
+			None => println!("unfinished"),
+		}
+		for name in [EVENTS, ERROR, recorder::LOG, SETTINGS] {
+			let Ok(content) = std::fs::read_to_string(run.join(name)) else { continue };
+			println!();
+			println!("--- {name}");
+			print!("{content}");
+		}
+		println!();
+		println!("--- the telemetry is in {}", run.join(TELEMETRY).display());
+		if run.join(recorder::TELEMETRY).exists() {
+			println!("--- the telemetry before {} is in {}", recorder::LOG, run.join(recorder::TELEMETRY).display());
+		}
+		Ok(())
+	}
+}
+
+/// What we know about a run at its end.
+#[derive(Debug, Deserialize, Serialize)]
+struct Summary {
+	started: String,
+	ticks: usize,
+	/// The time of the drive in `s`.
+	time: f64,
+	/// Every state we were in, in order, up to the one after the drive.
+	states: Vec<String>,
+	/// `ok` or `error`.
+	result: String,
+}
+
+fn read_summary(run: &Path) -> Option<Summary> {
+	let string = std::fs::read_to_string(run.join(SUMMARY)).ok()?;
+	toml::from_str(&string).ok()
+}
+
+/// The directory of one drive, from the first drive state until we leave the drive states
+/// again.
+///
+/// We don't want to stop the robot because the SD card is full, so after the start, we only
+/// report the first failed write and keep driving.
+#[derive(Debug)]
+pub(crate) struct Run {
+	path: PathBuf,
+	started: String,
+	start_tick: usize,
+	last_tick: usize,
+	/// The amount of tick records.
+	ticks: usize,
+	states: Vec<String>,
+	telemetry: BufWriter<File>,
+	events: BufWriter<File>,
+	failed: bool,
+}
+
+impl Run {
+	fn check(&mut self, result: std::io::Result<()>) {
+		if let Err(err) = result {
+			if !self.failed {
+				log::warn!(target: RUN, "Failed to write the run {:?}: {err}", self.path);
+			}
+			self.failed = true;
+		}
+	}
+
+	/// The time since the start, in `s`.
+	fn time(&self) -> f64 {
+		(self.last_tick - self.start_tick) as f64 * Program::TICK_TIME.as_secs_f64()
+	}
+
+	pub(crate) fn record(&mut self, record: &TickRecord) {
+		self.ticks += 1;
+		self.last_tick = self.last_tick.max(record.tick);
+		let result = writeln!(self.telemetry, "{}", record.csv());
+		self.check(result);
+	}
+
+	/// We switched from the state `from` to `to` in `tick`.
+	pub(crate) fn transition(&mut self, tick: usize, from: &RobotState, to: &RobotState) {
+		self.last_tick = self.last_tick.max(tick);
+		let name = format!("{to:?}");
+		if self.states.last() != Some(&name) {
+			self.states.push(name);
+		}
+		let result = writeln!(self.events, "{:>8.2}s {from:?} -> {to:?}", self.time());
+		self.check(result);
+	}
+
+	/// Writes the summary, and the error chain if the run failed.
+	pub(crate) fn finish(mut self, error: Option<&anyhow::Error>) {
+		recorder::set_run(None);
+		if let Some(error) = error {
+			let result = std::fs::write(self.path.join(ERROR), format!("{error:?}\n"));
+			self.check(result);
+		}
+		let summary = Summary {
+			started: self.started.clone(),
+			ticks: self.ticks,
+			time: self.time(),
+			states: std::mem::take(&mut self.states),
+			result: if error.is_some() { "error" } else { "ok" }.to_owned(),
+		};
+		let result = toml::to_string(&summary)
+			.map_err(std::io::Error::other)
+			.and_then(|x| std::fs::write(self.path.join(SUMMARY), x));
+		self.check(result);
+
+		let result = self.telemetry.flush()
+			.and_then(|_| logging::end_capture())
+			.and_then(|_| self.events.flush());
+		self.check(result);
+	}
+}
+
+/// The UTC time like `2023-11-29T14-05-09`, which works as a file name everywhere.
+pub(crate) fn timestamp(time: SystemTime) -> String {
+	let seconds = time.duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+	let (days, rest) = ((seconds / 86400) as i64, seconds % 86400);
+	let (year, month, day) = civil_from_days(days);
+	format!("{year:04}-{month:02}-{day:02}T{:02}-{:02}-{:02}", rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+/// The year, month and day of the `days` since 1970-01-01, from
+/// http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let day_of_era = z.rem_euclid(146097);
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let mp = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = year_of_era + era * 400 + i64::from(month <= 2);
+	(year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn days_to_dates() {
+		assert_eq!(civil_from_days(0), (1970, 1, 1));
+		assert_eq!(civil_from_days(-1), (1969, 12, 31));
+		assert_eq!(civil_from_days(11016), (2000, 2, 29));
+		assert_eq!(civil_from_days(19782), (2024, 2, 29));
+		assert_eq!(civil_from_days(19783), (2024, 3, 1));
+		// 2100 is no leap year.
+		assert_eq!(civil_from_days(47540), (2100, 2, 28));
+		assert_eq!(civil_from_days(47541), (2100, 3, 1));
+	}
+
+	#[test]
+	fn timestamps() {
+		assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00-00-00");
+		assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(1701266709)), "2023-11-29T14-05-09");
+		assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(1709251199)), "2024-02-29T23-59-59");
+		assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(1709251200)), "2024-03-01T00-00-00");
+	}
+}
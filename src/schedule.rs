@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use crate::autotune::Gains;
+
+/// One row of the gain schedule of the line PID.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct GainPoint {
+	/// The speed in percent these gains belong to.
+	pub(crate) speed: f64,
+	/// The curvature in `1/cm` these gains belong to, without a sign. Without it the row is used
+	/// for every curvature.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub(crate) curvature: Option<f64>,
+	pub(crate) k_p: f64,
+	pub(crate) k_i: f64,
+	pub(crate) k_d: f64,
+}
+
+/// The gains of the schedule at `speed` and `curvature`, interpolated linearly between the rows,
+/// or `None` for an empty schedule.
+///
+/// Outside of the rows we keep the gains of the closest row.
+pub(crate) fn gains(schedule: &[GainPoint], speed: f64, curvature: f64) -> Option<Gains> {
+	let mut curvatures: Vec<f64> = schedule.iter().filter_map(|x| x.curvature).collect();
+	curvatures.sort_by(f64::total_cmp);
+	curvatures.dedup();
+
+	if curvatures.is_empty() {
+		let rows: Vec<&GainPoint> = schedule.iter().collect();
+		return by_speed(&rows, speed);
+	}
+
+	let points: Vec<(f64, Gains)> = curvatures.iter()
+		.filter_map(|&key| {
+			let rows: Vec<&GainPoint> = schedule.iter()
+				.filter(|x| x.curvature.is_none_or(|x| x == key))
+				.collect();
+			by_speed(&rows, speed).map(|gains| (key, gains))
+		})
+		.collect();
+	interpolate(&points, curvature.abs())
+}
+
+fn by_speed(rows: &[&GainPoint], speed: f64) -> Option<Gains> {
+	let mut points: Vec<(f64, Gains)> = rows.iter()
+		.map(|x| (x.speed, Gains { k_p: x.k_p, k_i: x.k_i, k_d: x.k_d }))
+		.collect();
+	points.sort_by(|a, b| a.0.total_cmp(&b.0));
+	interpolate(&points, speed)
+}
+
+// The points need to be sorted by their key.
+fn interpolate(points: &[(f64, Gains)], key: f64) -> Option<Gains> {
+	let (first, last) = (points.first()?, points.last()?);
+	if key <= first.0 {
+		return Some(first.1);
+	}
+	if key >= last.0 {
+		return Some(last.1);
+	}
+
+	let upper = points.iter().position(|x| x.0 >= key)?;
+	let (a, b) = (&points[upper - 1], &points[upper]);
+	let t = (key - a.0) / (b.0 - a.0);
+	let lerp = |a: f64, b: f64| a + t * (b - a);
+	Some(Gains {
+		k_p: lerp(a.1.k_p, b.1.k_p),
+		k_i: lerp(a.1.k_i, b.1.k_i),
+		k_d: lerp(a.1.k_d, b.1.k_d),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn row(speed: f64, curvature: Option<f64>, k_p: f64) -> GainPoint {
+		GainPoint { speed, curvature, k_p, k_i: k_p / 10.0, k_d: k_p * 10.0 }
+	}
+
+	fn k_p(schedule: &[GainPoint], speed: f64, curvature: f64) -> Option<f64> {
+		gains(schedule, speed, curvature).map(|x| x.k_p)
+	}
+
+	#[test]
+	fn empty_schedule() {
+		assert_eq!(gains(&[], 50.0, 0.0), None);
+	}
+
+	#[test]
+	fn one_row_everywhere() {
+		let schedule = [row(50.0, None, -4.0)];
+		assert_eq!(k_p(&schedule, 0.0, 0.0), Some(-4.0));
+		assert_eq!(k_p(&schedule, 50.0, 0.1), Some(-4.0));
+		assert_eq!(k_p(&schedule, 150.0, -0.1), Some(-4.0));
+	}
+
+	#[test]
+	fn interpolates_by_speed() {
+		let schedule = [row(80.0, None, -2.0), row(40.0, None, -6.0)];
+		assert_eq!(k_p(&schedule, 60.0, 0.0), Some(-4.0));
+		let gains = gains(&schedule, 50.0, 0.0).unwrap();
+		assert!((gains.k_i - -0.5).abs() < 1e-9);
+		assert!((gains.k_d - -50.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn keeps_the_closest_row_outside() {
+		let schedule = [row(40.0, None, -6.0), row(80.0, None, -2.0)];
+		assert_eq!(k_p(&schedule, 0.0, 0.0), Some(-6.0));
+		assert_eq!(k_p(&schedule, -20.0, 0.0), Some(-6.0));
+		assert_eq!(k_p(&schedule, 120.0, 0.0), Some(-2.0));
+	}
+
+	#[test]
+	fn interpolates_by_curvature_without_sign() {
+		let schedule = [
+			row(50.0, Some(0.0), -4.0),
+			row(50.0, Some(0.02), -8.0),
+		];
+		assert_eq!(k_p(&schedule, 50.0, 0.01), Some(-6.0));
+		assert_eq!(k_p(&schedule, 50.0, -0.01), Some(-6.0));
+		assert_eq!(k_p(&schedule, 50.0, 1.0), Some(-8.0));
+	}
+
+	#[test]
+	fn rows_without_curvature_count_for_every_curvature() {
+		let schedule = [
+			row(30.0, None, -2.0),
+			row(70.0, Some(0.0), -4.0),
+			row(70.0, Some(0.02), -8.0),
+		];
+		assert_eq!(k_p(&schedule, 50.0, 0.0), Some(-3.0));
+		assert_eq!(k_p(&schedule, 50.0, 0.02), Some(-5.0));
+	}
+
+	#[test]
+	fn the_same_speed_twice_doesnt_divide_by_zero() {
+		let schedule = [row(50.0, None, -4.0), row(50.0, None, -6.0), row(70.0, None, -8.0)];
+		let k_p = k_p(&schedule, 50.0, 0.0).unwrap();
+		assert!(k_p.is_finite());
+		assert!(gains(&schedule, 60.0, 0.0).unwrap().k_p.is_finite());
+	}
+
+	#[test]
+	fn not_a_number_gives_no_gains() {
+		let schedule = [row(40.0, None, -6.0), row(80.0, None, -2.0)];
+		assert_eq!(gains(&schedule, f64::NAN, 0.0), None);
+	}
+}
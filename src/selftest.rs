@@ -0,0 +1,225 @@
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, ensure, Result};
+use crate::robot::button::Buttons;
+use crate::robot::Robot;
+
+/// How long we wait for anything the user has to do.
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Every motor runs this fast, for this long, and needs to turn at least this far each way.
+const MOTOR_SPEED: f64 = 30.0;
+const MOTOR_TIME: Duration = Duration::from_millis(500);
+const MIN_ROTATIONS: f64 = 0.1;
+
+/// Where the user holds the target for the ultrasonic sensor, in `cm`.
+const TARGET_DISTANCE: f64 = 20.0;
+const DISTANCE_TOLERANCE: f64 = 5.0;
+
+/// How much brighter white has to be than black, in percent.
+const MIN_CONTRAST: f64 = 20.0;
+
+type IsPressed = fn(&Buttons) -> bool;
+
+/// The results of all checks.
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+	checks: Vec<(String, Result<String>)>,
+}
+
+impl Report {
+	fn check(&mut self, name: impl Into<String>, result: Result<String>) {
+		let name = name.into();
+		match &result {
+			Ok(detail) => println!("pass: {name}: {detail}"),
+			Err(err) => println!("FAIL: {name}: {err:#}"),
+		}
+		self.checks.push((name, result));
+	}
+
+	pub(crate) fn failed(&self) -> usize {
+		self.checks.iter().filter(|(_, result)| result.is_err()).count()
+	}
+
+	pub(crate) fn print(&self) {
+		println!();
+		println!("self test: {} passed, {} failed", self.checks.len() - self.failed(), self.failed());
+		for (name, result) in &self.checks {
+			match result {
+				Ok(detail) => println!("  pass  {name}: {detail}"),
+				Err(err) => println!("  FAIL  {name}: {err:#}"),
+			}
+		}
+	}
+}
+
+/// Checks every device of the robot, with the help of the user.
+pub(crate) fn run(bot: &Robot) -> Report {
+	let mut report = Report::default();
+
+	// The buttons come first, we need enter for everything else.
+	let buttons: [(&str, IsPressed); 5] = [
+		("up", Buttons::is_up),
+		("down", Buttons::is_down),
+		("left", Buttons::is_left),
+		("right", Buttons::is_right),
+		("enter", Buttons::is_enter),
+	];
+	for (name, is_pressed) in buttons {
+		prompt(bot, &format!("Press {name}"));
+		let result = press(|| Ok(is_pressed(&bot.buttons)));
+		report.check(format!("button {name}"), result);
+	}
+
+	let lifted = confirm(bot, "Lift the robot, so the wheels turn freely");
+	if let Err(err) = lifted {
+		report.check("motors", Err(err));
+	} else {
+		report.check("left motor", motor(
+			|| bot.left.get_rotations(),
+			|speed| { bot.left.start()?; bot.left.set_speed(speed) },
+			|| bot.left.stop(),
+		));
+		report.check("right motor", motor(
+			|| bot.right.get_rotations(),
+			|speed| { bot.right.start()?; bot.right.set_speed(speed) },
+			|| bot.right.stop(),
+		));
+		report.check("top arm motor", motor(
+			|| bot.top_arm.get_rotations(),
+			|speed| { bot.top_arm.start_with_full_power()?; bot.top_arm.set_speed(speed) },
+			|| bot.top_arm.stop(),
+		));
+	}
+
+	prompt(bot, "Press the touch sensor");
+	report.check("touch sensor", press(|| bot.touch.is_pressed()));
+
+	let distance = confirm(bot, &format!("Hold a target {TARGET_DISTANCE}cm in front of the ultrasonic sensor"))
+		.and_then(|_| distance(bot));
+	report.check("ultrasonic sensor", distance);
+
+	report_colors(bot, &mut report);
+
+	report
+}
+
+/// Prompts the user to do something, and beeps so they look.
+fn prompt(bot: &Robot, text: &str) {
+	println!("{text}");
+	let _ = bot.beep();
+}
+
+/// Waits for `f`, until the timeout.
+fn wait_for(mut f: impl FnMut() -> Result<bool>) -> Result<bool> {
+	let start = Instant::now();
+	while start.elapsed() < TIMEOUT {
+		if f()? {
+			return Ok(true);
+		}
+		std::thread::sleep(Duration::from_millis(10));
+	}
+	Ok(false)
+}
+
+/// Waits for a press and the release after it.
+fn press(mut is_pressed: impl FnMut() -> Result<bool>) -> Result<String> {
+	let start = Instant::now();
+	if !wait_for(&mut is_pressed)? {
+		bail!("Not pressed within {}s", TIMEOUT.as_secs());
+	}
+	let pressed = start.elapsed();
+	if !wait_for(|| Ok(!is_pressed()?))? {
+		bail!("Still pressed after {}s", TIMEOUT.as_secs());
+	}
+	Ok(format!("pressed after {:.1}s", pressed.as_secs_f64()))
+}
+
+/// Prompts the user to do something, and waits until they press enter.
+fn confirm(bot: &Robot, text: &str) -> Result<()> {
+	prompt(bot, &format!("{text}, then press enter"));
+	press(|| Ok(bot.buttons.is_enter()))?;
+	Ok(())
+}
+
+/// Runs a motor forwards and backwards, and checks that its encoder follows.
+fn motor(
+	rotations: impl Fn() -> Result<f64>,
+	run: impl Fn(f64) -> Result<()>,
+	stop: impl Fn() -> Result<()>,
+) -> Result<String> {
+	let mut moved = [0.0; 2];
+	for (i, direction) in [1.0, -1.0].into_iter().enumerate() {
+		let before = rotations()?;
+		let result = run(direction * MOTOR_SPEED);
+		std::thread::sleep(MOTOR_TIME);
+		stop()?;
+		result?;
+		// Let it come to a stop, before we look where it is.
+		std::thread::sleep(Duration::from_millis(200));
+		moved[i] = rotations()? - before;
+	}
+
+	let [forwards, backwards] = moved;
+	ensure!(forwards >= MIN_ROTATIONS, "Turned {forwards:.2} rotations forwards, instead of at least {MIN_ROTATIONS}");
+	ensure!(backwards <= -MIN_ROTATIONS, "Turned {backwards:.2} rotations backwards, instead of at least -{MIN_ROTATIONS}");
+	Ok(format!("{forwards:+.2} and {backwards:+.2} rotations"))
+}
+
+/// The median of a few distance readings.
+fn distance(bot: &Robot) -> Result<String> {
+	let mut readings = Vec::new();
+	for _ in 0..10 {
+		readings.extend(bot.distance.get_distance()?);
+		std::thread::sleep(Duration::from_millis(50));
+	}
+	ensure!(!readings.is_empty(), "No echo at all");
+
+	readings.sort_by(f64::total_cmp);
+	let distance = readings[readings.len() / 2];
+	ensure!(
+		(distance - TARGET_DISTANCE).abs() <= DISTANCE_TOLERANCE,
+		"Measured {distance:.1}cm instead of {TARGET_DISTANCE}cm",
+	);
+	Ok(format!("{distance:.1}cm"))
+}
+
+/// Reads every color sensor on black and on white.
+fn report_colors(bot: &Robot, report: &mut Report) {
+	let sensors: Vec<_> = std::iter::once(("color sensor".to_owned(), &bot.color))
+		.chain(bot.line_sensors.iter().enumerate().map(|(i, x)| (format!("line sensor {}", i + 1), x)))
+		.collect();
+
+	let mut readings = Vec::new();
+	for surface in ["black", "white"] {
+		if let Err(err) = confirm(bot, &format!("Put the color sensors on {surface}")) {
+			for (name, _) in &sensors {
+				report.check(name.clone(), Err(anyhow!("{err:#}")));
+			}
+			return;
+		}
+		readings.push(sensors.iter()
+			.map(|(_, sensor)| {
+				let mut total = 0.0;
+				for _ in 0..10 {
+					total += sensor.get_color()?;
+					std::thread::sleep(Duration::from_millis(10));
+				}
+				Ok(total / 10.0)
+			})
+			.collect::<Vec<Result<f64>>>());
+	}
+
+	let white = readings.pop().unwrap_or_default();
+	let black = readings.pop().unwrap_or_default();
+	for (((name, _), black), white) in sensors.into_iter().zip(black).zip(white) {
+		let result = black.and_then(|black| {
+			let white = white?;
+			ensure!(
+				white - black >= MIN_CONTRAST,
+				"Black is {black:.1}% and white {white:.1}%, white needs to be at least {MIN_CONTRAST}% brighter",
+			);
+			Ok(format!("black {black:.1}%, white {white:.1}%"))
+		});
+		report.check(name, result);
+	}
+}
@@ -0,0 +1,85 @@
+use anyhow::{ensure, Result};
+use crate::follow::FollowMode;
+use crate::program::Program;
+use crate::sim::{Noise, CM_PER_PERCENT, MOTOR_TIME_CONSTANT};
+
+// How fast the leader changes its speed, in `cm/s²`.
+const LEADER_ACCEL: f64 = 20.0;
+// The leader speed in `cm/s` for every phase of the simulation.
+const LEADER_SPEEDS: &[f64] = &[20.0, 28.0, 12.0, 24.0, 16.0];
+const PHASE_TIME: f64 = 10.0;
+// The gap in `cm` we start with.
+const START_GAP: f64 = 35.0;
+// Every this many ticks the simulated sensor reports a bad echo.
+const OUTLIER_EVERY: usize = 97;
+// How far in `s` of our speed the gap may be from the one we want, at the end of every phase.
+const GAP_TOLERANCE: f64 = 0.1;
+
+/// Simulates driving behind a leader that changes its speed, only along the track.
+///
+/// This runs the distance filter and the follow controller of the [Program] against a leader that
+/// changes its speed every few seconds, and prints how well we keep the gap in every phase. With
+/// [FollowMode::TimeGap] it fails if the gap doesn't settle at the time gap, or if we ever get closer
+/// than `stop_distance`.
+pub(crate) fn follow(program: &mut Program) -> Result<()> {
+	let dt = Program::TICK_TIME.as_secs_f64();
+	let ticks_per_phase = (PHASE_TIME / dt) as usize;
+
+	let mut noise = Noise::new(2023);
+	let mut leader_speed = LEADER_SPEEDS[0];
+	let speed = program.follow_speed();
+	let mut own_speed = speed * CM_PER_PERCENT;
+	let mut gap = START_GAP;
+	let mut min_gap = gap;
+	let time_gap = program.follow_mode() == FollowMode::TimeGap;
+
+	println!("   t | leader | own  |  gap | estimate | correction");
+
+	for (phase, &wanted_leader_speed) in LEADER_SPEEDS.iter().enumerate() {
+		let mut gaps = Vec::new();
+		let mut targets = Vec::new();
+
+		for tick in 0..ticks_per_phase {
+			let reading = if tick % OUTLIER_EVERY == OUTLIER_EVERY - 1 {
+				Some(gap + 40.0 + noise.normal(20.0))
+			} else {
+				Some(gap + noise.normal(0.5))
+			};
+
+			let estimate = program.filter_distance(reading, dt);
+			let correction = program.follow_correction(estimate, speed, dt);
+			let command = speed * (1.0 + correction) * CM_PER_PERCENT;
+
+			own_speed += (command - own_speed) * dt / MOTOR_TIME_CONSTANT;
+			let change = (wanted_leader_speed - leader_speed).clamp(-LEADER_ACCEL * dt, LEADER_ACCEL * dt);
+			leader_speed += change;
+			gap += (leader_speed - own_speed) * dt;
+			min_gap = min_gap.min(gap);
+
+			// We only judge the second half of every phase, the first one is for settling in.
+			if tick >= ticks_per_phase / 2 {
+				gaps.push(gap);
+				targets.push(program.follow_target(own_speed));
+			}
+
+			if tick % 100 == 0 {
+				let t = (phase * ticks_per_phase + tick) as f64 * dt;
+				let estimate = estimate.map_or(f64::NAN, |x| x.distance);
+				println!("{t:>4.0} | {leader_speed:>6.1} | {own_speed:>4.1} | {gap:>4.1} | {estimate:>8.1} | {correction:>+6.3}");
+			}
+		}
+
+		let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+		let std_dev = (gaps.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / gaps.len() as f64).sqrt();
+		let target = targets.iter().sum::<f64>() / targets.len() as f64;
+		println!("phase {phase}: leader at {wanted_leader_speed:.1} cm/s, gap {mean:.1} cm ± {std_dev:.2} cm, wanted {target:.1} cm");
+		ensure!(!time_gap || (mean - target).abs() <= GAP_TOLERANCE * wanted_leader_speed,
+			"The gap in phase {phase} is {mean:.1}cm and not {target:.1}cm"
+		);
+	}
+
+	ensure!(!time_gap || min_gap >= program.stop_distance(),
+		"We came as close as {min_gap:.1}cm, under the stop distance of {:.1}cm", program.stop_distance()
+	);
+	Ok(())
+}
@@ -0,0 +1,175 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use anyhow::{Context, Result};
+use crate::program::Program;
+use crate::robot::Robot;
+use crate::state::RobotState;
+use crate::sim::world::{Scenario, World, LOST_DEVIATION};
+
+pub(crate) mod follow;
+pub(crate) mod world;
+
+// The speed one percent of motor speed gives, taken from the table in the settings file.
+const CM_PER_PERCENT: f64 = 0.45;
+// The time the motors need to reach a new speed.
+const MOTOR_TIME_CONSTANT: f64 = 0.1;
+// Closer than this (in `cm`) we crashed into the leader or the wall.
+const CRASH_DISTANCE: f64 = 1.0;
+// We give up on a run after this many seconds.
+pub(crate) const MAX_TIME: f64 = 60.0;
+
+/// The world the simulated devices of a [Robot] share.
+pub(crate) type SimWorld = Rc<RefCell<World>>;
+
+/// A small xorshift generator, so that every run of the simulation is the same.
+#[derive(Debug)]
+pub(crate) struct Noise(u64);
+
+impl Noise {
+	pub(crate) fn new(seed: u64) -> Noise {
+		Noise(seed.max(1))
+	}
+
+	/// Uniform in `0.0 .. 1.0`.
+	pub(crate) fn next(&mut self) -> f64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		(self.0 >> 11) as f64 / (1u64 << 53) as f64
+	}
+
+	/// Roughly normal distributed with the given standard deviation.
+	pub(crate) fn normal(&mut self, std_dev: f64) -> f64 {
+		let sum: f64 = (0..12).map(|_| self.next()).sum();
+		(sum - 6.0) * std_dev
+	}
+}
+
+/// How a simulated drive went.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Outcome {
+	/// The time in `s` until we stopped in front of the wall, or until we gave up.
+	pub(crate) time: f64,
+	/// The distance we drove in `cm`.
+	pub(crate) travelled: f64,
+	/// We stopped in front of the wall.
+	pub(crate) finished: bool,
+	pub(crate) left_track: bool,
+	pub(crate) crashed: bool,
+	/// The largest distance of the color sensor to the edge of the line, in `cm`.
+	pub(crate) max_deviation: f64,
+	/// The root mean square of the gap minus the one we want while following, in `cm`.
+	pub(crate) gap_error: f64,
+}
+
+impl Outcome {
+	/// The average speed in `cm/s`.
+	pub(crate) fn speed(&self) -> f64 {
+		self.travelled / self.time
+	}
+}
+
+/// Drives once with the settings as they are, on a circle with `diameter`, and prints how it went.
+pub(crate) fn track(program: &mut Program, state: RobotState) -> Result<()> {
+	let outcome = run_from(program, Scenario::new(program.diameter), state)?;
+
+	println!("{outcome:#?}");
+	println!("real speed: {:.1} cm/s", outcome.speed());
+
+	Ok(())
+}
+
+/// Drives the [Program] from [RobotState::DriveEntry] until it stops, or fails, on the track of
+/// the [Scenario].
+pub(crate) fn run(program: &mut Program, scenario: Scenario) -> Result<Outcome> {
+	run_from(program, scenario, RobotState::DriveEntry)
+}
+
+/// Drives the [Program] from the drive `state`, see [run].
+pub(crate) fn run_from(program: &mut Program, scenario: Scenario, state: RobotState) -> Result<Outcome> {
+	let dt = Program::TICK_TIME.as_secs_f64();
+	let world: SimWorld = Rc::new(RefCell::new(World::new(scenario)));
+	let bot = Robot::simulated(world.clone(), program.hardware(), program.line_sensors())?;
+	program.configure_sensors(&bot)
+		.context("Failed to configure the simulated color sensors")?;
+	program.start_feedback(&bot)
+		.context("Failed to start the feedback")?;
+
+	let previous = program.state().clone();
+	program.next_state(&bot, state)
+		.context("Failed to start the simulated drive")?;
+	program.follow_state(0, &previous);
+
+	let mut outcome = Outcome {
+		time: MAX_TIME,
+		travelled: 0.0,
+		finished: false,
+		left_track: false,
+		crashed: false,
+		max_deviation: 0.0,
+		gap_error: 0.0,
+	};
+	let mut gap_errors = Vec::new();
+	let mut exiting = false;
+	let mut moving_ticks = 0;
+
+	// The world only goes on while we drive, so the time in the menu doesn't count.
+	for tick in 0.. {
+		if moving_ticks >= (MAX_TIME / dt) as usize {
+			break;
+		}
+		// We only finish by stopping at the end of the exit, not by giving up somewhere.
+		if *program.state() != RobotState::Exit {
+			exiting = *program.state() == RobotState::DriveExit;
+		}
+		// With the remote control, by hand, or in the menu, we go in real time, so there is time to
+		// do something. Without a running remote control, e.g. in `tune`, we drive as fast as we can.
+		if program.remote_running() || matches!(program.state(), RobotState::Teleop | RobotState::InMenu) {
+			std::thread::sleep(Program::TICK_TIME);
+		}
+		let done = program.tick(&bot, tick).context("Failed to tick the simulated robot");
+		let done = done.inspect_err(|err| program.fail(err))?;
+		if done {
+			outcome.finished = exiting;
+			break;
+		}
+		if !program.state().is_run() {
+			continue;
+		}
+		moving_ticks += 1;
+
+		let mut world = world.borrow_mut();
+		world.step(dt);
+
+		let deviation = world.deviation().abs();
+		outcome.max_deviation = outcome.max_deviation.max(deviation);
+		if deviation > LOST_DEVIATION {
+			outcome.left_track = true;
+			break;
+		}
+
+		let gap = world.gap();
+		if gap.is_some_and(|x| x < CRASH_DISTANCE) {
+			outcome.crashed = true;
+			break;
+		}
+		if *program.state() == RobotState::DriveFollow {
+			if let Some(gap) = world.leader_gap() {
+				gap_errors.push(gap - program.follow_target(world.speed()));
+			}
+		}
+	}
+
+	program.finish_run(None);
+
+	let world = world.borrow();
+	if outcome.finished {
+		outcome.time = world.time();
+	}
+	outcome.travelled = world.travelled();
+	if !gap_errors.is_empty() {
+		outcome.gap_error = (gap_errors.iter().map(|x| x * x).sum::<f64>() / gap_errors.len() as f64).sqrt();
+	}
+
+	Ok(outcome)
+}
@@ -0,0 +1,247 @@
+use std::f64::consts::PI;
+use crate::sim::{Noise, CM_PER_PERCENT, MOTOR_TIME_CONSTANT};
+
+// The geometry of the robot, in `cm`.
+const WHEEL_WIDTH: f64 = 14.0;
+const WHEEL_DIAMETER: f64 = 5.6;
+// How far in front of the axle the color sensor and the ultrasonic sensor are.
+const COLOR_AHEAD: f64 = 7.0;
+const DISTANCE_AHEAD: f64 = 10.0;
+
+// The black line and what the color sensor sees of it.
+const LINE_WIDTH: f64 = 2.0;
+const SPOT_WIDTH: f64 = 1.0;
+const BLACK: f64 = 8.0;
+const WHITE: f64 = 80.0;
+
+// The ultrasonic sensor only sees things within this angle to both sides, and up to 255cm.
+const DISTANCE_CONE: f64 = 25.0 * PI / 180.0;
+const DISTANCE_MAX: f64 = 255.0;
+// The leader is as wide as we are, so on small circles we still see a part of it.
+const LEADER_WIDTH: f64 = 15.0;
+
+/// Further away than this (in `cm`) from the edge we follow, we left the track.
+pub(crate) const LOST_DEVIATION: f64 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Side {
+	Left,
+	Right,
+}
+
+impl Side {
+	fn index(self) -> usize {
+		match self {
+			Side::Left => 0,
+			Side::Right => 1,
+		}
+	}
+}
+
+/// What happens on the track.
+#[derive(Debug, Clone)]
+pub(crate) struct Scenario {
+	/// The diameter of the circle in `cm`, positive for driving clockwise, like `diameter` in the
+	/// settings.
+	pub(crate) diameter: f64,
+	/// The speed of the leader in `cm/s`.
+	pub(crate) leader_speed: f64,
+	/// How far (along the line) the leader is in front of us at the start, in `cm`.
+	pub(crate) leader_gap: f64,
+	/// After this many seconds the leader leaves the track.
+	pub(crate) leader_leaves: f64,
+	/// The distance from where the leader left to the wall at the end of the exit, in `cm`.
+	pub(crate) exit_length: f64,
+	/// The seed for the sensor noise.
+	pub(crate) seed: u64,
+}
+
+impl Scenario {
+	pub(crate) fn new(diameter: f64) -> Scenario {
+		Scenario {
+			diameter,
+			leader_speed: 15.0,
+			leader_gap: 80.0,
+			leader_leaves: 15.0,
+			exit_length: 150.0,
+			seed: 2023,
+		}
+	}
+}
+
+/// A robot on a circle of black line, with a leader in front of it.
+///
+/// We don't simulate the entry and exit lines. Instead, once the leader left, there's a wall in
+/// front of us after `exit_length`, as if we turned onto the exit.
+#[derive(Debug)]
+pub(crate) struct World {
+	scenario: Scenario,
+	noise: Noise,
+	time: f64,
+
+	radius: f64,
+	// `1.0` for counter clockwise, `-1.0` for clockwise.
+	direction: f64,
+
+	x: f64,
+	y: f64,
+	heading: f64,
+	angle: f64,
+
+	duty: [f64; 2],
+	speed: [f64; 2],
+	travelled: [f64; 2],
+
+	leader: Option<f64>,
+	wall: Option<f64>,
+}
+
+impl World {
+	pub(crate) fn new(scenario: Scenario) -> World {
+		let radius = scenario.diameter.abs() / 2.0;
+		let direction = if scenario.diameter > 0.0 { -1.0 } else { 1.0 };
+
+		// We follow the edge with the line on our left, and start with the color sensor on it.
+		let sensor_radius = radius + direction * LINE_WIDTH / 2.0;
+		let heading = direction * PI / 2.0;
+
+		World {
+			noise: Noise::new(scenario.seed),
+			time: 0.0,
+
+			radius,
+			direction,
+
+			x: sensor_radius - COLOR_AHEAD * heading.cos(),
+			y: -COLOR_AHEAD * heading.sin(),
+			heading,
+			angle: 0.0,
+
+			duty: [0.0; 2],
+			speed: [0.0; 2],
+			travelled: [0.0; 2],
+
+			leader: Some(scenario.leader_gap),
+			wall: None,
+
+			scenario,
+		}
+	}
+
+	pub(crate) fn time(&self) -> f64 {
+		self.time
+	}
+
+	pub(crate) fn set_duty(&mut self, side: Side, duty: f64) {
+		self.duty[side.index()] = duty;
+	}
+
+	pub(crate) fn rotations(&self, side: Side) -> f64 {
+		self.travelled[side.index()] / (PI * WHEEL_DIAMETER)
+	}
+
+	/// How far we got along the line, in `cm`.
+	pub(crate) fn progress(&self) -> f64 {
+		self.angle * self.radius
+	}
+
+	/// The distance we drove, in `cm`.
+	pub(crate) fn travelled(&self) -> f64 {
+		(self.travelled[0] + self.travelled[1]) / 2.0
+	}
+
+	/// Our speed in `cm/s`.
+	pub(crate) fn speed(&self) -> f64 {
+		(self.speed[0] + self.speed[1]) / 2.0
+	}
+
+	/// The leader or the wall, from our ultrasonic sensor, without any noise.
+	pub(crate) fn gap(&self) -> Option<f64> {
+		if let Some(wall) = self.wall {
+			return Some(wall - self.progress() - DISTANCE_AHEAD);
+		}
+
+		self.leader_gap()
+	}
+
+	/// The back of the leader, from our ultrasonic sensor, without any noise.
+	pub(crate) fn leader_gap(&self) -> Option<f64> {
+		let leader = self.leader?;
+		let angle = self.direction * leader / self.radius;
+		let (x, y) = (self.radius * angle.cos(), self.radius * angle.sin());
+		let (sensor_x, sensor_y) = self.ahead(DISTANCE_AHEAD);
+		let (dx, dy) = (x - sensor_x, y - sensor_y);
+
+		let gap = (dx * dx + dy * dy).sqrt();
+		let bearing = (dy.atan2(dx) - self.heading + PI).rem_euclid(2.0 * PI) - PI;
+		let half_width = (LEADER_WIDTH / 2.0).atan2(gap);
+		if bearing.abs() - half_width > DISTANCE_CONE {
+			return None;
+		}
+		Some(gap)
+	}
+
+	/// How far the color sensor is away from the edge we follow, in `cm`.
+	pub(crate) fn deviation(&self) -> f64 {
+		self.lateral(0.0) - self.direction * LINE_WIDTH / 2.0
+	}
+
+	/// The reflection a color sensor `offset` cm to the right of the middle sees, in percent.
+	pub(crate) fn reflection(&mut self, offset: f64) -> f64 {
+		let lateral = self.lateral(offset);
+		let from = (lateral - SPOT_WIDTH / 2.0).max(-LINE_WIDTH / 2.0);
+		let to = (lateral + SPOT_WIDTH / 2.0).min(LINE_WIDTH / 2.0);
+		let black = (to - from).max(0.0) / SPOT_WIDTH;
+
+		let reflection = WHITE - black * (WHITE - BLACK) + self.noise.normal(0.5);
+		reflection.clamp(0.0, 100.0).round()
+	}
+
+	/// The reading of the ultrasonic sensor, in `cm`.
+	pub(crate) fn distance(&mut self) -> Option<f64> {
+		let gap = self.gap()?;
+		let reading = gap + self.noise.normal(0.5);
+		(0.0..DISTANCE_MAX).contains(&reading).then(|| reading.round())
+	}
+
+	pub(crate) fn step(&mut self, dt: f64) {
+		self.time += dt;
+
+		for side in 0..2 {
+			let wanted = self.duty[side] * CM_PER_PERCENT;
+			self.speed[side] += (wanted - self.speed[side]) * dt / MOTOR_TIME_CONSTANT;
+			self.travelled[side] += self.speed[side] * dt;
+		}
+
+		let [left, right] = self.speed;
+		let speed = (left + right) / 2.0;
+		self.heading += (right - left) / WHEEL_WIDTH * dt;
+		self.x += speed * self.heading.cos() * dt;
+		self.y += speed * self.heading.sin() * dt;
+
+		// The angle on the circle of our axle, counted in our driving direction and without wrapping.
+		let angle = self.y.atan2(self.x) * self.direction;
+		self.angle += (angle - self.angle + PI).rem_euclid(2.0 * PI) - PI;
+
+		if let Some(leader) = &mut self.leader {
+			*leader += self.scenario.leader_speed * dt;
+		}
+		if self.leader.is_some() && self.time >= self.scenario.leader_leaves {
+			self.leader = None;
+			self.wall = Some(self.progress() + self.scenario.exit_length);
+		}
+	}
+
+	fn ahead(&self, distance: f64) -> (f64, f64) {
+		(self.x + distance * self.heading.cos(), self.y + distance * self.heading.sin())
+	}
+
+	// How far a color sensor `offset` cm to the right is away from the middle of the line,
+	// positive is outwards.
+	fn lateral(&self, offset: f64) -> f64 {
+		let (x, y) = self.ahead(COLOR_AHEAD);
+		let right = self.heading - PI / 2.0;
+		let (x, y) = (x + offset * right.cos(), y + offset * right.sin());
+		(x * x + y * y).sqrt() - self.radius
+	}
+}
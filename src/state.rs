@@ -5,6 +5,7 @@ pub(crate) enum RobotState {
 	InMenu,
 	Test,
 	Measure,
+	Autotune,
 
 	DriveSimpleOnly, // for testing our PID values without constant sideways drag
 
@@ -13,24 +14,30 @@ pub(crate) enum RobotState {
 	DriveEntry,
 	DriveFollow,
 	DriveExit,
+	/// Searching the line after we lost it, see [crate::recovery::Recovery].
+	DriveRecover,
+	/// Standing still, e.g. after the remote control stopped the drive.
+	Idle,
+	/// Driving by hand, see [crate::teleop::Teleop].
+	Teleop,
 }
 
 impl RobotState {
-	pub(crate) const HELP_TEXT: &'static str =
-		"Usage:\
-		\n    roborace2023 [<subcommand>]\
-		\n\
-		\nWhere <subcommand> is one of:\
-		\n    exit            Print out this help text and exit\
-		\n    menu            Open the menu for selecting any robot state\
-		\n    test            Run the quick and dirty test method\
-		\n    measure         Measure the line. This has no implementation yet and will panic\
-		\n    drive           Start the line driving\
-		\n    driveS          Drive simple only, for testing PID values\
-		\n    l|r DEGREE      Move the corresponding motor a tiny bit, depending on DEGREE\
-		\n    print           Print the robot struct out, for debugging.\
-		\n\
-		\nIf no subcommand is given, the robot will go into menu mode";
+	/// We drive on the line in this state.
+	pub(crate) fn is_drive(&self) -> bool {
+		matches!(self,
+			RobotState::DriveSimpleOnly |
+			RobotState::DriveEntry |
+			RobotState::DriveFollow |
+			RobotState::DriveExit |
+			RobotState::DriveRecover
+		)
+	}
+
+	/// We keep a run of this state: the drive states, and driving by hand.
+	pub(crate) fn is_run(&self) -> bool {
+		self.is_drive() || *self == RobotState::Teleop
+	}
 
 	pub(crate) const ALL: &'static [(&'static str, RobotState)] = &[
 		("exit", RobotState::Exit),
@@ -38,9 +45,17 @@ impl RobotState {
 		("start", RobotState::Start),
 		("test", RobotState::Test),
 		("measure", RobotState::Measure),
+		("autotune", RobotState::Autotune),
 		("drive entry", RobotState::DriveEntry),
 		("drive follow", RobotState::DriveFollow),
 		("drive exit", RobotState::DriveExit),
 		("drive sim", RobotState::DriveSimpleOnly),
+		("idle", RobotState::Idle),
+		("teleop", RobotState::Teleop),
 	];
+
+	/// The state with the `name` from [RobotState::ALL].
+	pub(crate) fn from_name(name: &str) -> Option<RobotState> {
+		Self::ALL.iter().find(|(x, _)| *x == name).map(|(_, state)| state.clone())
+	}
 }
\ No newline at end of file
@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use crate::autotune::Gains;
+use crate::state::RobotState;
+
+/// What happens to the integral of the line PID in a drive state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum IntegralPolicy {
+	/// Keep summing up, and keep what we summed up before.
+	#[default]
+	Keep,
+	/// Start from zero when we enter the state.
+	Reset,
+	/// Keep what we summed up before, but don't add anything.
+	Freeze,
+}
+
+/// The settings of one drive state, everything that's missing comes from the global settings.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct StateSettings {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) speed: Option<f64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) k_p: Option<f64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) k_i: Option<f64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) k_d: Option<f64>,
+	pub(crate) integral: IntegralPolicy,
+	/// Rotate the top arm in this state. Without it, we only rotate it while following.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) rotate_arm: Option<bool>,
+}
+
+impl StateSettings {
+	/// The line gains of this state, with the missing ones from `base`.
+	pub(crate) fn gains(&self, base: Gains) -> Gains {
+		Gains {
+			k_p: self.k_p.unwrap_or(base.k_p),
+			k_i: self.k_i.unwrap_or(base.k_i),
+			k_d: self.k_d.unwrap_or(base.k_d),
+		}
+	}
+}
+
+/// The settings for every drive state.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct States {
+	/// The time in `s` we take to move from the speed and gains of one state to the next.
+	pub(crate) blend: f64,
+
+	pub(crate) simple: StateSettings,
+	pub(crate) entry: StateSettings,
+	pub(crate) follow: StateSettings,
+	pub(crate) exit: StateSettings,
+}
+
+impl States {
+	/// The settings of a drive state, or `None` for the other states.
+	pub(crate) fn get(&self, state: &RobotState) -> Option<&StateSettings> {
+		match state {
+			RobotState::DriveSimpleOnly => Some(&self.simple),
+			RobotState::DriveEntry => Some(&self.entry),
+			RobotState::DriveFollow => Some(&self.follow),
+			RobotState::DriveExit => Some(&self.exit),
+			_ => None,
+		}
+	}
+}
+
+/// The speed and the line gains we drive with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Setpoint {
+	pub(crate) speed: f64,
+	pub(crate) gains: Gains,
+}
+
+/// Moves linearly from the setpoint we had at the last state change to the one of the new state.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Blend {
+	from: Option<Setpoint>,
+	last: Option<Setpoint>,
+	elapsed: f64,
+}
+
+impl Blend {
+	pub(crate) fn reset(&mut self) {
+		*self = Blend::default();
+	}
+
+	/// Starts blending from where we are now.
+	pub(crate) fn transition(&mut self) {
+		self.from = self.last;
+		self.elapsed = 0.0;
+	}
+
+	/// The speed [Blend::update] returns this tick, for a `target` with this speed.
+	pub(crate) fn speed(&self, target: f64, duration: f64, dt: f64) -> f64 {
+		match self.progress(duration, self.elapsed + dt) {
+			Some((from, t)) => from.speed + t * (target - from.speed),
+			None => target,
+		}
+	}
+
+	/// The setpoint for this tick, on the way to `target`.
+	pub(crate) fn update(&mut self, target: Setpoint, duration: f64, dt: f64) -> Setpoint {
+		self.elapsed += dt;
+
+		let setpoint = match self.progress(duration, self.elapsed) {
+			Some((from, t)) => {
+				let lerp = |a: f64, b: f64| a + t * (b - a);
+				Setpoint {
+					speed: lerp(from.speed, target.speed),
+					gains: Gains {
+						k_p: lerp(from.gains.k_p, target.gains.k_p),
+						k_i: lerp(from.gains.k_i, target.gains.k_i),
+						k_d: lerp(from.gains.k_d, target.gains.k_d),
+					},
+				}
+			},
+			None => target,
+		};
+
+		self.last = Some(setpoint);
+		setpoint
+	}
+
+	/// Where we blend from, and how far we are on the way, or `None` if we are there.
+	fn progress(&self, duration: f64, elapsed: f64) -> Option<(Setpoint, f64)> {
+		self.from.filter(|_| elapsed < duration).map(|from| (from, elapsed / duration))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn setpoint(speed: f64, k_p: f64) -> Setpoint {
+		Setpoint { speed, gains: Gains { k_p, k_i: 0.0, k_d: 0.0 } }
+	}
+
+	#[test]
+	fn target_without_a_transition() {
+		let mut blend = Blend::default();
+		assert_eq!(blend.speed(50.0, 1.0, 0.1), 50.0);
+		assert_eq!(blend.update(setpoint(50.0, 1.0), 1.0, 0.1), setpoint(50.0, 1.0));
+	}
+
+	#[test]
+	fn moves_linearly_to_the_target() {
+		let mut blend = Blend::default();
+		blend.update(setpoint(20.0, 1.0), 1.0, 0.25);
+		blend.transition();
+
+		assert_eq!(blend.speed(60.0, 1.0, 0.25), 30.0);
+		assert_eq!(blend.update(setpoint(60.0, 3.0), 1.0, 0.25), setpoint(30.0, 1.5));
+		assert_eq!(blend.update(setpoint(60.0, 3.0), 1.0, 0.25), setpoint(40.0, 2.0));
+		assert_eq!(blend.update(setpoint(60.0, 3.0), 1.0, 0.25), setpoint(50.0, 2.5));
+		assert_eq!(blend.update(setpoint(60.0, 3.0), 1.0, 0.25), setpoint(60.0, 3.0));
+		// Past the end we stay at the target.
+		assert_eq!(blend.speed(60.0, 1.0, 0.25), 60.0);
+		assert_eq!(blend.update(setpoint(60.0, 3.0), 1.0, 0.25), setpoint(60.0, 3.0));
+	}
+
+	#[test]
+	fn zero_or_negative_duration_jumps() {
+		for duration in [0.0, -1.0] {
+			let mut blend = Blend::default();
+			blend.update(setpoint(20.0, 1.0), duration, 0.01);
+			blend.transition();
+			assert_eq!(blend.speed(60.0, duration, 0.01), 60.0);
+			assert_eq!(blend.update(setpoint(60.0, 3.0), duration, 0.01), setpoint(60.0, 3.0));
+		}
+	}
+
+	#[test]
+	fn zero_dt_stays_at_the_start() {
+		let mut blend = Blend::default();
+		blend.update(setpoint(20.0, 1.0), 1.0, 0.01);
+		blend.transition();
+		assert_eq!(blend.speed(60.0, 1.0, 0.0), 20.0);
+		assert_eq!(blend.update(setpoint(60.0, 3.0), 1.0, 0.0), setpoint(20.0, 1.0));
+	}
+
+	#[test]
+	fn transition_during_a_transition_starts_from_where_we_are() {
+		let mut blend = Blend::default();
+		blend.update(setpoint(20.0, 1.0), 1.0, 0.5);
+		blend.transition();
+		blend.update(setpoint(60.0, 3.0), 1.0, 0.5);
+		blend.transition();
+		assert_eq!(blend.update(setpoint(0.0, 0.0), 1.0, 0.5), setpoint(20.0, 1.0));
+	}
+
+	#[test]
+	fn reset_forgets_the_start() {
+		let mut blend = Blend::default();
+		blend.update(setpoint(20.0, 1.0), 1.0, 0.1);
+		blend.reset();
+		blend.transition();
+		assert_eq!(blend.update(setpoint(60.0, 3.0), 1.0, 0.1), setpoint(60.0, 3.0));
+	}
+}
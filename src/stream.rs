@@ -0,0 +1,314 @@
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::logging::NETWORK;
+use crate::telemetry::TickRecord;
+
+/// The default port of the stream, and of `bin/watch.rs`.
+pub(crate) const PORT: u16 = 7700;
+
+/// How many records wait for the network, before we drop new ones.
+const QUEUE: usize = 1000;
+
+/// With UDP, every this many lines we send the header again, for the clients that came late.
+const UDP_HEADER_EVERY: usize = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Protocol {
+	/// We listen on `address`, and every client that connects gets the stream.
+	#[default]
+	Tcp,
+	/// We send every line as one datagram to `address`.
+	Udp,
+}
+
+/// Sends the record of every tick of a drive over the network, as lines of CSV with the header
+/// first. The control loop only hands the records to a thread, which does all the sending, so a
+/// slow network drops records instead of slowing the ticks down. See `bin/watch.rs` for the
+/// client.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Stream {
+	pub(crate) enabled: bool,
+	pub(crate) protocol: Protocol,
+	/// With TCP where we listen, with UDP where we send to, e.g. the laptop at `192.168.0.2:7700`.
+	pub(crate) address: String,
+
+	#[serde(skip)]
+	publisher: Option<Publisher>,
+	#[serde(skip)]
+	dropped: usize,
+}
+
+/// The thread that sends, and how we reach it.
+#[derive(Debug)]
+struct Publisher {
+	sender: Option<SyncSender<TickRecord>>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl Default for Stream {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			protocol: Protocol::Tcp,
+			address: format!("0.0.0.0:{PORT}"),
+			publisher: None,
+			dropped: 0,
+		}
+	}
+}
+
+impl Stream {
+	/// Opens the socket and starts the thread that sends, if the stream is enabled.
+	pub(crate) fn start(&mut self) -> Result<()> {
+		if !self.enabled || self.publisher.is_some() {
+			return Ok(());
+		}
+
+		let sink = self.sink()?;
+		self.publisher = Some(Publisher::start(sink)?);
+		Ok(())
+	}
+
+	/// Opens the socket for `protocol` and `address`.
+	fn sink(&self) -> Result<Sink> {
+		match self.protocol {
+			Protocol::Tcp => {
+				let listener = TcpListener::bind(&self.address)
+					.with_context(|| format!("Failed to listen for telemetry clients on {}", self.address))?;
+				listener.set_nonblocking(true)
+					.context("Failed to set up the telemetry listener")?;
+				log::info!(target: NETWORK, "streaming telemetry to everybody connecting to {}", listener.local_addr()?);
+				Ok(Sink::Tcp { listener, clients: Vec::new() })
+			},
+			Protocol::Udp => {
+				let target = self.address.to_socket_addrs()
+					.with_context(|| format!("Failed to resolve the telemetry address {}", self.address))?
+					.next()
+					.with_context(|| format!("No address for {}", self.address))?;
+				let local: SocketAddr = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse()?;
+				let socket = UdpSocket::bind(local)
+					.context("Failed to open the telemetry socket")?;
+				log::info!(target: NETWORK, "streaming telemetry to {target}");
+				Ok(Sink::Udp { socket, target, lines: 0 })
+			},
+		}
+	}
+
+	/// Hands `record` to the thread, or drops it if the thread is behind.
+	pub(crate) fn send(&mut self, record: &TickRecord) {
+		let Some(sender) = self.publisher.as_ref().and_then(|x| x.sender.as_ref()) else { return };
+		match sender.try_send(record.clone()) {
+			Ok(()) => {},
+			Err(TrySendError::Full(_)) => {
+				if self.dropped == 0 {
+					log::warn!(target: NETWORK, "the telemetry stream is behind, dropping records");
+				}
+				self.dropped += 1;
+			},
+			Err(TrySendError::Disconnected(_)) => {
+				log::warn!(target: NETWORK, "the telemetry thread is gone, stopping the stream");
+				self.publisher = None;
+			},
+		}
+	}
+}
+
+impl Publisher {
+	/// Starts the thread that sends to `sink`.
+	fn start(sink: Sink) -> Result<Publisher> {
+		let (sender, receiver) = std::sync::mpsc::sync_channel(QUEUE);
+		let thread = std::thread::Builder::new()
+			.name("telemetry stream".to_owned())
+			.spawn(move || publish(receiver, sink))
+			.context("Failed to start the telemetry thread")?;
+		Ok(Publisher { sender: Some(sender), thread: Some(thread) })
+	}
+}
+
+impl Drop for Publisher {
+	/// Lets the thread send what is still queued.
+	fn drop(&mut self) {
+		self.sender = None;
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+enum Sink {
+	Tcp {
+		listener: TcpListener,
+		clients: Vec<TcpStream>,
+	},
+	Udp {
+		socket: UdpSocket,
+		target: SocketAddr,
+		lines: usize,
+	},
+}
+
+impl Sink {
+	/// Takes the clients that connected since the last time.
+	fn accept(&mut self) {
+		let Sink::Tcp { listener, clients } = self else { return };
+		while let Ok((mut client, address)) = listener.accept() {
+			let ready = client.set_nonblocking(false)
+				.and_then(|_| client.set_nodelay(true))
+				.and_then(|_| client.set_write_timeout(Some(Duration::from_millis(100))))
+				.and_then(|_| writeln!(client, "{}", TickRecord::CSV_HEADER));
+			match ready {
+				Ok(()) => {
+					log::info!(target: NETWORK, "telemetry client {address} connected");
+					clients.push(client);
+				},
+				Err(err) => log::warn!(target: NETWORK, "Failed to set up the telemetry client {address}: {err}"),
+			}
+		}
+	}
+
+	fn send(&mut self, line: &str) {
+		match self {
+			Sink::Tcp { clients, .. } => clients.retain_mut(|client| {
+				let result = writeln!(client, "{line}");
+				if let Err(err) = &result {
+					log::info!(target: NETWORK, "telemetry client {:?} is gone: {err}", client.peer_addr().ok());
+				}
+				result.is_ok()
+			}),
+			Sink::Udp { socket, target, lines } => {
+				if *lines % UDP_HEADER_EVERY == 0 {
+					let _ = socket.send_to(TickRecord::CSV_HEADER.as_bytes(), *target);
+				}
+				*lines += 1;
+				// Nobody might be listening, that's fine with UDP.
+				let _ = socket.send_to(line.as_bytes(), *target);
+			},
+		}
+	}
+}
+
+/// Sends every record from `receiver`, until the [Stream] is gone.
+fn publish(receiver: Receiver<TickRecord>, mut sink: Sink) {
+	loop {
+		match receiver.recv_timeout(Duration::from_millis(100)) {
+			Ok(record) => {
+				sink.accept();
+				sink.send(&record.csv());
+			},
+			Err(RecvTimeoutError::Timeout) => sink.accept(),
+			Err(RecvTimeoutError::Disconnected) => return,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{BufRead, BufReader};
+	use crate::line::Position;
+	use crate::state::RobotState;
+	use super::*;
+
+	const TIMEOUT: Duration = Duration::from_secs(5);
+
+	fn record(tick: usize) -> TickRecord {
+		TickRecord {
+			tick,
+			state: RobotState::DriveFollow,
+			raw_distance: Some(20.0),
+			estimate: None,
+			speed_correction: 0.0,
+			reflection: 50.0,
+			line_position: Position { offset: 0.0, confidence: 1.0 },
+			line_correction: 0.0,
+			left: 30.0,
+			right: 40.0,
+			saturation: Default::default(),
+		}
+	}
+
+	#[test]
+	fn tcp_clients_get_the_header_and_every_line() {
+		let mut stream = Stream { enabled: true, address: "127.0.0.1:0".to_owned(), ..Stream::default() };
+		let sink = stream.sink().unwrap();
+		let Sink::Tcp { listener, .. } = &sink else { unreachable!() };
+		let address = listener.local_addr().unwrap();
+		stream.publisher = Some(Publisher::start(sink).unwrap());
+
+		let client = TcpStream::connect(address).unwrap();
+		client.set_read_timeout(Some(TIMEOUT)).unwrap();
+		let mut lines = BufReader::new(client).lines();
+		assert_eq!(lines.next().unwrap().unwrap(), TickRecord::CSV_HEADER);
+
+		for tick in 0..3 {
+			stream.send(&record(tick));
+		}
+		for tick in 0..3 {
+			assert_eq!(lines.next().unwrap().unwrap(), record(tick).csv());
+		}
+
+		// Without the stream the thread is gone, and so is the connection.
+		drop(stream);
+		assert!(lines.next().is_none());
+	}
+
+	#[test]
+	fn udp_sends_the_header_first_and_every_line() {
+		let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+		receiver.set_read_timeout(Some(TIMEOUT)).unwrap();
+		let mut stream = Stream {
+			enabled: true,
+			protocol: Protocol::Udp,
+			address: receiver.local_addr().unwrap().to_string(),
+			..Stream::default()
+		};
+		stream.start().unwrap();
+
+		for tick in 0..3 {
+			stream.send(&record(tick));
+		}
+		let mut buffer = [0; 1024];
+		let mut next = || {
+			let length = receiver.recv(&mut buffer).unwrap();
+			String::from_utf8(buffer[..length].to_vec()).unwrap()
+		};
+		assert_eq!(next(), TickRecord::CSV_HEADER);
+		for tick in 0..3 {
+			assert_eq!(next(), record(tick).csv());
+		}
+	}
+
+	#[test]
+	fn full_queue_drops_records() {
+		// Nobody takes the records out of the queue.
+		let (sender, receiver) = std::sync::mpsc::sync_channel(QUEUE);
+		let mut stream = Stream { enabled: true, ..Stream::default() };
+		stream.publisher = Some(Publisher { sender: Some(sender), thread: None });
+
+		for tick in 0..QUEUE + 5 {
+			stream.send(&record(tick));
+		}
+		assert_eq!(stream.dropped, 5);
+		assert!(receiver.try_iter().map(|x| x.tick).eq(0..QUEUE));
+
+		// Without the thread, we stop streaming.
+		drop(receiver);
+		stream.send(&record(0));
+		assert!(stream.publisher.is_none());
+	}
+
+	#[test]
+	fn disabled_does_not_start() {
+		let mut stream = Stream { address: "not an address".to_owned(), ..Stream::default() };
+		stream.start().unwrap();
+		assert!(stream.publisher.is_none());
+		stream.send(&record(0));
+		assert_eq!(stream.dropped, 0);
+	}
+}
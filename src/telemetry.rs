@@ -0,0 +1,98 @@
+use crate::filter::Estimate;
+use crate::line::Position;
+use crate::mixer::Saturation;
+use crate::state::RobotState;
+
+/// Everything we know about one tick of driving.
+#[derive(Debug, Clone)]
+pub(crate) struct TickRecord {
+	pub(crate) tick: usize,
+	pub(crate) state: RobotState,
+	pub(crate) raw_distance: Option<f64>,
+	pub(crate) estimate: Option<Estimate>,
+	pub(crate) speed_correction: f64,
+	pub(crate) reflection: f64,
+	pub(crate) line_position: Position,
+	pub(crate) line_correction: f64,
+	pub(crate) left: f64,
+	pub(crate) right: f64,
+	pub(crate) saturation: Saturation,
+}
+
+impl TickRecord {
+	/// The names of the columns of [TickRecord::csv].
+	pub(crate) const CSV_HEADER: &'static str = "tick,state,raw_distance,distance,relative_speed,confidence,\
+		speed_correction,reflection,line_offset,line_confidence,line_correction,left,right,\
+		speed_saturated,steering_saturated";
+
+	/// One line of CSV, empty where we have no value.
+	pub(crate) fn csv(&self) -> String {
+		let optional = |x: Option<f64>| x.map(|x| x.to_string()).unwrap_or_default();
+		format!("{},{:?},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+			self.tick,
+			self.state,
+			optional(self.raw_distance),
+			optional(self.estimate.map(|x| x.distance)),
+			optional(self.estimate.map(|x| x.speed)),
+			optional(self.estimate.map(|x| x.confidence)),
+			self.speed_correction,
+			self.reflection,
+			self.line_position.offset,
+			self.line_position.confidence,
+			self.line_correction,
+			self.left,
+			self.right,
+			self.saturation.speed,
+			self.saturation.steering,
+		)
+	}
+}
+
+/// Collects statistics over the tick records of one drive.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Telemetry {
+	ticks: usize,
+	speed_saturated: usize,
+	steering_saturated: usize,
+	saturation_events: usize,
+	last: Saturation,
+}
+
+impl Telemetry {
+	pub(crate) fn reset(&mut self) {
+		*self = Telemetry::default();
+	}
+
+	/// Returns `true` if a saturation event starts with this tick.
+	pub(crate) fn record(&mut self, record: &TickRecord) -> bool {
+		let saturation = record.saturation;
+
+		self.ticks += 1;
+		if saturation.speed {
+			self.speed_saturated += 1;
+		}
+		if saturation.steering {
+			self.steering_saturated += 1;
+		}
+
+		let started = saturation.any() && !self.last.any();
+		if started {
+			self.saturation_events += 1;
+		}
+		self.last = saturation;
+		started
+	}
+
+	pub(crate) fn summary(&self) -> Option<String> {
+		if self.ticks == 0 {
+			return None;
+		}
+		let percent = |x: usize| x as f64 * 100.0 / self.ticks as f64;
+		Some(format!("saturation: {} events, speed limited in {:.1}% and steering limited in {:.1}% of {} ticks",
+			self.saturation_events,
+			percent(self.speed_saturated),
+			percent(self.steering_saturated),
+			self.ticks,
+		))
+	}
+}
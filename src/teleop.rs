@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::keyboard::{self, Key};
+use crate::logging::CONTROL;
+
+/// The speeds of all motors, in percent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub(crate) struct Motors {
+	pub(crate) left: f64,
+	pub(crate) right: f64,
+	#[serde(default)]
+	pub(crate) top_arm: f64,
+}
+
+/// Driving by hand in [crate::state::RobotState::Teleop], with the remote control or the arrow
+/// keys on standard input. Without a new command for `timeout`, every motor stops.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Teleop {
+	/// The speed of up and down, in percent.
+	pub(crate) speed: f64,
+	/// What left and right add to the speed of one wheel and take from the other, in percent.
+	pub(crate) turn: f64,
+	/// The speed of the top arm with `a` and `d`, in percent.
+	pub(crate) arm_speed: f64,
+	/// How long a command counts, in `s`. A held key repeats, but only after the delay of the
+	/// terminal, so this needs to be longer than that.
+	pub(crate) timeout: f64,
+	/// Take the arrow keys from standard input.
+	pub(crate) keyboard: bool,
+
+	#[serde(skip)]
+	remote: Option<(Motors, Instant)>,
+	#[serde(skip)]
+	moving: bool,
+}
+
+impl Default for Teleop {
+	fn default() -> Self {
+		Self {
+			speed: 40.0,
+			turn: 20.0,
+			arm_speed: 30.0,
+			timeout: 0.7,
+			keyboard: true,
+			remote: None,
+			moving: false,
+		}
+	}
+}
+
+impl Teleop {
+	pub(crate) fn reset(&mut self) {
+		self.remote = None;
+		self.moving = false;
+	}
+
+	/// A command from the remote control.
+	pub(crate) fn set(&mut self, motors: Motors) {
+		self.remote = Some((motors, Instant::now()));
+	}
+
+	/// What the motors do now: the newest command from the remote control, or else the held
+	/// keys, or else nothing.
+	pub(crate) fn motors(&mut self) -> Motors {
+		let timeout = Duration::from_secs_f64(self.timeout);
+		let remote = self.remote.filter(|(_, time)| time.elapsed() <= timeout).map(|(x, _)| x);
+		let motors = remote.or_else(|| self.keyboard.then(|| self.keys(timeout)).flatten());
+
+		if self.moving && motors.is_none() {
+			log::info!(target: CONTROL, "teleop: no command for {}s, stopping", self.timeout);
+		}
+		self.moving = motors.is_some();
+		motors.unwrap_or_default()
+	}
+
+	/// The motors for the held keys, if any.
+	fn keys(&self, timeout: Duration) -> Option<Motors> {
+		let held = |key| if keyboard::is_held(key, timeout) { 1.0 } else { 0.0 };
+		let forward = held(Key::Up) - held(Key::Down);
+		let turn = held(Key::Right) - held(Key::Left);
+		let arm = held(Key::Char('a')) - held(Key::Char('d'));
+		if forward == 0.0 && turn == 0.0 && arm == 0.0 {
+			return None;
+		}
+
+		// Without up or down, left and right turn on the spot.
+		Some(Motors {
+			left: forward * self.speed + turn * self.turn,
+			right: forward * self.speed - turn * self.turn,
+			top_arm: arm * self.arm_speed,
+		})
+	}
+}
@@ -0,0 +1,411 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use crate::io::{self, Locations};
+use crate::program::Program;
+use crate::sim::{self, Noise, Outcome, MAX_TIME};
+use crate::sim::world::Scenario;
+
+/// A setting we search over, with the path of it in the settings file.
+struct Parameter {
+	path: &'static str,
+	min: f64,
+	max: f64,
+	/// The first step for twiddle, and the size of the first simplex for Nelder–Mead.
+	step: f64,
+}
+
+impl Parameter {
+	fn clamp(&self, value: f64) -> f64 {
+		value.clamp(self.min, self.max)
+	}
+}
+
+/// Every value within the bounds of its parameter.
+fn clamp(parameters: &[Parameter], values: Vec<f64>) -> Vec<f64> {
+	parameters.iter().zip(values)
+		.map(|(parameter, value)| parameter.clamp(value))
+		.collect()
+}
+
+const PARAMETERS: &[Parameter] = &[
+	Parameter { path: "speed", min: 20.0, max: 100.0, step: 10.0 },
+	Parameter { path: "line.k_p", min: -15.0, max: 0.0, step: 1.0 },
+	Parameter { path: "line.k_i", min: -0.5, max: 0.0, step: 0.03 },
+	Parameter { path: "line.k_d", min: 0.0, max: 100.0, step: 10.0 },
+	Parameter { path: "distance.k_p", min: 0.0, max: 20.0, step: 2.0 },
+	Parameter { path: "distance.k_i", min: 0.0, max: 1.0, step: 0.05 },
+	Parameter { path: "distance.k_d", min: 0.0, max: 50.0, step: 5.0 },
+	Parameter { path: "speed_correction_max", min: 0.0, max: 1.0, step: 0.1 },
+];
+
+// The diameters of our test tracks, in `cm`.
+const DIAMETERS: &[f64] = &[78.0, 100.0, 129.0];
+
+// How much every bad thing costs, in seconds of lap time.
+const COST_DEVIATION: f64 = 2.0;
+const COST_GAP: f64 = 1.0;
+const COST_LEFT_TRACK: f64 = 100.0;
+const COST_CRASH: f64 = 100.0;
+
+// How many of the best candidates we print.
+const RANKED: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Method {
+	/// Changes one parameter after the other, with steps that grow while they help.
+	Twiddle,
+	/// Random candidates within the bounds.
+	Random,
+	/// The downhill simplex method.
+	NelderMead,
+}
+
+/// Searches for good settings by driving in the simulation, and prints the best ones as a table
+/// like the one in `robot_settings.toml`.
+#[derive(Debug, Parser)]
+#[command(name = "tune")]
+struct Args {
+	/// How we search.
+	#[arg(value_enum, default_value_t = Method::Twiddle)]
+	method: Method,
+	/// How many rounds the method searches.
+	#[arg(default_value_t = 20)]
+	iterations: usize,
+}
+
+/// One set of parameters, and how it did on every track.
+#[derive(Debug, Clone)]
+struct Candidate {
+	values: Vec<f64>,
+	cost: f64,
+	outcomes: Vec<(f64, Outcome)>,
+}
+
+/// Runs the candidates in the simulation, and remembers all of them.
+struct Search {
+	base: toml::Value,
+	direction: f64,
+	evaluated: Vec<Candidate>,
+}
+
+impl Search {
+	fn settings(&self, values: &[f64], diameter: f64) -> Result<Program> {
+		let mut settings = self.base.clone();
+		set(&mut settings, "diameter", toml::Value::Float(self.direction * diameter))?;
+		for (parameter, &value) in PARAMETERS.iter().zip(values) {
+			set(&mut settings, parameter.path, toml::Value::Float(value))?;
+		}
+		settings.try_into()
+			.context("Failed to build settings for a candidate")
+	}
+
+	fn evaluate(&mut self, values: &[f64]) -> Result<f64> {
+		let values = clamp(PARAMETERS, values.to_vec());
+
+		let mut outcomes = Vec::new();
+		for &diameter in DIAMETERS {
+			let mut program = self.settings(&values, diameter)?;
+			let outcome = sim::run(&mut program, Scenario::new(self.direction * diameter))?;
+			outcomes.push((diameter, outcome));
+		}
+
+		let cost = outcomes.iter().map(|(_, x)| cost(x)).sum::<f64>() / outcomes.len() as f64;
+		self.evaluated.push(Candidate { values, cost, outcomes });
+		Ok(cost)
+	}
+}
+
+/// Searches the `parameters` from `start`, with the costs from `evaluate`.
+fn twiddle(parameters: &[Parameter], start: Vec<f64>, iterations: usize, mut evaluate: impl FnMut(&[f64]) -> Result<f64>) -> Result<()> {
+	// We stay within the bounds, so we never walk off where every candidate looks the same.
+	let mut values = clamp(parameters, start);
+	let mut steps: Vec<f64> = parameters.iter().map(|x| x.step).collect();
+	let mut best = evaluate(&values)?;
+
+	for _ in 0..iterations {
+		for (i, parameter) in parameters.iter().enumerate() {
+			let current = values[i];
+
+			values[i] = parameter.clamp(current + steps[i]);
+			let cost = evaluate(&values)?;
+			if cost < best {
+				best = cost;
+				steps[i] *= 1.1;
+				continue;
+			}
+
+			values[i] = parameter.clamp(current - steps[i]);
+			let cost = evaluate(&values)?;
+			if cost < best {
+				best = cost;
+				steps[i] *= 1.1;
+				continue;
+			}
+
+			values[i] = current;
+			steps[i] *= 0.9;
+		}
+	}
+
+	Ok(())
+}
+
+fn random(parameters: &[Parameter], start: Vec<f64>, iterations: usize, mut evaluate: impl FnMut(&[f64]) -> Result<f64>) -> Result<()> {
+	let mut noise = Noise::new(2023);
+
+	evaluate(&clamp(parameters, start))?;
+	for _ in 0..iterations {
+		let values: Vec<f64> = parameters.iter()
+			.map(|x| x.min + noise.next() * (x.max - x.min))
+			.collect();
+		evaluate(&values)?;
+	}
+
+	Ok(())
+}
+
+fn nelder_mead(parameters: &[Parameter], start: Vec<f64>, iterations: usize, mut evaluate: impl FnMut(&[f64]) -> Result<f64>) -> Result<()> {
+	// `a + factor * (b - a)`
+	let along = |a: &[f64], b: &[f64], factor: f64| -> Vec<f64> {
+		clamp(parameters, a.iter().zip(b).map(|(a, b)| a + factor * (b - a)).collect())
+	};
+
+	// The start too, so the simplex is within the bounds from the beginning.
+	let start = clamp(parameters, start);
+
+	let mut simplex = vec![(evaluate(&start)?, start.clone())];
+	for (i, parameter) in parameters.iter().enumerate() {
+		let mut values = start.clone();
+		// Step towards the inside, so we don't fall onto the bounds right away.
+		values[i] += if values[i] + parameter.step > parameter.max { -parameter.step } else { parameter.step };
+		let values = clamp(parameters, values);
+		simplex.push((evaluate(&values)?, values));
+	}
+
+	for _ in 0..iterations {
+		simplex.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+		let (worst_cost, worst) = simplex.last().cloned().expect("the simplex is never empty");
+		let others = &simplex[..simplex.len() - 1];
+		let centroid: Vec<f64> = (0..start.len())
+			.map(|i| others.iter().map(|(_, x)| x[i]).sum::<f64>() / others.len() as f64)
+			.collect();
+
+		let reflected = along(&centroid, &worst, -1.0);
+		let reflected_cost = evaluate(&reflected)?;
+
+		let best_cost = simplex[0].0;
+		let second_worst_cost = simplex[simplex.len() - 2].0;
+
+		let replacement = if reflected_cost < best_cost {
+			let expanded = along(&centroid, &worst, -2.0);
+			let expanded_cost = evaluate(&expanded)?;
+			if expanded_cost < reflected_cost {
+				Some((expanded_cost, expanded))
+			} else {
+				Some((reflected_cost, reflected))
+			}
+		} else if reflected_cost < second_worst_cost {
+			Some((reflected_cost, reflected))
+		} else {
+			let contracted = along(&centroid, &worst, 0.5);
+			let contracted_cost = evaluate(&contracted)?;
+			(contracted_cost < worst_cost).then_some((contracted_cost, contracted))
+		};
+
+		match replacement {
+			Some(replacement) => *simplex.last_mut().expect("the simplex is never empty") = replacement,
+			None => {
+				// Shrink everything towards the best point.
+				let best = simplex[0].1.clone();
+				for entry in simplex.iter_mut().skip(1) {
+					let values = along(&best, &entry.1, 0.5);
+					*entry = (evaluate(&values)?, values);
+				}
+			},
+		}
+	}
+
+	Ok(())
+}
+
+/// How bad an outcome is, roughly in seconds.
+fn cost(outcome: &Outcome) -> f64 {
+	let mut cost = outcome.time
+		+ COST_DEVIATION * outcome.max_deviation
+		+ COST_GAP * outcome.gap_error;
+	if !outcome.finished {
+		cost += MAX_TIME;
+	}
+	if outcome.left_track {
+		cost += COST_LEFT_TRACK;
+	}
+	if outcome.crashed {
+		cost += COST_CRASH;
+	}
+	cost
+}
+
+/// Sets the value at a path like `line.k_p`.
+fn set(settings: &mut toml::Value, path: &str, value: toml::Value) -> Result<()> {
+	let mut current = settings;
+	for key in path.split('.') {
+		current = current.get_mut(key)
+			.ok_or_else(|| anyhow!("No setting {path:?}"))?;
+	}
+	*current = value;
+	Ok(())
+}
+
+fn get(settings: &toml::Value, path: &str) -> Result<f64> {
+	let mut current = settings;
+	for key in path.split('.') {
+		current = current.get(key)
+			.ok_or_else(|| anyhow!("No setting {path:?}"))?;
+	}
+	current.as_float()
+		.or_else(|| current.as_integer().map(|x| x as f64))
+		.ok_or_else(|| anyhow!("The setting {path:?} is not a number"))
+}
+
+fn print_ranked(search: &Search) -> Result<()> {
+	let mut ranked = search.evaluated.clone();
+	ranked.sort_by(|a, b| a.cost.total_cmp(&b.cost));
+	ranked.dedup_by(|a, b| a.values == b.values);
+
+	let spin = if get(&search.base, "robot_wheel_width")? == 0.0 { "n" } else { "y" };
+	let width = get(&search.base, "robot_wheel_width")?;
+	let center = get(&search.base, "line.center")?;
+
+	println!("# evaluated {} candidates, the best {} are:", search.evaluated.len(), RANKED.min(ranked.len()));
+	println!("#");
+	println!("# rank | cost  | spin | width | diameter | lcenter | speed | l k_p | l k_i | l k_d | d k_p | d k_i | d k_d | corr | real speed | comment");
+	for (rank, candidate) in ranked.iter().take(RANKED).enumerate() {
+		let [speed, k_p, k_i, k_d, d_k_p, d_k_i, d_k_d, correction] = candidate.values[..] else {
+			bail!("A candidate has the wrong amount of values");
+		};
+
+		for (diameter, outcome) in &candidate.outcomes {
+			let diameter = search.direction * diameter;
+			let comment = if outcome.left_track {
+				"leaves the track".to_owned()
+			} else if outcome.crashed {
+				"crashes".to_owned()
+			} else if !outcome.finished {
+				"doesn't finish".to_owned()
+			} else {
+				format!("{:.1}s, deviation {:.1}cm, gap error {:.1}cm", outcome.time, outcome.max_deviation, outcome.gap_error)
+			};
+			println!("# {:>4} | {:>5.1} | {spin:<4} | {width:>5.1} | {diameter:>8.1} | {center:>7.1} | {speed:>5.1} | {k_p:>5.2} | {k_i:>5.2} | {k_d:>5.1} | {d_k_p:>5.2} | {d_k_i:>5.2} | {d_k_d:>5.1} | {correction:>4.2} | {:>5.1} cm/s | {comment}",
+				rank + 1, candidate.cost, outcome.speed()
+			);
+		}
+		println!("#");
+	}
+
+	Ok(())
+}
+
+pub(crate) fn main() -> Result<()> {
+	let Args { method, iterations } = Args::parse();
+
+	let program = io::read(&Locations::default()).context("Failed to read the config file")?.program;
+	let base = toml::Value::try_from(&program)
+		.context("Failed to convert the settings")?;
+
+	let start = PARAMETERS.iter()
+		.map(|x| get(&base, x.path))
+		.collect::<Result<Vec<f64>>>()?;
+
+	let mut search = Search {
+		direction: program.diameter.signum(),
+		base,
+		evaluated: Vec::new(),
+	};
+
+	eprintln!("searching with {method:?} for {iterations} iterations on diameters {DIAMETERS:?}");
+	match method {
+		Method::Twiddle => twiddle(PARAMETERS, start, iterations, |x| search.evaluate(x))?,
+		Method::Random => random(PARAMETERS, start, iterations, |x| search.evaluate(x))?,
+		Method::NelderMead => nelder_mead(PARAMETERS, start, iterations, |x| search.evaluate(x))?,
+	}
+
+	print_ranked(&search)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const BOUNDS: &[Parameter] = &[
+		Parameter { path: "x", min: -10.0, max: 10.0, step: 1.0 },
+		Parameter { path: "y", min: 0.0, max: 5.0, step: 1.0 },
+	];
+
+	/// Runs `method` on a bowl around `(3, 2)`, or `(3, 8)` outside of the bounds, and returns every
+	/// point it looked at, and the best one.
+	fn search(method: Method, start: Vec<f64>, y: f64) -> (Vec<Vec<f64>>, Vec<f64>) {
+		let mut points = Vec::new();
+		let evaluate = |values: &[f64]| {
+			points.push(values.to_vec());
+			Ok((values[0] - 3.0).powi(2) + (values[1] - y).powi(2))
+		};
+		match method {
+			Method::Twiddle => twiddle(BOUNDS, start, 200, evaluate).unwrap(),
+			Method::Random => random(BOUNDS, start, 50, evaluate).unwrap(),
+			Method::NelderMead => nelder_mead(BOUNDS, start, 100, evaluate).unwrap(),
+		}
+		let best = points.iter()
+			.min_by(|a, b| ((a[0] - 3.0).powi(2) + (a[1] - y).powi(2)).total_cmp(&((b[0] - 3.0).powi(2) + (b[1] - y).powi(2))))
+			.unwrap()
+			.clone();
+		(points, best)
+	}
+
+	fn in_bounds(points: &[Vec<f64>]) -> bool {
+		points.iter().all(|x| BOUNDS.iter().zip(x).all(|(bounds, &x)| (bounds.min..=bounds.max).contains(&x)))
+	}
+
+	#[test]
+	fn twiddle_finds_the_minimum() {
+		// Twiddle only gets close.
+		let (points, best) = search(Method::Twiddle, vec![-5.0, 0.0], 2.0);
+		assert!(in_bounds(&points));
+		assert!((best[0] - 3.0).abs() < 0.1 && (best[1] - 2.0).abs() < 0.1, "{best:?}");
+	}
+
+	#[test]
+	fn nelder_mead_finds_the_minimum() {
+		let (points, best) = search(Method::NelderMead, vec![-5.0, 0.0], 2.0);
+		assert!(in_bounds(&points));
+		assert!((best[0] - 3.0).abs() < 0.01 && (best[1] - 2.0).abs() < 0.01, "{best:?}");
+	}
+
+	#[test]
+	fn a_minimum_outside_is_found_on_the_bounds() {
+		for (method, tolerance) in [(Method::Twiddle, 0.1), (Method::NelderMead, 0.01)] {
+			let (points, best) = search(method, vec![0.0, 1.0], 8.0);
+			assert!(in_bounds(&points));
+			assert!((best[0] - 3.0).abs() < tolerance && best[1] == 5.0, "{method:?}: {best:?}");
+		}
+	}
+
+	#[test]
+	fn every_method_starts_within_the_bounds() {
+		for method in [Method::Twiddle, Method::Random, Method::NelderMead] {
+			let (points, _) = search(method, vec![20.0, -3.0], 2.0);
+			assert_eq!(points[0], [10.0, 0.0], "{method:?}");
+			assert!(in_bounds(&points), "{method:?}");
+		}
+	}
+
+	#[test]
+	fn the_arguments() {
+		let args = Args::try_parse_from(["tune"]).unwrap();
+		assert_eq!((args.method, args.iterations), (Method::Twiddle, 20));
+		let args = Args::try_parse_from(["tune", "nelder-mead", "5"]).unwrap();
+		assert_eq!((args.method, args.iterations), (Method::NelderMead, 5));
+		assert!(Args::try_parse_from(["tune", "simplex"]).is_err());
+		assert!(Args::try_parse_from(["tune", "random", "-1"]).is_err());
+	}
+}
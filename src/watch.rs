@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use crate::stream::PORT;
+
+/// Watches the telemetry the robot streams with `[stream]`, and prints it or writes it into a
+/// file.
+#[derive(Debug, Parser)]
+#[command(name = "watch")]
+struct Args {
+	/// With TCP the robot to connect to, with UDP where we listen. Defaults to `ev3dev:7700`,
+	/// and to `0.0.0.0:7700` with UDP.
+	address: Option<String>,
+
+	/// Listen for the stream with UDP, instead of connecting to the robot with TCP.
+	#[arg(long)]
+	udp: bool,
+
+	/// Only these columns, e.g. `tick,reflection,left,right`.
+	#[arg(long, short, value_delimiter = ',')]
+	columns: Vec<String>,
+
+	/// Write CSV into this file, instead of printing a table.
+	#[arg(long, short, value_name = "PATH")]
+	output: Option<PathBuf>,
+}
+
+pub(crate) fn main() -> Result<()> {
+	let args = Args::parse();
+
+	let mut lines: Box<dyn Iterator<Item = Result<String>>> = if args.udp {
+		let address = args.address.unwrap_or_else(|| format!("0.0.0.0:{PORT}"));
+		let socket = UdpSocket::bind(&address)
+			.with_context(|| format!("Failed to listen on {address}"))?;
+		eprintln!("listening on {address}");
+		let mut buffer = vec![0; 65536];
+		Box::new(std::iter::from_fn(move || Some(
+			socket.recv(&mut buffer)
+				.context("Failed to receive")
+				.map(|size| String::from_utf8_lossy(&buffer[..size]).into_owned())
+		)))
+	} else {
+		let address = args.address.unwrap_or_else(|| format!("ev3dev:{PORT}"));
+		let stream = TcpStream::connect(&address)
+			.with_context(|| format!("Failed to connect to {address}"))?;
+		eprintln!("connected to {address}");
+		Box::new(BufReader::new(stream).lines().map(|x| x.context("Failed to receive")))
+	};
+
+	// With UDP we might come in the middle, so we wait for the next header.
+	let header = loop {
+		match lines.next() {
+			Some(line) => {
+				let line = line?;
+				if line.starts_with("tick,") {
+					break line;
+				}
+			},
+			None => return Ok(()),
+		}
+	};
+	let names: Vec<&str> = header.split(',').collect();
+	let columns = if args.columns.is_empty() {
+		(0..names.len()).collect()
+	} else {
+		args.columns.iter()
+			.map(|column| match names.iter().position(|x| x == column) {
+				Some(i) => Ok(i),
+				None => bail!("No column {column:?}, there are only {names:?}"),
+			})
+			.collect::<Result<Vec<_>>>()?
+	};
+
+	let mut output: Box<dyn Write> = match &args.output {
+		// Unbuffered, so we keep everything up to a Ctrl-C.
+		Some(path) => Box::new(File::create(path)
+			.with_context(|| format!("Failed to create {path:?}"))?),
+		None => Box::new(std::io::stdout().lock()),
+	};
+	let table = args.output.is_none();
+	let widths: Vec<usize> = columns.iter().map(|&i| names[i].len().max(10)).collect();
+
+	let mut write = |values: &[&str]| -> Result<()> {
+		let values = columns.iter().map(|&i| values.get(i).copied().unwrap_or(""));
+		let line = if table {
+			values.zip(&widths)
+				.map(|(value, &width)| match value.parse::<f64>() {
+					Ok(number) if value.contains('.') => format!("{number:>width$.2}"),
+					_ => format!("{value:>width$}"),
+				})
+				.collect::<Vec<_>>()
+				.join(" ")
+		} else {
+			values.collect::<Vec<_>>().join(",")
+		};
+		writeln!(output, "{line}").context("Failed to write")
+	};
+
+	write(&names)?;
+	for line in lines {
+		let line = line?;
+		// The header comes again with UDP, and we already have it.
+		if line.starts_with("tick,") {
+			continue;
+		}
+		write(&line.split(',').collect::<Vec<_>>())?;
+	}
+	Ok(())
+}